@@ -0,0 +1,64 @@
+use diar::recovery::{add_recovery_record, repair_recovery_record, RecoveryConfiguration};
+use std::{env, fs::OpenOptions, process::ExitCode};
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+    let args = env::args().collect::<Vec<_>>();
+    let (command, path, percent) = match args.as_slice() {
+        [_, command, path] => (command.as_str(), path, None),
+        [_, command, path, percent] => (command.as_str(), path, percent.parse().ok()),
+        _ => {
+            eprintln!("usage: recovery add <archive> [percent] | recovery repair <archive>");
+            return ExitCode::from(2);
+        }
+    };
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command {
+        "add" => {
+            let mut cfg = RecoveryConfiguration::default();
+            if let Some(percent) = percent {
+                cfg = cfg.percent(percent);
+            }
+            if let Err(e) = add_recovery_record(&mut file, &cfg) {
+                eprintln!("{path}: cannot add recovery record: {e}");
+                return ExitCode::FAILURE;
+            }
+            ExitCode::SUCCESS
+        }
+        "repair" => {
+            let report = match repair_recovery_record(&mut file) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("{path}: cannot repair archive: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            println!(
+                "{path}: {} of {} shards damaged, {} repaired",
+                report.damaged_shards, report.shards, report.repaired_shards,
+            );
+            if report.trailer_damaged {
+                println!("repaired the trailer");
+            }
+            for range in &report.unrecoverable {
+                println!("cannot repair bytes {}..{}", range.start, range.end);
+            }
+            if report.is_repairable() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        _ => {
+            eprintln!("unknown command {command}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use diar::reader::{ArchiveReader, ReaderConfiguration};
+use std::{env, process::ExitCode};
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+    let args = env::args().collect::<Vec<_>>();
+    let [_, archive, target] = args.as_slice() else {
+        eprintln!("usage: salvage <archive> <target directory>");
+        return ExitCode::from(2);
+    };
+
+    let cfg = ReaderConfiguration::default();
+    let report = match ArchiveReader::salvage_file(archive, &cfg, target) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{archive}: cannot salvage archive: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "{archive}: found {} objects, recovered {} files ({} bytes)",
+        report.objects, report.files, report.bytes,
+    );
+    if !report.found_root {
+        println!("the root directory was not found");
+    }
+    for offset in &report.orphans {
+        println!("recovered lost+found/{offset} without its original path");
+    }
+    for range in &report.damaged {
+        println!("damaged bytes {}..{}", range.start, range.end);
+    }
+    for issue in &report.issues {
+        match (&issue.path, issue.offset) {
+            (Some(path), _) => println!("could not recover {path}: {}", issue.error),
+            (None, Some(offset)) => {
+                println!("could not recover object at {offset}: {}", issue.error)
+            }
+            (None, None) => println!("error: {}", issue.error),
+        }
+    }
+    if report.found_root && report.issues.is_empty() && report.damaged.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
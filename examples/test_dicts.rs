@@ -1,9 +1,4 @@
-use std::{
-    fs::File,
-    io::{BufReader, Write},
-    path::PathBuf,
-};
-use zstd::zstd_safe::{CParameter, DParameter};
+use std::{fs::File, path::PathBuf};
 
 fn main() {
     tracing_subscriber::fmt::init();
@@ -0,0 +1,55 @@
+use diar::reader::ArchiveReader;
+use std::{env, process::ExitCode};
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: verify <archive>");
+        return ExitCode::from(2);
+    };
+    let mut reader = match ArchiveReader::open(&path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{path}: cannot open archive: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = reader.verify();
+    println!(
+        "{path}: {} objects, {} blobs, {} bytes{}",
+        report.objects,
+        report.blobs,
+        report.bytes,
+        if report.hashed { ", hashes checked" } else { "" },
+    );
+    for (offset, ty) in &report.unknown_objects {
+        println!("unknown object type {ty} at offset {offset}");
+    }
+    for range in &report.unreachable {
+        println!("unreachable bytes {}..{}", range.start, range.end);
+    }
+    if let Some(recovery) = &report.recovery {
+        println!(
+            "recovery record: {} of {} shards damaged{}",
+            recovery.damaged_shards,
+            recovery.shards,
+            if recovery.is_repairable() {
+                ""
+            } else {
+                ", some cannot be repaired"
+            },
+        );
+    }
+    for issue in &report.issues {
+        match issue.offset {
+            Some(offset) => println!("error at offset {offset}: {}", issue.error),
+            None => println!("error: {}", issue.error),
+        }
+    }
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{errors::*, objects::*};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        KeyInit, OsRng,
+    },
+    Key, XChaCha20Poly1305,
+};
+use std::{
+    fmt::{Debug, Formatter},
+    io,
+    io::{Read, Write},
+};
+
+const NONCE_LENGTH: usize = 19;
+const TAG_LENGTH: usize = 16;
+const SALT_LENGTH: usize = 16;
+const CHUNK_SIZE: u64 = 1024 * 64;
+const KEY_CHECK_CONTEXT: &str = "diar 2023 XChaCha20-Poly1305 key check";
+
+/// The most memory, in KiB, key derivation may use, so archives cannot exhaust the reader's
+/// memory.
+const MAX_M_COST: u32 = 256 * 1024;
+/// The most passes key derivation may make, so archives cannot stall the reader.
+const MAX_T_COST: u32 = 10;
+/// The most lanes key derivation may use. Lanes are computed one after another, so each adds to
+/// the time taken.
+const MAX_P_COST: u32 = 16;
+
+/// A key used to encrypt or decrypt an archive.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// A raw 256-bit key.
+    Raw([u8; 32]),
+    /// A passphrase, from which a key is derived using Argon2id.
+    Passphrase(String),
+}
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncryptionKey::Raw(_) => f.write_str("EncryptionKey::Raw(..)"),
+            EncryptionKey::Passphrase(_) => f.write_str("EncryptionKey::Passphrase(..)"),
+        }
+    }
+}
+
+fn derive_key(key: &EncryptionKey, kdf: &KeyDerivation) -> Result<Key> {
+    match (key, kdf) {
+        (EncryptionKey::Raw(key), KeyDerivation::Raw) => Ok((*key).into()),
+        (
+            EncryptionKey::Passphrase(pass),
+            KeyDerivation::Argon2id { salt, m_cost, t_cost, p_cost },
+        ) => {
+            ensure_valid(
+                *m_cost <= MAX_M_COST && *t_cost <= MAX_T_COST && *p_cost <= MAX_P_COST,
+                &"Argon2 parameters are too expensive",
+            )?;
+            let Ok(params) = Params::new(*m_cost, *t_cost, *p_cost, Some(32)) else {
+                return corrupt(&"invalid Argon2 parameters");
+            };
+            let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+            let mut key = Key::default();
+            if argon2
+                .hash_password_into(pass.as_bytes(), salt, &mut key)
+                .is_err()
+            {
+                return corrupt(&"failed to derive key from passphrase");
+            }
+            Ok(key)
+        }
+        (EncryptionKey::Raw(_), _) => {
+            Error::key_invalid("archive expects a passphrase, not a raw key")
+        }
+        (EncryptionKey::Passphrase(_), _) => {
+            Error::key_invalid("archive expects a raw key, not a passphrase")
+        }
+    }
+}
+fn key_check(key: &Key) -> Vec<u8> {
+    blake3::derive_key(KEY_CHECK_CONTEXT, key).to_vec()
+}
+
+/// The key and parameters used to encrypt or decrypt data for an encryption filter.
+pub(crate) struct Cipher {
+    key: Key,
+    chunk_size: usize,
+}
+impl Cipher {
+    /// Creates a new cipher with a fresh salt, alongside the filter object describing it.
+    pub fn new_filter(key: &EncryptionKey) -> Result<(Cipher, ObjFilterXChaCha20Poly1305)> {
+        let kdf = match key {
+            EncryptionKey::Raw(_) => KeyDerivation::Raw,
+            EncryptionKey::Passphrase(_) => {
+                let mut salt = vec![0; SALT_LENGTH];
+                OsRng.fill_bytes(&mut salt);
+                KeyDerivation::Argon2id {
+                    salt,
+                    m_cost: Params::DEFAULT_M_COST,
+                    t_cost: Params::DEFAULT_T_COST,
+                    p_cost: Params::DEFAULT_P_COST,
+                }
+            }
+        };
+        let key = derive_key(key, &kdf)?;
+        let filter =
+            ObjFilterXChaCha20Poly1305 { kdf, key_check: key_check(&key), chunk_size: CHUNK_SIZE };
+        Ok((Cipher { key, chunk_size: CHUNK_SIZE as usize }, filter))
+    }
+
+    /// Derives the cipher for an existing filter object.
+    pub fn from_filter(
+        key: &EncryptionKey,
+        filter: &ObjFilterXChaCha20Poly1305,
+    ) -> Result<Cipher> {
+        ensure_valid(
+            filter.chunk_size > 0 && filter.chunk_size <= 1024 * 1024 * 16,
+            &"invalid encryption chunk size",
+        )?;
+        let key = derive_key(key, &filter.kdf)?;
+        if key_check(&key) != filter.key_check {
+            return Error::key_invalid("key does not match the archive");
+        }
+        Ok(Cipher { key, chunk_size: filter.chunk_size as usize })
+    }
+
+    /// Encrypts data written to the returned stream into `stream`.
+    ///
+    /// [`EncryptWriter::finish`] must be called to write the final chunk.
+    pub fn writer<W: Write>(&self, mut stream: W) -> Result<EncryptWriter<W>> {
+        let mut nonce = [0; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        stream.write_all(&nonce)?;
+        let cipher = XChaCha20Poly1305::new(&self.key);
+        Ok(EncryptWriter {
+            stream,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, (&nonce).into())),
+            buffer: Vec::new(),
+            chunk_size: self.chunk_size,
+        })
+    }
+
+    /// Decrypts the data read from `stream`.
+    pub fn reader<R: Read>(&self, mut stream: R) -> Result<DecryptReader<R>> {
+        let mut nonce = [0; NONCE_LENGTH];
+        stream.read_exact(&mut nonce)?;
+        let cipher = XChaCha20Poly1305::new(&self.key);
+        Ok(DecryptReader {
+            stream,
+            decryptor: Some(DecryptorBE32::from_aead(cipher, (&nonce).into())),
+            pending: Vec::new(),
+            chunk: Vec::new(),
+            pos: 0,
+            chunk_size: self.chunk_size,
+        })
+    }
+}
+
+fn crypto_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "encrypted data could not be authenticated")
+}
+
+/// Splits data into chunks, encrypting each with XChaCha20-Poly1305 in the STREAM construction.
+pub(crate) struct EncryptWriter<W> {
+    stream: W,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+impl<W: Write> EncryptWriter<W> {
+    /// Encrypts the final chunk, returning the underlying stream.
+    pub fn finish(mut self) -> Result<W> {
+        let encryptor = self
+            .encryptor
+            .take()
+            .expect("EncryptWriter already finished");
+        let data = encryptor
+            .encrypt_last(self.buffer.as_slice())
+            .map_err(|_| crypto_error())?;
+        self.stream.write_all(&data)?;
+        Ok(self.stream)
+    }
+}
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        // the last chunk is only written by `finish`, so a full chunk is kept back
+        while self.buffer.len() > self.chunk_size {
+            let encryptor = self
+                .encryptor
+                .as_mut()
+                .expect("EncryptWriter already finished");
+            let data = encryptor
+                .encrypt_next(&self.buffer[..self.chunk_size])
+                .map_err(|_| crypto_error())?;
+            self.stream.write_all(&data)?;
+            self.buffer.drain(..self.chunk_size);
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Decrypts data written by [`EncryptWriter`].
+pub(crate) struct DecryptReader<R> {
+    stream: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    pending: Vec<u8>,
+    chunk: Vec<u8>,
+    pos: usize,
+    chunk_size: usize,
+}
+impl<R: Read> DecryptReader<R> {
+    fn next_chunk(&mut self) -> io::Result<()> {
+        self.chunk.clear();
+        self.pos = 0;
+        let Some(decryptor) = &mut self.decryptor else {
+            return Ok(());
+        };
+
+        // read one byte past the end of the chunk to find out whether it is the last one
+        let full_len = self.chunk_size + TAG_LENGTH;
+        while self.pending.len() <= full_len {
+            let start = self.pending.len();
+            self.pending.resize(full_len + 1, 0);
+            let len = self.stream.read(&mut self.pending[start..])?;
+            self.pending.truncate(start + len);
+            if len == 0 {
+                break;
+            }
+        }
+
+        if self.pending.len() > full_len {
+            self.chunk = decryptor
+                .decrypt_next(&self.pending[..full_len])
+                .map_err(|_| crypto_error())?;
+            self.pending.drain(..full_len);
+        } else {
+            let decryptor = self.decryptor.take().unwrap();
+            self.chunk = decryptor
+                .decrypt_last(self.pending.as_slice())
+                .map_err(|_| crypto_error())?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
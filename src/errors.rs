@@ -1,12 +1,16 @@
-use crate::objects::ObjectId;
+use crate::objects::{FormatInfo, ObjectId};
 use std::{
     fmt::{Display, Formatter},
     panic::Location,
+    path::PathBuf,
 };
 
 #[derive(Debug)]
 pub struct Error(ErrorContents);
+
+/// The specific kind of an [`Error`], for callers that need to tell failures apart.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
     #[error("io error encountered at {1}: {0}")]
     IoError(std::io::Error, &'static Location<'static>),
@@ -14,6 +18,74 @@ pub enum ErrorKind {
     JWalkError(jwalk::Error, &'static Location<'static>),
     #[error("encountered while iterating directory at {1}: {0}")]
     FastCDC(fastcdc::v2020::Error, &'static Location<'static>),
+    #[error("invalid filter pattern at {1}: {0}")]
+    IgnoreError(ignore::Error, &'static Location<'static>),
+    #[error("invalid path transform at {1}: {0}")]
+    RegexError(regex::Error, &'static Location<'static>),
+    #[error("invalid manifest at {1}: {0}")]
+    JsonError(serde_json::Error, &'static Location<'static>),
+    #[error("manifest entry {0:?} refers to the archive root, which must be a directory, at {1}")]
+    ManifestRootEntry(String, &'static Location<'static>),
+    #[error("SOURCE_DATE_EPOCH is set to {0:?}, which is not a valid timestamp, at {1}")]
+    InvalidSourceDateEpoch(String, &'static Location<'static>),
+    #[error("file name of {} is not valid UTF-8 at {1}", .0.display())]
+    NonUtf8Name(PathBuf, &'static Location<'static>),
+    #[error(
+        "archive format version {0} is not supported (supported versions are {} to {}) at {1}",
+        FormatInfo::MIN_VERSION,
+        FormatInfo::CURRENT_VERSION
+    )]
+    UnsupportedVersion(u64, &'static Location<'static>),
+    #[error("archive requires unsupported features {0:#x} at {1}")]
+    UnsupportedFeatures(u64, &'static Location<'static>),
+    #[error("archive header not found at {0}")]
+    BadMagic(&'static Location<'static>),
+    #[error("archive trailer is missing or truncated at {0}")]
+    TruncatedTrailer(&'static Location<'static>),
+    #[error("invalid varint in archive at {0}")]
+    InvalidVarint(&'static Location<'static>),
+    #[error("unknown object type {0:#x} where a known object is required at {1}")]
+    UnknownObjectType(u32, &'static Location<'static>),
+    #[error("reference to offset {0} does not point into the archive at {1}")]
+    DanglingOffset(u64, &'static Location<'static>),
+    #[error(
+        "failed to decompress {} at {2}: {1}",
+        .0.as_deref().unwrap_or("file")
+    )]
+    DecompressionFailed(Option<String>, std::io::Error, &'static Location<'static>),
+    #[error("archive is corrupt: {0} at {1}")]
+    Corrupt(&'static str, &'static Location<'static>),
+    #[error("archive exceeds the {0} limit at {1}")]
+    LimitExceeded(ResourceLimit, &'static Location<'static>),
+    #[error("archive is not signed, but a signature is required at {0}")]
+    SignatureMissing(&'static Location<'static>),
+    #[error("archive signature is invalid at {0}")]
+    SignatureInvalid(&'static Location<'static>),
+    #[error("archive is encrypted, but no decryption key was given at {0}")]
+    KeyRequired(&'static Location<'static>),
+    #[error("wrong decryption key: {0} at {1}")]
+    KeyInvalid(&'static str, &'static Location<'static>),
+    #[error("{0} was not found in the archive at {1}")]
+    NotFound(String, &'static Location<'static>),
+    #[error("{0} at {1}")]
+    WrongObjectType(&'static str, &'static Location<'static>),
+}
+
+/// A limit on the resources used while reading an archive, as set in
+/// [`ReaderLimits`](crate::reader::ReaderLimits).
+#[derive(thiserror::Error, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum ResourceLimit {
+    #[error("entry size")]
+    EntrySize,
+    #[error("total size")]
+    TotalSize,
+    #[error("entry count")]
+    EntryCount,
+    #[error("directory depth")]
+    Depth,
+    #[error("zstd window size")]
+    ZstdWindow,
 }
 #[derive(Debug)]
 pub enum ErrorContents {
@@ -23,15 +95,202 @@ pub enum ErrorContents {
 }
 
 impl ErrorContents {
-    pub fn emit<T>(mut self) -> Result<T> {
+    pub fn emit<T>(self) -> Result<T> {
         Err(Error(self))
     }
 }
 
+impl Error {
+    /// Returns the kind of this error, if it is not an internal error.
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match &self.0 {
+            ErrorContents::Kind(kind) => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this error was caused by a damaged or malformed archive, rather than by
+    /// an IO problem or a bad argument.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self.kind(),
+            Some(
+                ErrorKind::BadMagic(..)
+                    | ErrorKind::TruncatedTrailer(..)
+                    | ErrorKind::InvalidVarint(..)
+                    | ErrorKind::UnknownObjectType(..)
+                    | ErrorKind::DanglingOffset(..)
+                    | ErrorKind::DecompressionFailed(..)
+                    | ErrorKind::Corrupt(..)
+            )
+        )
+    }
+
+    /// Reports an object of the wrong type as corruption, for objects reached through references
+    /// in the archive rather than given by the caller.
+    pub(crate) fn in_archive(self) -> Error {
+        match self.0 {
+            ErrorContents::Kind(kind) => match *kind {
+                ErrorKind::WrongObjectType(msg, loc) => {
+                    Error(ErrorContents::Kind(Box::new(ErrorKind::Corrupt(msg, loc))))
+                }
+                kind => Error(ErrorContents::Kind(Box::new(kind))),
+            },
+            contents => Error(contents),
+        }
+    }
+
+    /// Attaches the path of the entry being read to a decompression failure, if it is known.
+    pub(crate) fn with_path(mut self, path: &str) -> Error {
+        if path.is_empty() {
+            return self;
+        }
+        if let ErrorContents::Kind(kind) = &mut self.0 {
+            if let ErrorKind::DecompressionFailed(p @ None, ..) = &mut **kind {
+                *p = Some(path.to_string());
+            }
+        }
+        self
+    }
+
+    fn from_kind<T>(kind: ErrorKind) -> Result<T> {
+        Err(Error(ErrorContents::Kind(Box::new(kind))))
+    }
+    #[track_caller]
+    pub(crate) fn manifest_root_entry<T>(path: impl Into<String>) -> Result<T> {
+        Self::from_kind(ErrorKind::ManifestRootEntry(path.into(), Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn invalid_source_date_epoch<T>(value: impl Into<String>) -> Result<T> {
+        Self::from_kind(ErrorKind::InvalidSourceDateEpoch(value.into(), Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn non_utf8_name<T>(path: impl Into<PathBuf>) -> Result<T> {
+        Self::from_kind(ErrorKind::NonUtf8Name(path.into(), Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn unsupported_version<T>(version: u64) -> Result<T> {
+        Self::from_kind(ErrorKind::UnsupportedVersion(version, Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn unsupported_features<T>(features: u64) -> Result<T> {
+        Self::from_kind(ErrorKind::UnsupportedFeatures(features, Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn bad_magic<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::BadMagic(Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn truncated_trailer<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::TruncatedTrailer(Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn invalid_varint<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::InvalidVarint(Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn unknown_object_type<T>(ty: u32) -> Result<T> {
+        Self::from_kind(ErrorKind::UnknownObjectType(ty, Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn dangling_offset<T>(offset: u64) -> Result<T> {
+        Self::from_kind(ErrorKind::DanglingOffset(offset, Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn limit_exceeded<T>(limit: ResourceLimit) -> Result<T> {
+        Self::from_kind(ErrorKind::LimitExceeded(limit, Location::caller()))
+    }
+    /// Returns an error reporting that a required signature is absent.
+    #[track_caller]
+    pub(crate) fn signature_missing<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::SignatureMissing(Location::caller()))
+    }
+    /// Returns an error reporting that the archive signature does not verify.
+    #[track_caller]
+    pub(crate) fn signature_invalid<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::SignatureInvalid(Location::caller()))
+    }
+    /// Returns an error reporting that an encrypted archive was opened without a key.
+    #[track_caller]
+    pub(crate) fn key_required<T>() -> Result<T> {
+        Self::from_kind(ErrorKind::KeyRequired(Location::caller()))
+    }
+    /// Returns an error reporting that the given key cannot decrypt the archive.
+    #[track_caller]
+    pub(crate) fn key_invalid<T>(why: &'static str) -> Result<T> {
+        Self::from_kind(ErrorKind::KeyInvalid(why, Location::caller()))
+    }
+    /// Returns an error reporting that a named item does not exist in the archive.
+    #[track_caller]
+    pub(crate) fn not_found<T>(what: impl Into<String>) -> Result<T> {
+        Self::from_kind(ErrorKind::NotFound(what.into(), Location::caller()))
+    }
+    /// Converts an error raised while decoding the data of an object.
+    ///
+    /// Errors from reading the stored data itself are kept as IO errors.
+    /// Returns an error reporting that an object given by the caller is of the wrong type.
+    #[track_caller]
+    pub(crate) fn wrong_object_type<T>(what: &'static str) -> Result<T> {
+        Self::from_kind(ErrorKind::WrongObjectType(what, Location::caller()))
+    }
+    #[track_caller]
+    pub(crate) fn decompression_failed(err: std::io::Error) -> Error {
+        let kind = match limit_of(&err) {
+            Some(limit) => ErrorKind::LimitExceeded(limit, Location::caller()),
+            None => match StoredDataError::unwrap(err) {
+                Ok(err) => ErrorKind::IoError(err, Location::caller()),
+                Err(err) => ErrorKind::DecompressionFailed(None, err, Location::caller()),
+            },
+        };
+        Error(ErrorContents::Kind(Box::new(kind)))
+    }
+}
+
+/// Finds the limit a reader stopped at, for IO errors raised while decoding data.
+fn limit_of(err: &std::io::Error) -> Option<ResourceLimit> {
+    err.get_ref()?.downcast_ref::<ResourceLimit>().copied()
+}
+
+/// An IO error raised while reading the stored data of an object, marked so that it can be told
+/// apart from a failure of the filters decoding that data.
+#[derive(Debug)]
+pub(crate) struct StoredDataError(std::io::Error);
+impl StoredDataError {
+    pub(crate) fn wrap(err: std::io::Error) -> std::io::Error {
+        std::io::Error::new(err.kind(), StoredDataError(err))
+    }
+    /// Returns the original error if `err` was marked with [`StoredDataError::wrap`].
+    fn unwrap(err: std::io::Error) -> std::result::Result<std::io::Error, std::io::Error> {
+        if err.get_ref().is_some_and(|x| x.is::<StoredDataError>()) {
+            let inner = err.into_inner().unwrap();
+            Ok(inner.downcast::<StoredDataError>().unwrap().0)
+        } else {
+            Err(err)
+        }
+    }
+}
+impl Display for StoredDataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl std::error::Error for StoredDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
 impl From<std::io::Error> for Error {
     #[track_caller]
     fn from(err: std::io::Error) -> Self {
-        Error(ErrorContents::Kind(Box::new(ErrorKind::IoError(err, Location::caller()))))
+        let kind = match limit_of(&err) {
+            Some(limit) => ErrorKind::LimitExceeded(limit, Location::caller()),
+            None => {
+                let err = StoredDataError::unwrap(err).unwrap_or_else(|err| err);
+                ErrorKind::IoError(err, Location::caller())
+            }
+        };
+        Error(ErrorContents::Kind(Box::new(kind)))
     }
 }
 impl From<jwalk::Error> for Error {
@@ -46,6 +305,24 @@ impl From<fastcdc::v2020::Error> for Error {
         Error(ErrorContents::Kind(Box::new(ErrorKind::FastCDC(err, Location::caller()))))
     }
 }
+impl From<ignore::Error> for Error {
+    #[track_caller]
+    fn from(err: ignore::Error) -> Self {
+        Error(ErrorContents::Kind(Box::new(ErrorKind::IgnoreError(err, Location::caller()))))
+    }
+}
+impl From<regex::Error> for Error {
+    #[track_caller]
+    fn from(err: regex::Error) -> Self {
+        Error(ErrorContents::Kind(Box::new(ErrorKind::RegexError(err, Location::caller()))))
+    }
+}
+impl From<serde_json::Error> for Error {
+    #[track_caller]
+    fn from(err: serde_json::Error) -> Self {
+        Error(ErrorContents::Kind(Box::new(ErrorKind::JsonError(err, Location::caller()))))
+    }
+}
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self.0 {
@@ -71,3 +348,18 @@ pub fn ensure(cond: bool, str: &'static &'static str) -> Result<()> {
         error(str)
     }
 }
+
+/// Returns an error reporting that the archive being read is malformed.
+#[track_caller]
+pub(crate) fn corrupt<T>(str: &'static &'static str) -> Result<T> {
+    Error::from_kind(ErrorKind::Corrupt(str, Location::caller()))
+}
+/// Returns [`corrupt`] if the given condition about the archive being read does not hold.
+#[track_caller]
+pub(crate) fn ensure_valid(cond: bool, str: &'static &'static str) -> Result<()> {
+    if cond {
+        Ok(())
+    } else {
+        corrupt(str)
+    }
+}
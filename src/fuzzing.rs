@@ -0,0 +1,72 @@
+//! Entry points for the fuzz targets in `fuzz/`. These are not part of the public API.
+//!
+//! Each function takes arbitrary bytes, and must return without panicking, looping forever or
+//! allocating without bound, whatever the input.
+
+use crate::{
+    object_io::{encode_varuint, DiarIo, ObjectReader},
+    objects::*,
+    reader::{ArchiveReader, ReaderConfiguration, ReaderLimits},
+};
+use std::io::Cursor;
+
+/// Parses the input as the trailer and format header of an archive.
+pub fn parse_archive_header(data: &[u8]) {
+    let _ = ObjectReader::open(Cursor::new(data));
+}
+
+/// Parses the input as the encoded header of a single object.
+///
+/// The low bit of the first byte selects whether object references include hashes.
+pub fn parse_object(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let mut reader = host_archive(flags & 1 != 0);
+    let _ = reader.read_sealed(data.to_vec());
+}
+
+/// Parses the input as the fields of a directory object.
+///
+/// The low bit of the first byte selects whether object references include hashes.
+pub fn parse_directory(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let mut header = Vec::new();
+    encode_varuint(&mut header, u32::from(ObjectType::Directory) as u64);
+    encode_varuint(&mut header, 0);
+    encode_varuint(&mut header, data.len() as u64);
+    header.extend_from_slice(data);
+
+    let mut reader = host_archive(flags & 1 != 0);
+    let _ = reader.read_sealed(header);
+}
+
+/// Opens the input as an archive, and walks every entry in it.
+///
+/// Small limits are used, so that inputs claiming huge contents are rejected quickly.
+pub fn read_archive(data: &[u8]) {
+    let limits = ReaderLimits::default()
+        .max_entry_size(1 << 20)
+        .max_total_size(16 << 20)
+        .max_entries(10_000)
+        .max_depth(64)
+        .max_window_log(20);
+    let cfg = ReaderConfiguration::default().limits(limits);
+    let Ok(mut reader) = ArchiveReader::new_with_config(Cursor::new(data), &cfg) else {
+        return;
+    };
+    let _ = reader.entries();
+    let _ = reader.lookup("a/b");
+}
+
+/// Creates a minimal archive, so object headers can be parsed as if they were stored in it.
+fn host_archive(hashed: bool) -> ObjectReader<Cursor<Vec<u8>>> {
+    let mut archive = Vec::new();
+    let mut io = DiarIo::create(&mut archive, hashed, &FormatInfo::new(0, 0)).unwrap();
+    let obj = DiarObject::Symlink(ObjSymlink { target: String::new() });
+    let id = io.write_object(&obj).unwrap();
+    io.finish(id).unwrap();
+    ObjectReader::open(Cursor::new(archive)).unwrap()
+}
@@ -3,14 +3,26 @@
 #[macro_use]
 extern crate tracing;
 
+mod encryption;
 mod errors;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod metadata;
+#[allow(dead_code)] // not yet used by the object format
 mod names;
 mod object_io;
 mod objects;
-mod reader;
+pub mod reader;
+pub mod recovery;
+pub mod signature;
 pub mod writer;
 
+pub use encryption::EncryptionKey;
 pub use errors::*;
+pub use metadata::EntryMetadata;
+pub use object_io::Truncate;
+pub use objects::{FormatInfo, ObjectHash, ObjectId};
 
 static GEAR_TABLE: gearhash::Table = [
     0xb9a737056bfa0e58, 0xfebb6c31b48737de, 0x01825746dcf248ca, 0x6fffaabd8522996b,
@@ -0,0 +1,135 @@
+use crate::{errors::*, objects::*};
+use derive_setters::Setters;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The metadata stored alongside an entry in a directory.
+///
+/// Every field is optional, and fields that are not set are not stored in the archive.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Setters, Serialize, Deserialize)]
+#[setters(strip_option)]
+#[non_exhaustive]
+pub struct EntryMetadata {
+    /// The Unix permission bits of the entry, written as an octal string in manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "serialize_mode", deserialize_with = "deserialize_mode")]
+    pub mode: Option<u32>,
+    /// The numeric id of the user that owns the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u64>,
+    /// The numeric id of the group that owns the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u64>,
+    /// The name of the user that owns the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[setters(into)]
+    pub user: Option<String>,
+    /// The name of the group that owns the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[setters(into)]
+    pub group: Option<String>,
+    /// The modification time of the entry, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// The tags of metadata stored in the archive that this version does not understand, either
+    /// because the tag or the type of its value is unknown.
+    ///
+    /// This is only filled in when reading an archive, and is ignored when writing one.
+    #[serde(skip)]
+    #[setters(skip)]
+    pub unknown_tags: Vec<u32>,
+}
+impl EntryMetadata {
+    /// Returns whether no fields that would be stored in an archive are set.
+    pub fn is_empty(&self) -> bool {
+        self.to_map().is_empty()
+    }
+
+    pub(crate) fn to_map(&self) -> MetadataMap {
+        let mut map = MetadataMap::default();
+        if let Some(mode) = self.mode {
+            map.insert(MetadataTag::UnixMode, Metadata::VarUInt(mode as u64));
+        }
+        if let Some(uid) = self.uid {
+            map.insert(MetadataTag::UnixUid, Metadata::VarUInt(uid));
+        }
+        if let Some(gid) = self.gid {
+            map.insert(MetadataTag::UnixGid, Metadata::VarUInt(gid));
+        }
+        if let Some(user) = &self.user {
+            map.insert(MetadataTag::UserName, Metadata::String(user.clone()));
+        }
+        if let Some(group) = &self.group {
+            map.insert(MetadataTag::GroupName, Metadata::String(group.clone()));
+        }
+        if let Some(mtime) = self.mtime {
+            map.insert(MetadataTag::ModifiedTime, Metadata::VarInt(mtime));
+        }
+        map
+    }
+
+    pub(crate) fn from_map(map: &MetadataMap) -> Result<EntryMetadata> {
+        fn get_uint(map: &MetadataMap, tag: MetadataTag) -> Result<Option<u64>> {
+            match map.get(&tag) {
+                None | Some(Metadata::Unknown { .. }) => Ok(None),
+                Some(Metadata::VarUInt(v)) => Ok(Some(*v)),
+                Some(_) => corrupt(&"entry metadata has the wrong type"),
+            }
+        }
+        fn get_string(map: &MetadataMap, tag: MetadataTag) -> Result<Option<String>> {
+            match map.get(&tag) {
+                None | Some(Metadata::Unknown { .. }) => Ok(None),
+                Some(Metadata::String(v)) => Ok(Some(v.clone())),
+                Some(_) => corrupt(&"entry metadata has the wrong type"),
+            }
+        }
+
+        let mode = match get_uint(map, MetadataTag::UnixMode)? {
+            Some(mode) => match u32::try_from(mode) {
+                Ok(mode) => Some(mode),
+                Err(_) => return corrupt(&"file mode out of range"),
+            },
+            None => None,
+        };
+        let mtime = match map.get(&MetadataTag::ModifiedTime) {
+            None | Some(Metadata::Unknown { .. }) => None,
+            Some(Metadata::VarInt(v)) => Some(*v),
+            Some(_) => return corrupt(&"entry metadata has the wrong type"),
+        };
+        Ok(EntryMetadata {
+            mode,
+            uid: get_uint(map, MetadataTag::UnixUid)?,
+            gid: get_uint(map, MetadataTag::UnixGid)?,
+            user: get_string(map, MetadataTag::UserName)?,
+            group: get_string(map, MetadataTag::GroupName)?,
+            mtime,
+            unknown_tags: map
+                .iter()
+                .filter(|(k, v)| {
+                    matches!(k, MetadataTag::Unknown(_)) || matches!(v, Metadata::Unknown { .. })
+                })
+                .map(|(k, _)| u32::from(*k))
+                .collect(),
+        })
+    }
+}
+
+fn serialize_mode<S: Serializer>(
+    mode: &Option<u32>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match mode {
+        Some(mode) => serializer.serialize_str(&format!("{mode:o}")),
+        None => serializer.serialize_none(),
+    }
+}
+fn deserialize_mode<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<u32>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(mode) => match u32::from_str_radix(&mode, 8) {
+            Ok(mode) => Ok(Some(mode)),
+            Err(_) => Err(D::Error::custom(format!("invalid octal file mode: {mode:?}"))),
+        },
+        None => Ok(None),
+    }
+}
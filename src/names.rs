@@ -1,8 +1,12 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    hash::{Hash, Hasher},
+};
 
 macro_rules! name {
     ($($tok:ident $str:literal)*) => {
         #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+        #[allow(clippy::enum_variant_names)]
         pub enum KnownName {
             $($tok,)*
         }
@@ -35,7 +39,7 @@ name! {
 }
 
 /// A name which may or may not be registered in the string table.
-#[derive(Clone, Debug, Hash)]
+#[derive(Clone, Debug)]
 pub struct Name<'a>(NameData<'a>);
 #[derive(Clone, Debug, Hash)]
 enum NameData<'a> {
@@ -73,6 +77,11 @@ impl<'a, 'b> PartialEq<Name<'a>> for Name<'b> {
     }
 }
 impl<'a> Eq for Name<'a> {}
+impl<'a> Hash for Name<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
 
 impl<'a> From<KnownName> for Name<'a> {
     fn from(name: KnownName) -> Self {
@@ -1,25 +1,41 @@
-use crate::{
-    errors::*,
-    names::{KnownName, Name},
-    objects::*,
-};
+use crate::{errors::*, objects::*};
 use byteorder::*;
 use std::{
-    collections::HashMap,
+    cmp,
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
     io,
-    io::{BufWriter, Cursor, Seek, SeekFrom, Write},
-    sync::Arc,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
 };
 use twox_hash::RandomXxh3HashBuilder64;
-use zstd::{
-    dict::EncoderDictionary,
-    zstd_safe::{CParameter, CompressionLevel},
-    Encoder,
-};
 
 const ARC_HEADER: u64 = u64::from_le_bytes(*b"DiarArc1");
-const END_HEADER: u64 = u64::from_le_bytes(*b"DiarEnd1");
+pub(crate) const END_HEADER: u64 = u64::from_le_bytes(*b"DiarEnd1");
+pub(crate) const END_HEADER_HASHED: u64 = u64::from_le_bytes(*b"DiarEndH");
+const SYNC_MARKER: u64 = u64::from_le_bytes(*b"DiarSync");
+
+/// The length of the fixed part of the trailer: the magic, the archive length and the root offset.
+pub(crate) const END_LENGTH: u64 = 24;
+pub(crate) const HASH_LENGTH: u64 = 32;
+/// The length of a sync marker: the magic, and the length of the header it follows.
+const SYNC_LENGTH: u64 = 12;
+
+/// Appends the variable-length encoding of an unsigned integer used throughout the format.
+pub(crate) fn encode_varuint(out: &mut Vec<u8>, mut data: u64) {
+    loop {
+        let frag = data & 0x7F;
+        data >>= 7;
+
+        if data == 0 {
+            out.push(frag as u8);
+            break;
+        } else {
+            out.push(0x80 | frag as u8);
+        }
+    }
+}
 
 /// A trait for stream-like objects that can be efficiently truncated.
 pub trait Truncate {
@@ -40,57 +56,140 @@ impl Truncate for Cursor<Vec<u8>> {
 }
 impl Truncate for File {
     fn truncate(&mut self, len: u64) -> io::Result<()> {
-        if self.seek(SeekFrom::Current(0))? > len {
+        if self.stream_position()? > len {
             self.seek(SeekFrom::Start(len))?;
         }
         self.set_len(len)
     }
 }
 
-pub struct DiarIo<S> {
+/// A stream wrapper that tracks the current offset in the archive, and hashes the bytes of the
+/// object currently being written.
+///
+/// While `capture` is set, writes are collected into it instead of reaching the stream.
+pub struct HashWriter<S> {
     stream: S,
+    position: u64,
+    hasher: Option<blake3::Hasher>,
+    capture: Option<Vec<u8>>,
+}
+impl<S: Write> Write for HashWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(capture) = &mut self.capture {
+            capture.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        let len = self.stream.write(buf)?;
+        self.position += len as u64;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..len]);
+        }
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Encodes the magic number and format header an archive starts with, so it can be updated in
+/// place by writers adding to an existing archive.
+pub(crate) fn format_header(format: &FormatInfo) -> Result<Vec<u8>> {
+    Ok(DiarIo::create(Vec::new(), false, format)?.into_inner())
+}
+
+pub struct DiarIo<S> {
+    stream: HashWriter<S>,
     obj_ids: HashMap<ObjectId, u64, RandomXxh3HashBuilder64>,
-    rel_offset: u64,
+    obj_hashes: HashMap<ObjectId, ObjectHash, RandomXxh3HashBuilder64>,
+    hash_objects: bool,
+    sync_markers: bool,
 }
-impl<S: Write + Seek> DiarIo<S> {
-    pub fn create(mut stream: S) -> Result<Self> {
+impl<S: Write> DiarIo<S> {
+    /// Starts a new archive in the given stream.
+    ///
+    /// The stream does not need to be seekable, as offsets are tracked by counting the bytes
+    /// written. If `hash_objects` is set, every object reference also commits to the hash of the
+    /// object it points to, and the hash of the root object is stored in the trailer.
+    ///
+    /// If `format` includes [`FormatInfo::OPTIONAL_SYNC_MARKERS`], a sync marker is written after
+    /// every object.
+    pub fn create(stream: S, hash_objects: bool, format: &FormatInfo) -> Result<Self> {
+        let mut stream = HashWriter { stream, position: 0, hasher: None, capture: None };
         stream.write_u64::<LE>(ARC_HEADER)?;
-        let rel_offset = stream.seek(SeekFrom::Current(0))?;
-        Ok(DiarIo { stream, obj_ids: Default::default(), rel_offset })
+        let mut io = DiarIo {
+            stream,
+            obj_ids: Default::default(),
+            obj_hashes: Default::default(),
+            hash_objects,
+            sync_markers: format.optional_features & FormatInfo::OPTIONAL_SYNC_MARKERS != 0,
+        };
+        let mut optional_features = format.optional_features;
+        if hash_objects {
+            optional_features |= FormatInfo::OPTIONAL_OBJECT_HASHES;
+        }
+        io.write_varuint(format.version)?;
+        io.write_varuint(format.required_features)?;
+        io.write_varuint(optional_features)?;
+        Ok(io)
     }
 
-    fn write_varint(&mut self, mut data: i64) -> Result<()> {
-        if data < 0 {
-            data = data ^ 0x7FFFFFFFFFFFFFFF;
+    /// Continues writing an archive that already contains `position` bytes, such as one truncated
+    /// back to a checkpoint. The format header is not written again.
+    ///
+    /// Objects already in the archive can be referred to after registering them with
+    /// [`DiarIo::import_object`].
+    pub fn resume(stream: S, position: u64, hash_objects: bool, sync_markers: bool) -> Self {
+        DiarIo {
+            stream: HashWriter { stream, position, hasher: None, capture: None },
+            obj_ids: Default::default(),
+            obj_hashes: Default::default(),
+            hash_objects,
+            sync_markers,
         }
-        data = (data << 1) | ((data >> 63) & 1);
-        let mut data = data as u64;
-
-        loop {
-            let frag = data & 0x7F;
-            data = data >> 7;
+    }
 
-            if data == 0 {
-                self.stream.write_u8(frag as u8)?;
-                break;
-            } else {
-                self.stream.write_u8(0x80 | frag as u8)?;
-            }
+    /// Registers an object already in the archive, so that new objects can refer to it.
+    pub fn import_object(&mut self, offset: u64, hash: Option<ObjectHash>) -> Result<ObjectId> {
+        ensure(offset < self.stream.position, &"imported object is past the end of the archive")?;
+        ensure(
+            hash.is_some() == self.hash_objects,
+            &"imported object does not match hash setting",
+        )?;
+        let id = ObjectId::new();
+        self.obj_ids.insert(id, offset);
+        if let Some(hash) = hash {
+            self.obj_hashes.insert(id, hash);
         }
+        Ok(id)
+    }
+
+    /// Returns the number of bytes written to the archive so far.
+    pub fn position(&self) -> u64 {
+        self.stream.position
+    }
+
+    /// Flushes the objects written so far to the underlying stream.
+    pub fn flush(&mut self) -> Result<()> {
+        self.stream.flush()?;
         Ok(())
     }
-    fn write_varuint(&mut self, mut data: u64) -> Result<()> {
-        loop {
-            let frag = data & 0x7F;
-            data = data >> 7;
 
-            if data == 0 {
-                self.stream.write_u8(frag as u8)?;
-                break;
-            } else {
-                self.stream.write_u8(0x80 | frag as u8)?;
-            }
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream.stream
+    }
+
+    fn write_varint(&mut self, mut data: i64) -> Result<()> {
+        if data < 0 {
+            data ^= 0x7FFFFFFFFFFFFFFF;
         }
+        data = (data << 1) | ((data >> 63) & 1);
+        self.write_varuint(data as u64)
+    }
+    fn write_varuint(&mut self, data: u64) -> Result<()> {
+        let mut buf = Vec::with_capacity(10);
+        encode_varuint(&mut buf, data);
+        self.stream.write_all(&buf)?;
         Ok(())
     }
     fn get_object_offset(&self, id: ObjectId) -> Result<u64> {
@@ -103,9 +202,33 @@ impl<S: Write + Seek> DiarIo<S> {
             }
         }
     }
+    pub fn get_object_hash(&self, id: ObjectId) -> Result<ObjectHash> {
+        match self.obj_hashes.get(&id) {
+            Some(hash) => Ok(*hash),
+            None => ErrorContents::ObjectIdError(id).emit(),
+        }
+    }
     fn write_object_id(&mut self, id: ObjectId) -> Result<()> {
-        let id = self.get_object_offset(id)?;
-        self.write_varuint(id)
+        let offset = self.get_object_offset(id)?;
+        self.write_varuint(offset)?;
+        if self.hash_objects && id != ObjectId::NONE {
+            let hash = self.get_object_hash(id)?;
+            self.stream.write_all(&hash)?;
+        }
+        Ok(())
+    }
+    /// Writes an object id with a fixed length, so that tables of them can be searched in place.
+    fn write_fixed_object_id(&mut self, id: ObjectId) -> Result<()> {
+        let offset = self.get_object_offset(id)?;
+        self.stream.write_u64::<LE>(offset)?;
+        if self.hash_objects {
+            let hash = match id {
+                ObjectId::NONE => ObjectHash::default(),
+                id => self.get_object_hash(id)?,
+            };
+            self.stream.write_all(&hash)?;
+        }
+        Ok(())
     }
     fn write_object_ids(&mut self, list: &[ObjectId]) -> Result<()> {
         for id in list {
@@ -114,11 +237,14 @@ impl<S: Write + Seek> DiarIo<S> {
         self.write_object_id(ObjectId::NONE)?;
         Ok(())
     }
-    fn write_full_string(&mut self, value: &str) -> Result<()> {
+    fn write_full_bytes(&mut self, value: &[u8]) -> Result<()> {
         self.write_varuint(value.len() as u64)?;
-        self.stream.write_all(value.as_bytes())?;
+        self.stream.write_all(value)?;
         Ok(())
     }
+    fn write_full_string(&mut self, value: &str) -> Result<()> {
+        self.write_full_bytes(value.as_bytes())
+    }
 
     fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
         match metadata {
@@ -138,43 +264,113 @@ impl<S: Write + Seek> DiarIo<S> {
                 self.write_varuint(META_TAG_STRING)?;
                 self.write_full_string(v)?;
             }
+            Metadata::Bytes(v) => {
+                self.write_varuint(META_TAG_BYTES)?;
+                self.write_full_bytes(v)?;
+            }
+            Metadata::Unknown { kind, data } => {
+                ensure(*kind > META_TAG_LAST_KNOWN, &"unknown metadata type is reserved")?;
+                self.write_varuint(*kind)?;
+                self.write_full_bytes(data)?;
+            }
         }
         Ok(())
     }
     fn write_metadata_table(&mut self, table: &MetadataMap) -> Result<()> {
         for (k, v) in table {
             ensure(*k != MetadataTag::EndTag, &"early EndTag encountered!")?;
-            self.write_varuint(*k as u64)?;
+            self.write_varuint(u32::from(*k) as u64)?;
             self.write_metadata(v)?;
         }
-        self.write_varuint(MetadataTag::EndTag as u64)?;
+        self.write_varuint(u32::from(MetadataTag::EndTag) as u64)?;
         Ok(())
     }
 
+    fn begin_object(&mut self) {
+        if self.hash_objects {
+            self.stream.hasher = Some(blake3::Hasher::new());
+        }
+    }
+
     pub fn write_object(&mut self, obj: &DiarObject) -> Result<ObjectId> {
+        self.begin_object();
         self.write_object_contents(obj, 0)
     }
     pub fn write_object_with_data(
         &mut self,
         obj: &DiarObject,
-        data_write: impl FnOnce(&mut S) -> Result<()>,
+        data_write: impl FnOnce(&mut HashWriter<S>) -> Result<()>,
     ) -> Result<ObjectId> {
-        let start_offset = self.stream.seek(SeekFrom::Current(0))?;
+        self.begin_object();
+        let start_offset = self.stream.position;
         data_write(&mut self.stream)?;
-        let end_offset = self.stream.seek(SeekFrom::Current(0))?;
+        let end_offset = self.stream.position;
         let length = end_offset - start_offset;
         self.write_object_contents(obj, length)
     }
+    /// Writes an object whose header is stored as data passed through the given filters.
+    ///
+    /// `data_write` receives the encoded header, and must apply the filters to it.
+    pub fn write_sealed_object(
+        &mut self,
+        obj: &DiarObject,
+        filters: &[ObjectId],
+        data_write: impl FnOnce(&[u8], &mut HashWriter<S>) -> Result<()>,
+    ) -> Result<ObjectId> {
+        ensure(!obj.has_data(), &"object cannot be sealed")?;
+        self.stream.capture = Some(Vec::new());
+        let result = self.encode_object(obj, 0);
+        let contents = self.stream.capture.take().unwrap();
+        result?;
+
+        let sealed = DiarObject::Sealed(ObjSealed { filters: filters.to_vec() });
+        self.write_object_with_data(&sealed, |x| data_write(&contents, x))
+    }
     fn write_object_contents(&mut self, obj: &DiarObject, length: u64) -> Result<ObjectId> {
-        let header_off = self.stream.seek(SeekFrom::Current(0))? - self.rel_offset;
+        let header_off = self.stream.position;
+        self.encode_object(obj, length)?;
+
+        let id = ObjectId::new();
+        self.obj_ids.insert(id, header_off);
+        if let Some(hasher) = self.stream.hasher.take() {
+            self.obj_hashes.insert(id, *hasher.finalize().as_bytes());
+        }
+        if self.sync_markers {
+            let header_len = self.stream.position - header_off;
+            ensure(header_len <= u32::MAX as u64, &"object header is too long")?;
+            self.stream.write_u64::<LE>(SYNC_MARKER)?;
+            self.stream.write_u32::<LE>(header_len as u32)?;
+        }
+        Ok(id)
+    }
+    /// Encodes an object header: its type, the length of its data, and its fields prefixed with
+    /// their length, so that readers can skip objects and fields they do not know.
+    fn encode_object(&mut self, obj: &DiarObject, length: u64) -> Result<()> {
+        ensure(obj.has_data() || length == 0, &"data not allowed for this object type")?;
+
+        let previous = self.stream.capture.replace(Vec::new());
+        let result = self.encode_fields(obj);
+        let fields = mem::replace(&mut self.stream.capture, previous).unwrap();
+        result?;
+
+        self.write_varuint(obj.object_type() as u64)?;
+        self.write_varuint(length)?;
+        self.write_full_bytes(&fields)?;
+        Ok(())
+    }
+    fn encode_fields(&mut self, obj: &DiarObject) -> Result<()> {
         match obj {
             DiarObject::BlobPlain(obj) => {
-                self.write_varuint(ObjectType::BlobPlain as u64)?;
                 self.write_object_ids(&obj.filters)?;
             }
             DiarObject::Directory(obj) => {
-                self.write_varuint(ObjectType::Directory as u64)?;
-                ensure(length == 0, &"length not allowed for Directory")?;
+                if obj.sorted {
+                    ensure(
+                        obj.entries.windows(2).all(|x| x[0].name < x[1].name),
+                        &"directory entries are not sorted",
+                    )?;
+                }
+                self.write_varuint(if obj.sorted { DIR_FLAG_SORTED } else { 0 })?;
                 for entry in &obj.entries {
                     self.write_object_id(entry.data)?;
                     self.write_object_id(entry.metadata)?;
@@ -183,19 +379,13 @@ impl<S: Write + Seek> DiarIo<S> {
                 self.write_object_id(ObjectId::NONE)?;
             }
             DiarObject::Metadata(obj) => {
-                self.write_varuint(ObjectType::Metadata as u64)?;
-                ensure(length == 0, &"length not allowed for Metadata")?;
                 self.write_metadata_table(&obj.metadata)?;
             }
             DiarObject::Archive(obj) => {
-                self.write_varuint(ObjectType::Archive as u64)?;
-                ensure(length == 0, &"length not allowed for Archive")?;
                 self.write_object_id(obj.root)?;
                 self.write_metadata_table(&obj.metadata)?;
             }
             DiarObject::Root(obj) => {
-                self.write_varuint(ObjectType::Root as u64)?;
-                ensure(length == 0, &"length not allowed for Root")?;
                 self.write_object_id(obj.main)?;
                 for (k, v) in &obj.alt {
                     self.write_object_id(*v)?;
@@ -204,32 +394,876 @@ impl<S: Write + Seek> DiarIo<S> {
                 self.write_object_id(ObjectId::NONE)?;
                 self.write_metadata_table(&obj.metadata)?;
             }
+            DiarObject::Sealed(obj) => {
+                self.write_object_ids(&obj.filters)?;
+            }
+            DiarObject::Symlink(obj) => {
+                self.write_full_string(&obj.target)?;
+            }
+            DiarObject::PathIndex(obj) => {
+                ensure(
+                    obj.entries.windows(2).all(|x| x[0].hash < x[1].hash),
+                    &"path index is not sorted",
+                )?;
+                self.write_varuint(obj.entries.len() as u64)?;
+                for entry in &obj.entries {
+                    self.stream.write_all(&entry.hash)?;
+                    self.write_fixed_object_id(entry.data)?;
+                    self.write_fixed_object_id(entry.metadata)?;
+                }
+            }
+            DiarObject::ContentIndex(obj) => {
+                ensure(
+                    obj.entries.windows(2).all(|x| x[0].hash < x[1].hash),
+                    &"content index is not sorted",
+                )?;
+                self.write_varuint(obj.entries.len() as u64)?;
+                for entry in &obj.entries {
+                    self.stream.write_all(&entry.hash)?;
+                    self.write_varuint(entry.len)?;
+                    self.write_object_id(entry.data)?;
+                }
+            }
             DiarObject::FilterZstd(obj) => {
-                self.write_varuint(ObjectType::FilterZstd as u64)?;
-                ensure(length == 0, &"length not allowed for FilterZstd")?;
                 self.write_object_ids(&obj.dict_sources)?;
             }
+            DiarObject::FilterXChaCha20Poly1305(obj) => {
+                match &obj.kdf {
+                    KeyDerivation::Raw => self.write_varuint(KDF_RAW)?,
+                    KeyDerivation::Argon2id { salt, m_cost, t_cost, p_cost } => {
+                        self.write_varuint(KDF_ARGON2ID)?;
+                        self.write_full_bytes(salt)?;
+                        self.write_varuint(*m_cost as u64)?;
+                        self.write_varuint(*t_cost as u64)?;
+                        self.write_varuint(*p_cost as u64)?;
+                    }
+                }
+                self.write_full_bytes(&obj.key_check)?;
+                self.write_varuint(obj.chunk_size)?;
+            }
             DiarObject::ZstdPreloadList(obj) => {
-                self.write_varuint(ObjectType::FilterZstd as u64)?;
-                ensure(length == 0, &"length not allowed for ZstdPreloadList")?;
                 self.write_object_ids(&obj.list)?;
             }
+            DiarObject::Unknown(_) => return error(&"unknown objects cannot be written"),
         }
-
-        let id = ObjectId::new();
-        self.obj_ids.insert(id, header_off);
-        Ok(id)
+        Ok(())
     }
 
     pub fn finish(&mut self, root_id: ObjectId) -> Result<()> {
-        let arc_end = self.stream.seek(SeekFrom::Current(0))?;
-        let length = arc_end - self.rel_offset;
+        let length = self.stream.position;
 
         let obj_offset = self.get_object_offset(root_id)?;
 
-        self.stream.write_u64::<LE>(END_HEADER)?;
+        if self.hash_objects {
+            let hash = self.get_object_hash(root_id)?;
+            self.stream.write_all(&hash)?;
+            self.stream.write_u64::<LE>(END_HEADER_HASHED)?;
+        } else {
+            self.stream.write_u64::<LE>(END_HEADER)?;
+        }
         self.stream.write_u64::<LE>(length)?;
         self.stream.write_u64::<LE>(obj_offset)?;
+        self.stream.flush()?;
         Ok(())
     }
 }
+
+struct ObjectLocation {
+    offset: u64,
+    hash: Option<ObjectHash>,
+}
+
+/// Reads objects from an existing archive.
+///
+/// Object ids are assigned as references to objects are encountered, so they are only
+/// meaningful for the reader that returned them.
+pub struct ObjectReader<S> {
+    stream: S,
+    base: u64,
+    length: u64,
+    hashed: bool,
+    format: FormatInfo,
+    objects_start: u64,
+    root: ObjectId,
+    obj_ids: HashMap<u64, ObjectId, RandomXxh3HashBuilder64>,
+    obj_locs: HashMap<ObjectId, ObjectLocation, RandomXxh3HashBuilder64>,
+    header: Vec<u8>,
+    /// The length `header` may not grow past while parsing the current object.
+    header_limit: usize,
+    sealed: Option<Cursor<Vec<u8>>>,
+    /// The path indexes that have been checked against their hashes by
+    /// [`ObjectReader::search_path_index`].
+    verified_indexes: HashSet<ObjectId, RandomXxh3HashBuilder64>,
+}
+impl<S: Read + Seek> ObjectReader<S> {
+    /// Opens an archive that ends at the end of the given stream.
+    pub fn open(mut stream: S) -> Result<Self> {
+        let end = stream.seek(SeekFrom::End(0))?;
+        if end < END_LENGTH + 8 {
+            return Error::truncated_trailer();
+        }
+
+        stream.seek(SeekFrom::Start(end - END_LENGTH))?;
+        let magic = stream.read_u64::<LE>()?;
+        let length = stream.read_u64::<LE>()?;
+        let root_offset = stream.read_u64::<LE>()?;
+
+        let (hashed, trailer_start) = match magic {
+            END_HEADER => (false, end - END_LENGTH),
+            END_HEADER_HASHED => {
+                if end < END_LENGTH + HASH_LENGTH + 8 {
+                    return Error::truncated_trailer();
+                }
+                (true, end - END_LENGTH - HASH_LENGTH)
+            }
+            _ => return Error::truncated_trailer(),
+        };
+        let root_hash = if hashed {
+            let mut hash = ObjectHash::default();
+            stream.seek(SeekFrom::Start(trailer_start))?;
+            stream.read_exact(&mut hash)?;
+            Some(hash)
+        } else {
+            None
+        };
+
+        if length < 8 || length > trailer_start {
+            return Error::truncated_trailer();
+        }
+        let base = trailer_start - length;
+        stream.seek(SeekFrom::Start(base))?;
+        if stream.read_u64::<LE>()? != ARC_HEADER {
+            return Error::bad_magic();
+        }
+
+        let mut reader = ObjectReader::with_stream(stream, base, length, hashed);
+        reader.format = reader.read_format()?;
+        reader.objects_start = reader.stream.stream_position()? - base;
+        reader.root = reader.object_at(root_offset, root_hash)?;
+        Ok(reader)
+    }
+
+    /// Opens an archive that starts at the start of the given stream, without reading its
+    /// trailer.
+    ///
+    /// This is used to salvage archives whose trailer is missing or damaged, and requires the
+    /// archive to have been written with sync markers. The returned reader has no root object, and
+    /// objects are found with [`ObjectReader::scan`] instead.
+    pub fn open_unterminated(mut stream: S) -> Result<Self> {
+        let length = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(0))?;
+        if length < 8 || stream.read_u64::<LE>()? != ARC_HEADER {
+            return Error::bad_magic();
+        }
+
+        let mut reader = ObjectReader::with_stream(stream, 0, length, false);
+        reader.format = reader.read_format()?;
+        reader.objects_start = reader.stream.stream_position()?;
+        reader.hashed = reader.format.optional_features & FormatInfo::OPTIONAL_OBJECT_HASHES != 0;
+        if !reader.has_sync_markers() {
+            return error(&"archive was written without sync markers, and cannot be salvaged");
+        }
+        Ok(reader)
+    }
+
+    fn with_stream(stream: S, base: u64, length: u64, hashed: bool) -> Self {
+        ObjectReader {
+            stream,
+            base,
+            length,
+            hashed,
+            format: FormatInfo::new(0, 0),
+            objects_start: 0,
+            root: ObjectId::NONE,
+            obj_ids: Default::default(),
+            obj_locs: Default::default(),
+            header: Vec::new(),
+            header_limit: usize::MAX,
+            sealed: None,
+            verified_indexes: Default::default(),
+        }
+    }
+
+    fn read_format(&mut self) -> Result<FormatInfo> {
+        let version = self.read_varuint()?;
+        if version < FormatInfo::MIN_VERSION || version > FormatInfo::CURRENT_VERSION {
+            return Error::unsupported_version(version);
+        }
+        let required_features = self.read_varuint()?;
+        let optional_features = self.read_varuint()?;
+        let unknown = required_features & !FormatInfo::REQUIRED_KNOWN;
+        if unknown != 0 {
+            return Error::unsupported_features(unknown);
+        }
+        self.header.clear();
+        Ok(FormatInfo { version, required_features, optional_features })
+    }
+
+    /// Returns the format version and features of the archive.
+    pub fn format(&self) -> FormatInfo {
+        self.format
+    }
+
+    /// Returns the root object of the archive.
+    pub fn root(&self) -> ObjectId {
+        self.root
+    }
+
+    /// Returns the length of the archive, not including the trailer.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns the offset in the stream the archive starts at.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the offset the first object starts at, after the format header.
+    pub fn objects_start(&self) -> u64 {
+        self.objects_start
+    }
+
+    /// Returns the offset of an object's header in the archive.
+    pub fn object_offset(&self, id: ObjectId) -> Result<u64> {
+        Ok(self.location(id)?.offset)
+    }
+
+    /// Returns the range of the archive an object occupies, including its data, header and sync
+    /// marker.
+    pub fn object_extent(&mut self, id: ObjectId) -> Result<Range<u64>> {
+        let (_, length) = self.read_header(id)?;
+        let offset = self.location(id)?.offset;
+        let marker = if self.has_sync_markers() { SYNC_LENGTH } else { 0 };
+        Ok(offset - length..offset + self.header.len() as u64 + marker)
+    }
+
+    /// Returns the object whose header starts at the given offset, if it is stored there.
+    ///
+    /// This is meant for offsets found with [`ObjectReader::scan`]. Objects found this way are
+    /// not checked against a hash unless a reference to them has already been read.
+    pub fn object_at_offset(&mut self, offset: u64) -> Result<ObjectId> {
+        match self.obj_ids.get(&offset) {
+            Some(id) => Ok(*id),
+            None => self.object_at(offset, None),
+        }
+    }
+
+    /// Returns whether a reference to the object at the given offset has been read.
+    pub fn is_referenced(&self, offset: u64) -> bool {
+        self.obj_ids.contains_key(&offset)
+    }
+
+    fn has_sync_markers(&self) -> bool {
+        self.format.optional_features & FormatInfo::OPTIONAL_SYNC_MARKERS != 0
+    }
+
+    /// Finds every intact object by scanning forward from the start of the archive, using the
+    /// sync markers written after each object.
+    ///
+    /// Only the archive before `end` is scanned, which excludes anything stored after the
+    /// objects. Returns the offsets of the objects found in order, and the ranges of the archive
+    /// that contained no intact object, including an incomplete object at the end.
+    pub fn scan(&mut self, end: u64) -> Result<(Vec<u64>, Vec<Range<u64>>)> {
+        ensure(self.has_sync_markers(), &"archive was written without sync markers")?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut objects = Vec::new();
+        let mut damaged = Vec::new();
+        let mut pos = self.objects_start;
+        loop {
+            // prefer an object that starts right after the previous one, as a sync marker may
+            // also appear by chance inside the data of an object
+            let mut search = pos;
+            let mut resync = None;
+            let found = loop {
+                let Some(marker) = self.find_sync_marker(search, end, &mut buf)? else {
+                    break resync;
+                };
+                search = marker + 1;
+                match self.object_before_marker(marker)? {
+                    Some((start, offset)) if start == pos => break Some((start, offset, marker)),
+                    Some((start, offset)) if start > pos && resync.is_none() => {
+                        resync = Some((start, offset, marker));
+                    }
+                    _ => {}
+                }
+            };
+            let Some((start, offset, marker)) = found else {
+                break;
+            };
+            if start > pos {
+                damaged.push(pos..start);
+            }
+            objects.push(offset);
+            pos = marker + SYNC_LENGTH;
+        }
+        if pos < end && !(end == self.length && self.is_trailer(pos)?) {
+            damaged.push(pos..end);
+        }
+        Ok((objects, damaged))
+    }
+    fn find_sync_marker(&mut self, from: u64, end: u64, buf: &mut [u8]) -> Result<Option<u64>> {
+        let magic = SYNC_MARKER.to_le_bytes();
+        let mut pos = from;
+        while pos + SYNC_LENGTH <= end {
+            let len = cmp::min(buf.len() as u64, end - pos) as usize;
+            self.stream.seek(SeekFrom::Start(self.base + pos))?;
+            self.stream.read_exact(&mut buf[..len])?;
+            if let Some(idx) = buf[..len].windows(magic.len()).position(|x| x == magic) {
+                return Ok(Some(pos + idx as u64));
+            }
+            pos += (len - magic.len() + 1) as u64;
+        }
+        Ok(None)
+    }
+    /// Checks that the sync marker at the given offset follows a well-formed object header,
+    /// returning where the object's data starts and the offset of its header.
+    fn object_before_marker(&mut self, marker: u64) -> Result<Option<(u64, u64)>> {
+        if marker + SYNC_LENGTH > self.length {
+            return Ok(None);
+        }
+        self.stream.seek(SeekFrom::Start(self.base + marker + 8))?;
+        let header_len = self.stream.read_u32::<LE>()? as u64;
+        let Some(offset) = marker.checked_sub(header_len) else {
+            return Ok(None);
+        };
+        if offset < self.objects_start {
+            return Ok(None);
+        }
+
+        self.stream.seek(SeekFrom::Start(self.base + offset))?;
+        self.header.clear();
+        self.header_limit = header_len as usize;
+        match self.read_frame() {
+            Ok((length, fields_len)) if fields_len == header_len - self.header.len() as u64 => {
+                Ok(offset
+                    .checked_sub(length)
+                    .filter(|x| *x >= self.objects_start)
+                    .map(|start| (start, offset)))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if e.is_corruption() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    fn read_frame(&mut self) -> Result<(u64, u64)> {
+        self.read_u32()?;
+        let length = self.read_varuint()?;
+        let fields_len = self.read_varuint()?;
+        Ok((length, fields_len))
+    }
+    /// Returns whether the archive ends with a complete trailer starting at the given offset.
+    fn is_trailer(&mut self, pos: u64) -> Result<bool> {
+        let expected = match self.length - pos {
+            END_LENGTH => END_HEADER,
+            x if x == END_LENGTH + HASH_LENGTH => END_HEADER_HASHED,
+            _ => return Ok(false),
+        };
+        self.stream
+            .seek(SeekFrom::Start(self.base + self.length - END_LENGTH))?;
+        Ok(self.stream.read_u64::<LE>()? == expected)
+    }
+
+    /// Returns the underlying stream, which the archive ends at the end of.
+    pub fn stream_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns whether the objects in this archive are covered by hashes.
+    pub fn is_hashed(&self) -> bool {
+        self.hashed
+    }
+
+    /// Returns the hash an object is expected to have, if the archive is hashed.
+    pub fn object_hash(&self, id: ObjectId) -> Result<Option<ObjectHash>> {
+        Ok(self.location(id)?.hash)
+    }
+
+    fn object_at(&mut self, offset: u64, hash: Option<ObjectHash>) -> Result<ObjectId> {
+        if offset < 8 || offset >= self.length {
+            return Error::dangling_offset(offset);
+        }
+        match self.obj_ids.get(&offset) {
+            Some(id) => {
+                ensure_valid(self.obj_locs[id].hash == hash, &"conflicting hashes for object")?;
+                Ok(*id)
+            }
+            None => {
+                let id = ObjectId::new();
+                self.obj_ids.insert(offset, id);
+                self.obj_locs.insert(id, ObjectLocation { offset, hash });
+                Ok(id)
+            }
+        }
+    }
+    fn location(&self, id: ObjectId) -> Result<&ObjectLocation> {
+        match self.obj_locs.get(&id) {
+            Some(loc) => Ok(loc),
+            None => ErrorContents::ObjectIdError(id).emit(),
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+    fn read_bytes(&mut self, len: usize) -> Result<&[u8]> {
+        let start = self.header.len();
+        ensure_valid(
+            len <= self.header_limit - start,
+            &"object header is longer than its stated length",
+        )?;
+        self.header.resize(start + len, 0);
+        let result = match &mut self.sealed {
+            Some(sealed) => sealed.read_exact(&mut self.header[start..]),
+            None => self.stream.read_exact(&mut self.header[start..]),
+        };
+        match result {
+            Ok(()) => Ok(&self.header[start..]),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                corrupt(&"object header runs past the end of its data")
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    fn read_u32(&mut self) -> Result<u32> {
+        match u32::try_from(self.read_varuint()?) {
+            Ok(v) => Ok(v),
+            Err(_) => Error::invalid_varint(),
+        }
+    }
+    fn read_varint(&mut self) -> Result<i64> {
+        let data = self.read_varuint()?;
+        let mut data = data.rotate_right(1) as i64;
+        if data < 0 {
+            data ^= 0x7FFFFFFFFFFFFFFF;
+        }
+        Ok(data)
+    }
+    fn read_varuint(&mut self) -> Result<u64> {
+        let mut data = 0;
+        for i in 0..10 {
+            let frag = self.read_u8()?;
+            if i == 9 && frag > 1 {
+                return Error::invalid_varint();
+            }
+            data |= ((frag & 0x7F) as u64) << (i * 7);
+            if frag & 0x80 == 0 {
+                return Ok(data);
+            }
+        }
+        Error::invalid_varint()
+    }
+    fn read_object_id(&mut self) -> Result<ObjectId> {
+        let offset = self.read_varuint()?;
+        if offset == 0 {
+            Ok(ObjectId::NONE)
+        } else {
+            let hash = if self.hashed {
+                let mut hash = ObjectHash::default();
+                hash.copy_from_slice(self.read_bytes(HASH_LENGTH as usize)?);
+                Some(hash)
+            } else {
+                None
+            };
+            self.object_at(offset, hash)
+        }
+    }
+    fn read_fixed_object_id(&mut self) -> Result<ObjectId> {
+        let offset = u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap());
+        let hash = if self.hashed {
+            let mut hash = ObjectHash::default();
+            hash.copy_from_slice(self.read_bytes(HASH_LENGTH as usize)?);
+            Some(hash)
+        } else {
+            None
+        };
+        match offset {
+            0 => Ok(ObjectId::NONE),
+            offset => self.object_at(offset, hash),
+        }
+    }
+    fn read_object_ids(&mut self) -> Result<Vec<ObjectId>> {
+        let mut list = Vec::new();
+        loop {
+            match self.read_object_id()? {
+                ObjectId::NONE => return Ok(list),
+                id => list.push(id),
+            }
+        }
+    }
+    fn read_full_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_varuint()?;
+        ensure_valid(len <= self.length, &"string length out of bounds")?;
+        Ok(self.read_bytes(len as usize)?.to_vec())
+    }
+    fn read_full_string(&mut self) -> Result<String> {
+        let data = self.read_full_bytes()?;
+        match String::from_utf8(data) {
+            Ok(str) => Ok(str),
+            Err(_) => corrupt(&"string is not valid UTF-8"),
+        }
+    }
+
+    fn read_metadata(&mut self) -> Result<Metadata> {
+        Ok(match self.read_varuint()? {
+            META_TAG_VARINT => Metadata::VarInt(self.read_varint()?),
+            META_TAG_VARUINT => Metadata::VarUInt(self.read_varuint()?),
+            META_TAG_OBJECTREF => Metadata::ObjectRef(self.read_object_id()?),
+            META_TAG_STRING => Metadata::String(self.read_full_string()?),
+            META_TAG_BYTES => Metadata::Bytes(self.read_full_bytes()?),
+            kind => Metadata::Unknown { kind, data: self.read_full_bytes()? },
+        })
+    }
+    fn read_metadata_table(&mut self) -> Result<MetadataMap> {
+        let mut table = MetadataMap::default();
+        loop {
+            let tag = match MetadataTag::from(self.read_u32()?) {
+                MetadataTag::EndTag => return Ok(table),
+                tag => tag,
+            };
+            let value = self.read_metadata()?;
+            table.insert(tag, value);
+        }
+    }
+
+    /// Parses the header of an object, returning it alongside the length of its data.
+    ///
+    /// The raw bytes of the header are left in `self.header`.
+    fn read_header(&mut self, id: ObjectId) -> Result<(DiarObject, u64)> {
+        let offset = self.location(id)?.offset;
+        self.stream.seek(SeekFrom::Start(self.base + offset))?;
+        self.header.clear();
+        self.header_limit = usize::MAX;
+        self.parse_header(offset)
+    }
+    fn parse_header(&mut self, offset: u64) -> Result<(DiarObject, u64)> {
+        let ty = self.read_u32()?;
+        let length = self.read_varuint()?;
+        ensure_valid(length <= offset.saturating_sub(8), &"object data out of bounds")?;
+        let fields_len = self.read_varuint()?;
+        let available = match &self.sealed {
+            Some(sealed) => sealed.get_ref().len() as u64 - sealed.position(),
+            None => (self.length - offset).saturating_sub(self.header.len() as u64),
+        };
+        ensure_valid(fields_len <= available, &"object header out of bounds")?;
+        self.header_limit = self.header.len() + fields_len as usize;
+
+        let start = self.header.len();
+        let obj = match ObjectType::try_from(ty) {
+            Ok(ty) => self.parse_fields(ty)?,
+            Err(_) => DiarObject::Unknown(ObjUnknown { object_type: ty }),
+        };
+        let read = (self.header.len() - start) as u64;
+        ensure_valid(read <= fields_len, &"object header is longer than its stated length")?;
+
+        // skip fields this version does not know, along with the contents of unknown objects
+        self.read_bytes((fields_len - read) as usize)?;
+        ensure_valid(obj.has_data() || length == 0, &"data not allowed for this object type")?;
+        Ok((obj, length))
+    }
+    fn parse_fields(&mut self, ty: ObjectType) -> Result<DiarObject> {
+        Ok(match ty {
+            ObjectType::BlobPlain => {
+                DiarObject::BlobPlain(ObjBlobPlain { filters: self.read_object_ids()? })
+            }
+            ObjectType::Directory => {
+                let flags = self.read_varuint()?;
+                ensure_valid(flags & !DIR_FLAGS_KNOWN == 0, &"unknown directory flags")?;
+                let sorted = flags & DIR_FLAG_SORTED != 0;
+                let mut entries = Vec::new();
+                loop {
+                    let data = self.read_object_id()?;
+                    if data == ObjectId::NONE {
+                        break;
+                    }
+                    let metadata = self.read_object_id()?;
+                    let name = self.read_full_string()?;
+                    entries.push(DirectoryEntry { name, data, metadata });
+                }
+                if sorted {
+                    ensure_valid(
+                        entries.windows(2).all(|x| x[0].name < x[1].name),
+                        &"directory entries are not sorted",
+                    )?;
+                }
+                DiarObject::Directory(ObjDirectory { sorted, entries })
+            }
+            ObjectType::Metadata => {
+                DiarObject::Metadata(ObjMetadata { metadata: self.read_metadata_table()? })
+            }
+            ObjectType::Archive => {
+                let root = self.read_object_id()?;
+                DiarObject::Archive(ObjArchive { root, metadata: self.read_metadata_table()? })
+            }
+            ObjectType::Root => {
+                let main = self.read_object_id()?;
+                let mut alt = BTreeMap::new();
+                loop {
+                    let id = self.read_object_id()?;
+                    if id == ObjectId::NONE {
+                        break;
+                    }
+                    alt.insert(self.read_full_string()?, id);
+                }
+                let metadata = self.read_metadata_table()?;
+                DiarObject::Root(ObjRoot { main, alt, metadata })
+            }
+            ObjectType::Sealed => {
+                DiarObject::Sealed(ObjSealed { filters: self.read_object_ids()? })
+            }
+            ObjectType::Symlink => {
+                DiarObject::Symlink(ObjSymlink { target: self.read_full_string()? })
+            }
+            ObjectType::PathIndex => {
+                let count = self.read_varuint()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push(self.read_path_index_entry()?);
+                }
+                ensure_valid(
+                    entries.windows(2).all(|x| x[0].hash < x[1].hash),
+                    &"path index is not sorted",
+                )?;
+                DiarObject::PathIndex(ObjPathIndex { entries })
+            }
+            ObjectType::ContentIndex => {
+                let count = self.read_varuint()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    let hash = self
+                        .read_bytes(CONTENT_HASH_LENGTH as usize)?
+                        .try_into()
+                        .unwrap();
+                    let len = self.read_varuint()?;
+                    let data = self.read_object_id()?;
+                    entries.push(ContentIndexEntry { hash, len, data });
+                }
+                ensure_valid(
+                    entries.windows(2).all(|x| x[0].hash < x[1].hash),
+                    &"content index is not sorted",
+                )?;
+                DiarObject::ContentIndex(ObjContentIndex { entries })
+            }
+            ObjectType::FilterZstd => {
+                DiarObject::FilterZstd(ObjFilterZstd { dict_sources: self.read_object_ids()? })
+            }
+            ObjectType::FilterXChaCha20Poly1305 => {
+                let kdf = match self.read_varuint()? {
+                    KDF_RAW => KeyDerivation::Raw,
+                    KDF_ARGON2ID => KeyDerivation::Argon2id {
+                        salt: self.read_full_bytes()?,
+                        m_cost: self.read_u32()?,
+                        t_cost: self.read_u32()?,
+                        p_cost: self.read_u32()?,
+                    },
+                    _ => return corrupt(&"unknown key derivation function"),
+                };
+                let key_check = self.read_full_bytes()?;
+                let chunk_size = self.read_varuint()?;
+                DiarObject::FilterXChaCha20Poly1305(ObjFilterXChaCha20Poly1305 {
+                    kdf,
+                    key_check,
+                    chunk_size,
+                })
+            }
+            ObjectType::ZstdPreloadList => {
+                DiarObject::ZstdPreloadList(ObjZstdPreloadList { list: self.read_object_ids()? })
+            }
+        })
+    }
+
+    /// Returns the length of an entry in a path index, which depends on whether the archive is
+    /// hashed.
+    fn path_index_entry_len(&self) -> u64 {
+        let id_len = if self.hashed { 8 + HASH_LENGTH } else { 8 };
+        PATH_HASH_LENGTH + 2 * id_len
+    }
+    fn read_path_index_entry(&mut self) -> Result<PathIndexEntry> {
+        let hash = self
+            .read_bytes(PATH_HASH_LENGTH as usize)?
+            .try_into()
+            .unwrap();
+        let data = self.read_fixed_object_id()?;
+        let metadata = self.read_fixed_object_id()?;
+        Ok(PathIndexEntry { hash, data, metadata })
+    }
+
+    /// Returns whether an object is sealed, without reading the rest of it.
+    pub fn is_sealed(&mut self, id: ObjectId) -> Result<bool> {
+        let offset = self.location(id)?.offset;
+        self.stream.seek(SeekFrom::Start(self.base + offset))?;
+        self.header.clear();
+        self.header_limit = usize::MAX;
+        Ok(ObjectType::try_from(self.read_u32()?) == Ok(ObjectType::Sealed))
+    }
+
+    /// Finds the entry for a path hash in a path index, without reading the rest of the index.
+    ///
+    /// Entries have a fixed length, so the index is binary searched where it is stored. If the
+    /// archive is hashed, the whole index is checked against its hash the first time it is
+    /// searched.
+    pub fn search_path_index(
+        &mut self,
+        id: ObjectId,
+        hash: &PathHash,
+    ) -> Result<Option<PathIndexEntry>> {
+        let offset = self.location(id)?.offset;
+        self.stream.seek(SeekFrom::Start(self.base + offset))?;
+        self.header.clear();
+        self.header_limit = usize::MAX;
+        match ObjectType::try_from(self.read_u32()?) {
+            Ok(ObjectType::PathIndex) => {}
+            _ => return corrupt(&"path index reference does not point to a path index"),
+        }
+        ensure_valid(self.read_varuint()? == 0, &"data not allowed for this object type")?;
+        let fields_len = self.read_varuint()?;
+        let fields_start = self.header.len() as u64;
+        ensure_valid(
+            fields_len <= (self.length - offset).saturating_sub(fields_start),
+            &"object header out of bounds",
+        )?;
+        let count = self.read_varuint()?;
+        let entries_start = self.header.len() as u64;
+        let entry_len = self.path_index_entry_len();
+        ensure_valid(
+            count
+                .checked_mul(entry_len)
+                .is_some_and(|x| x <= fields_start + fields_len - entries_start),
+            &"path index is longer than its stated length",
+        )?;
+
+        if self.hashed && self.verified_indexes.insert(id) {
+            if let Err(e) = self.verify_header_hash(id, fields_start + fields_len) {
+                self.verified_indexes.remove(&id);
+                return Err(e);
+            }
+        }
+
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let pos = self.base + offset + entries_start + mid * entry_len;
+            self.stream.seek(SeekFrom::Start(pos))?;
+            self.header.clear();
+            let entry_hash: PathHash = self
+                .read_bytes(PATH_HASH_LENGTH as usize)?
+                .try_into()
+                .unwrap();
+            match entry_hash.cmp(hash) {
+                cmp::Ordering::Less => lo = mid + 1,
+                cmp::Ordering::Greater => hi = mid,
+                cmp::Ordering::Equal => {
+                    self.stream.seek(SeekFrom::Start(pos))?;
+                    self.header.clear();
+                    return Ok(Some(self.read_path_index_entry()?));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks the header of an object without data against its hash, reading it in chunks.
+    fn verify_header_hash(&mut self, id: ObjectId, len: u64) -> Result<()> {
+        let loc = self.location(id)?;
+        let (offset, expected) = (loc.offset, loc.hash);
+        self.stream.seek(SeekFrom::Start(self.base + offset))?;
+        let mut hasher = blake3::Hasher::new();
+        let copied = io::copy(&mut (&mut self.stream).take(len), &mut hasher)?;
+        ensure_valid(copied == len, &"object header runs past the end of its data")?;
+        if let Some(expected) = expected {
+            ensure_valid(*hasher.finalize().as_bytes() == expected, &"object hash mismatch")?;
+        }
+        Ok(())
+    }
+
+    /// Reads an object from the archive.
+    ///
+    /// Objects without data are checked against their hash immediately. The data of blobs is
+    /// only checked when it is read with [`ObjectReader::read_data`].
+    pub fn read_object(&mut self, id: ObjectId) -> Result<DiarObject> {
+        let (obj, length) = self.read_header(id)?;
+        if length == 0 {
+            if let Some(expected) = self.location(id)?.hash {
+                let hash = blake3::hash(&self.header);
+                ensure_valid(*hash.as_bytes() == expected, &"object hash mismatch")?;
+            }
+        }
+        Ok(obj)
+    }
+
+    /// Parses the contents of a sealed object, once its data has been passed through its filters.
+    pub fn read_sealed(&mut self, data: Vec<u8>) -> Result<DiarObject> {
+        self.header.clear();
+        self.header_limit = usize::MAX;
+        self.sealed = Some(Cursor::new(data));
+        let result = self.parse_header(0);
+        self.sealed = None;
+        match result? {
+            (DiarObject::BlobPlain(_) | DiarObject::Sealed(_), _) => {
+                corrupt(&"sealed object cannot contain data")
+            }
+            (obj, _) => Ok(obj),
+        }
+    }
+
+    /// Returns the length of the data stored before an object's header.
+    pub fn data_length(&mut self, id: ObjectId) -> Result<u64> {
+        Ok(self.read_header(id)?.1)
+    }
+
+    /// Opens the raw data stored alongside an object.
+    ///
+    /// If the archive is hashed, the returned stream fails with an error once the end of the data
+    /// is reached and it does not match the object's hash.
+    pub fn read_data(&mut self, id: ObjectId) -> Result<DataReader<'_, S>> {
+        let (_, length) = self.read_header(id)?;
+        let loc = self.location(id)?;
+        let start = self.base + loc.offset - length;
+        let verify = loc.hash.map(|hash| (blake3::Hasher::new(), hash));
+        self.stream.seek(SeekFrom::Start(start))?;
+        Ok(DataReader {
+            stream: &mut self.stream,
+            header: &self.header,
+            remaining: length,
+            verify,
+        })
+    }
+}
+
+/// The raw data of an object, as returned by [`ObjectReader::read_data`].
+pub struct DataReader<'a, S> {
+    stream: &'a mut S,
+    header: &'a [u8],
+    remaining: u64,
+    verify: Option<(blake3::Hasher, ObjectHash)>,
+}
+impl<'a, S: Read> Read for DataReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if let Some((mut hasher, expected)) = self.verify.take() {
+                hasher.update(self.header);
+                if *hasher.finalize().as_bytes() != expected {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "object hash mismatch",
+                    ));
+                }
+            }
+            return Ok(0);
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let len = self
+            .stream
+            .read(&mut buf[..max])
+            .map_err(StoredDataError::wrap)?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some((hasher, _)) = &mut self.verify {
+            hasher.update(&buf[..len]);
+        }
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
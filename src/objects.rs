@@ -1,10 +1,13 @@
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     fmt::Debug,
     sync::atomic::{AtomicU64, Ordering},
 };
-use twox_hash::RandomXxh3HashBuilder64;
+
+/// The BLAKE3 hash of an object's data and header, used when an archive is written with
+/// object hashes enabled.
+pub type ObjectHash = [u8; 32];
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub struct ObjectId(u64);
@@ -17,6 +20,50 @@ impl ObjectId {
     }
 }
 
+/// The format version and features an archive was written with, stored after the magic
+/// number at the start of the archive.
+///
+/// Readers refuse archives with a newer version, or with required features they do not know.
+/// Optional features only describe extra information that readers may ignore.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct FormatInfo {
+    pub version: u64,
+    pub required_features: u64,
+    pub optional_features: u64,
+}
+impl FormatInfo {
+    /// The newest format version this library can read and write.
+    pub const CURRENT_VERSION: u64 = 2;
+    /// The oldest format version this library can read.
+    pub const MIN_VERSION: u64 = 2;
+
+    /// Some objects are encrypted, or stored sealed.
+    pub const REQUIRED_ENCRYPTION: u64 = 1 << 0;
+    /// The archive contains symbolic links.
+    pub const REQUIRED_SYMLINKS: u64 = 1 << 1;
+    /// Every required feature this library supports.
+    pub const REQUIRED_KNOWN: u64 = Self::REQUIRED_ENCRYPTION | Self::REQUIRED_SYMLINKS;
+
+    /// The archive contains a path index.
+    pub const OPTIONAL_PATH_INDEX: u64 = 1 << 0;
+    /// The archive contains an embedded signature.
+    pub const OPTIONAL_SIGNATURE: u64 = 1 << 1;
+    /// Some directory entries have metadata.
+    pub const OPTIONAL_ENTRY_METADATA: u64 = 1 << 2;
+    /// References to objects include their hashes. This is also indicated by the trailer, and is
+    /// recorded here so that archives without one can be salvaged.
+    pub const OPTIONAL_OBJECT_HASHES: u64 = 1 << 3;
+    /// Every object is followed by a sync marker, so that objects can be found by scanning the
+    /// archive forward from its start.
+    pub const OPTIONAL_SYNC_MARKERS: u64 = 1 << 4;
+    /// The root object lists the snapshots stored in the archive by name.
+    pub const OPTIONAL_SNAPSHOTS: u64 = 1 << 5;
+
+    pub(crate) fn new(required_features: u64, optional_features: u64) -> FormatInfo {
+        FormatInfo { version: Self::CURRENT_VERSION, required_features, optional_features }
+    }
+}
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 #[derive(TryFromPrimitive, IntoPrimitive)]
 #[repr(u32)]
@@ -26,26 +73,66 @@ pub enum ObjectType {
     Metadata = 2,
     Archive = 3,
     Root = 4,
+    Sealed = 5,
+    Symlink = 6,
+    PathIndex = 7,
+    ContentIndex = 8,
 
     FilterZstd = 0x20,
+    FilterXChaCha20Poly1305 = 0x21,
 
     ZstdPreloadList = 0x40,
 }
 
+/// The tag identifying an entry in a metadata table.
+///
+/// Tags this version does not know are kept as [`MetadataTag::Unknown`], so they can be
+/// reported rather than rejected.
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
-#[derive(TryFromPrimitive, IntoPrimitive)]
+#[derive(FromPrimitive, IntoPrimitive)]
 #[repr(u32)]
 pub enum MetadataTag {
+    UnixMode = 0x01,
+    UnixUid = 0x02,
+    UnixGid = 0x03,
+    UserName = 0x04,
+    GroupName = 0x05,
+    ModifiedTime = 0x06,
+
     ZstdPreloadList = 0x40,
     EntryArchive = 0x41,
+    Ed25519Signature = 0x42,
+    PathIndex = 0x43,
+    /// On the archive of a checkpoint, the path index of the entries written since the previous
+    /// checkpoint.
+    Checkpoint = 0x44,
+    /// On the archive of a checkpoint, the archive of the checkpoint before it.
+    PreviousCheckpoint = 0x45,
+    /// On an archive, the compression filter used for files, so that writers adding to the
+    /// archive can reuse its dictionary.
+    CompressionFilter = 0x46,
+    /// On an archive, the encryption filter used for files.
+    EncryptionFilter = 0x47,
+    /// On the archive of a snapshot, when it was created, in seconds since the Unix epoch.
+    CreationTime = 0x48,
+    /// On the archive of a snapshot, an index of its files by a hash of their contents, so that
+    /// later snapshots can share unchanged files.
+    ContentIndex = 0x49,
 
     EndTag = 0x7F,
+
+    #[num_enum(catch_all)]
+    Unknown(u32),
 }
 
 pub const META_TAG_VARINT: u64 = 0;
 pub const META_TAG_VARUINT: u64 = 1;
 pub const META_TAG_OBJECTREF: u64 = 2;
 pub const META_TAG_STRING: u64 = 3;
+pub const META_TAG_BYTES: u64 = 4;
+/// Value types after this one are always stored as a length followed by that many bytes, so
+/// readers can skip types they do not know.
+pub const META_TAG_LAST_KNOWN: u64 = META_TAG_BYTES;
 
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 #[repr(u32)]
@@ -54,6 +141,12 @@ pub enum Metadata {
     VarUInt(u64),
     ObjectRef(ObjectId),
     String(String),
+    Bytes(Vec<u8>),
+    /// A value of a type this version does not know, with its raw contents.
+    Unknown {
+        kind: u64,
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -61,8 +154,13 @@ pub struct ObjBlobPlain {
     pub filters: Vec<ObjectId>,
 }
 
+pub const DIR_FLAG_SORTED: u64 = 1 << 0;
+pub const DIR_FLAGS_KNOWN: u64 = DIR_FLAG_SORTED;
+
 #[derive(Clone, Debug)]
 pub struct ObjDirectory {
+    /// Whether the entries are sorted by the bytes of their names, with no duplicates.
+    pub sorted: bool,
     pub entries: Vec<DirectoryEntry>,
 }
 #[derive(Clone, Debug)]
@@ -72,7 +170,7 @@ pub struct DirectoryEntry {
     pub metadata: ObjectId,
 }
 
-pub type MetadataMap = HashMap<MetadataTag, Metadata, RandomXxh3HashBuilder64>;
+pub type MetadataMap = BTreeMap<MetadataTag, Metadata>;
 
 #[derive(Clone, Debug)]
 pub struct ObjMetadata {
@@ -88,20 +186,99 @@ pub struct ObjArchive {
 #[derive(Clone, Debug)]
 pub struct ObjRoot {
     pub main: ObjectId,
-    pub alt: HashMap<String, ObjectId, RandomXxh3HashBuilder64>,
+    pub alt: BTreeMap<String, ObjectId>,
     pub metadata: MetadataMap,
 }
 
+#[derive(Clone, Debug)]
+pub struct ObjSymlink {
+    pub target: String,
+}
+
+/// A truncated BLAKE3 hash of the full `/`-separated path of an entry.
+pub type PathHash = [u8; PATH_HASH_LENGTH as usize];
+pub const PATH_HASH_LENGTH: u64 = 16;
+
+pub fn path_hash(path: &str) -> PathHash {
+    blake3::hash(path.as_bytes()).as_bytes()[..PATH_HASH_LENGTH as usize]
+        .try_into()
+        .unwrap()
+}
+
+#[derive(Clone, Debug)]
+pub struct PathIndexEntry {
+    pub hash: PathHash,
+    pub data: ObjectId,
+    pub metadata: ObjectId,
+}
+
+/// A list of every entry in an archive, sorted by the hash of its path.
+///
+/// Entries are stored with a fixed length, so that readers can binary search the index without
+/// loading it.
+#[derive(Clone, Debug)]
+pub struct ObjPathIndex {
+    pub entries: Vec<PathIndexEntry>,
+}
+
+/// A truncated BLAKE3 hash of the contents of a file.
+pub type ContentHash = [u8; CONTENT_HASH_LENGTH as usize];
+pub const CONTENT_HASH_LENGTH: u64 = 16;
+
+#[derive(Clone, Debug)]
+pub struct ContentIndexEntry {
+    pub hash: ContentHash,
+    /// The length of the file's contents.
+    pub len: u64,
+    pub data: ObjectId,
+}
+
+/// A list of the files of a snapshot, sorted by the hash of their contents.
+#[derive(Clone, Debug)]
+pub struct ObjContentIndex {
+    pub entries: Vec<ContentIndexEntry>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ObjFilterZstd {
     pub dict_sources: Vec<ObjectId>,
 }
 
+pub const KDF_RAW: u64 = 0;
+pub const KDF_ARGON2ID: u64 = 1;
+
+#[derive(Clone, Debug)]
+pub enum KeyDerivation {
+    Raw,
+    Argon2id { salt: Vec<u8>, m_cost: u32, t_cost: u32, p_cost: u32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjFilterXChaCha20Poly1305 {
+    pub kdf: KeyDerivation,
+    pub key_check: Vec<u8>,
+    pub chunk_size: u64,
+}
+
+/// An object whose header is stored in its data, passed through a list of filters.
+#[derive(Clone, Debug)]
+pub struct ObjSealed {
+    pub filters: Vec<ObjectId>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ObjZstdPreloadList {
     pub list: Vec<ObjectId>,
 }
 
+/// An object of a type this version does not know.
+///
+/// Every object header records its length, so these can be skipped over.
+#[derive(Clone, Debug)]
+pub struct ObjUnknown {
+    pub object_type: u32,
+}
+
 #[derive(Clone, Debug)]
 pub enum DiarObject {
     BlobPlain(ObjBlobPlain),
@@ -109,8 +286,87 @@ pub enum DiarObject {
     Metadata(ObjMetadata),
     Archive(ObjArchive),
     Root(ObjRoot),
+    Sealed(ObjSealed),
+    Symlink(ObjSymlink),
+    PathIndex(ObjPathIndex),
+    ContentIndex(ObjContentIndex),
 
     FilterZstd(ObjFilterZstd),
+    FilterXChaCha20Poly1305(ObjFilterXChaCha20Poly1305),
 
     ZstdPreloadList(ObjZstdPreloadList),
+
+    Unknown(ObjUnknown),
+}
+impl DiarObject {
+    /// Returns the raw type of the object, as stored in its header.
+    pub fn object_type(&self) -> u32 {
+        let ty = match self {
+            DiarObject::BlobPlain(_) => ObjectType::BlobPlain,
+            DiarObject::Directory(_) => ObjectType::Directory,
+            DiarObject::Metadata(_) => ObjectType::Metadata,
+            DiarObject::Archive(_) => ObjectType::Archive,
+            DiarObject::Root(_) => ObjectType::Root,
+            DiarObject::Sealed(_) => ObjectType::Sealed,
+            DiarObject::Symlink(_) => ObjectType::Symlink,
+            DiarObject::PathIndex(_) => ObjectType::PathIndex,
+            DiarObject::ContentIndex(_) => ObjectType::ContentIndex,
+            DiarObject::FilterZstd(_) => ObjectType::FilterZstd,
+            DiarObject::FilterXChaCha20Poly1305(_) => ObjectType::FilterXChaCha20Poly1305,
+            DiarObject::ZstdPreloadList(_) => ObjectType::ZstdPreloadList,
+            DiarObject::Unknown(obj) => return obj.object_type,
+        };
+        ty.into()
+    }
+
+    /// Returns every object this object refers to, in the order they are stored in its header.
+    pub fn references(&self) -> Vec<ObjectId> {
+        fn metadata_refs(out: &mut Vec<ObjectId>, metadata: &MetadataMap) {
+            out.extend(metadata.values().filter_map(|x| match x {
+                Metadata::ObjectRef(id) => Some(*id),
+                _ => None,
+            }));
+        }
+
+        let mut out = Vec::new();
+        match self {
+            DiarObject::BlobPlain(obj) => out.extend(&obj.filters),
+            DiarObject::Directory(obj) => {
+                for entry in &obj.entries {
+                    out.push(entry.data);
+                    out.push(entry.metadata);
+                }
+            }
+            DiarObject::Metadata(obj) => metadata_refs(&mut out, &obj.metadata),
+            DiarObject::Archive(obj) => {
+                out.push(obj.root);
+                metadata_refs(&mut out, &obj.metadata);
+            }
+            DiarObject::Root(obj) => {
+                out.push(obj.main);
+                out.extend(obj.alt.values());
+                metadata_refs(&mut out, &obj.metadata);
+            }
+            DiarObject::Sealed(obj) => out.extend(&obj.filters),
+            DiarObject::PathIndex(obj) => {
+                for entry in &obj.entries {
+                    out.push(entry.data);
+                    out.push(entry.metadata);
+                }
+            }
+            DiarObject::ContentIndex(obj) => out.extend(obj.entries.iter().map(|x| x.data)),
+            DiarObject::FilterZstd(obj) => out.extend(&obj.dict_sources),
+            DiarObject::ZstdPreloadList(obj) => out.extend(&obj.list),
+            DiarObject::Symlink(_)
+            | DiarObject::FilterXChaCha20Poly1305(_)
+            | DiarObject::Unknown(_) => {}
+        }
+        out.retain(|x| *x != ObjectId::NONE);
+        out
+    }
+
+    /// Returns whether objects of this type may have data stored before their header.
+    pub fn has_data(&self) -> bool {
+        matches!(self, DiarObject::BlobPlain(_) | DiarObject::Sealed(_) | DiarObject::Unknown(_))
+    }
 }
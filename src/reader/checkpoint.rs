@@ -0,0 +1,114 @@
+use crate::{
+    errors::*,
+    object_io::ObjectReader,
+    objects::*,
+    reader::{
+        reuse::{object_ref, ArchiveFilters, ObjectLocation},
+        ArchiveReader, ReaderConfiguration,
+    },
+};
+use std::{
+    collections::HashSet,
+    io::{Read, Seek},
+};
+
+/// An entry written before the last checkpoint of a partial archive.
+pub(crate) struct CheckpointEntry {
+    pub hash: PathHash,
+    pub data: ObjectLocation,
+    pub metadata: Option<ObjectLocation>,
+}
+
+/// The state of a partial archive at its last checkpoint.
+pub(crate) struct Checkpoint {
+    /// The length of the archive up to the end of the checkpoint.
+    pub end: u64,
+    /// The format header the archive was started with.
+    pub format: FormatInfo,
+    /// The archive object of the checkpoint.
+    pub archive: ObjectLocation,
+    pub filters: ArchiveFilters,
+    pub entries: Vec<CheckpointEntry>,
+}
+
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Finds the last checkpoint in an archive that was never finished, returning `None` if it
+    /// has none, or was finished after it.
+    ///
+    /// Like [`ArchiveReader::salvage`], this finds objects by scanning the archive, which requires
+    /// it to have been written with sync markers.
+    pub(crate) fn find_checkpoint(
+        stream: S,
+        cfg: &ReaderConfiguration,
+    ) -> Result<Option<Checkpoint>> {
+        let mut objects = ObjectReader::open_unterminated(stream)?;
+        let end = objects.length();
+        let (offsets, _) = objects.scan(end)?;
+        let mut reader = ArchiveReader::from_objects(objects, cfg);
+
+        // checkpoints are written last, so the first root found from the end is the latest
+        for &offset in offsets.iter().rev() {
+            let id = reader.objects.object_at_offset(offset)?;
+            let DiarObject::Root(root) = reader.read_object(id)? else {
+                continue;
+            };
+            let DiarObject::Archive(archive) = reader.read_object(root.main)? else {
+                return corrupt(&"root object does not point to an archive");
+            };
+            if !archive.metadata.contains_key(&MetadataTag::Checkpoint) {
+                return Ok(None);
+            }
+            let checkpoint_end = reader.objects.object_extent(id)?.end;
+            return reader
+                .read_checkpoint(root.main, archive, checkpoint_end)
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    fn read_checkpoint(
+        &mut self,
+        archive_id: ObjectId,
+        archive: ObjArchive,
+        end: u64,
+    ) -> Result<Checkpoint> {
+        let Some(filters) = self.read_filters(&archive.metadata)? else {
+            return corrupt(&"checkpoint has no compression filter");
+        };
+        let mut checkpoint = Checkpoint {
+            end,
+            format: self.objects.format(),
+            archive: self.location(archive_id)?,
+            filters,
+            entries: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        let mut current = Some((archive_id, archive));
+        while let Some((id, archive)) = current {
+            ensure_valid(seen.insert(id), &"checkpoint is its own predecessor")?;
+            if let Some(index) = object_ref(&archive.metadata, MetadataTag::Checkpoint)? {
+                let DiarObject::PathIndex(index) = self.read_object(index)? else {
+                    return corrupt(&"checkpoint does not point to a path index");
+                };
+                for entry in index.entries {
+                    let metadata = match entry.metadata {
+                        ObjectId::NONE => None,
+                        id => Some(self.location(id)?),
+                    };
+                    let data = self.location(entry.data)?;
+                    checkpoint
+                        .entries
+                        .push(CheckpointEntry { hash: entry.hash, data, metadata });
+                }
+            }
+            current = match object_ref(&archive.metadata, MetadataTag::PreviousCheckpoint)? {
+                None => None,
+                Some(id) => match self.read_object(id)? {
+                    DiarObject::Archive(archive) => Some((id, archive)),
+                    _ => return corrupt(&"previous checkpoint is not an archive"),
+                },
+            };
+        }
+        Ok(checkpoint)
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{
+    errors::*,
+    metadata::EntryMetadata,
+    objects::*,
+    reader::{ArchiveReader, Traversal},
+    writer::ManifestEntryType,
+};
+use serde::Serialize;
+use std::io::{Read, Seek, Write};
+
+/// The format used by [`ArchiveReader::export_manifest`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ManifestFormat {
+    /// One JSON object per line, with the same field names as [`EntryInfo`].
+    JsonLines,
+    /// Comma-separated values, with a header row.
+    Csv,
+}
+
+/// A description of an entry in an archive.
+#[derive(Clone, Debug, Serialize)]
+#[non_exhaustive]
+pub struct EntryInfo {
+    /// The `/`-separated path of the entry in the archive.
+    pub path: String,
+    /// The type of the entry.
+    #[serde(rename = "type")]
+    pub kind: ManifestEntryType,
+    /// The uncompressed size of a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The size of a file as it is stored in the archive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    /// The target of a symlink.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// The raw object type of an entry this version does not understand.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_type: Option<u32>,
+    /// The BLAKE3 hash of the uncompressed contents of a file, in hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+    /// The hash of the entry's object in the archive, in hex, if the archive is hashed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_hash: Option<String>,
+    /// The metadata stored for the entry.
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
+}
+
+const CSV_HEADER: &str = "path,type,size,compressed_size,target,object_type,blake3,object_hash,\
+                          mode,uid,gid,user,group,mtime";
+
+fn csv_field(out: &mut String, value: Option<impl ToString>) {
+    if !out.is_empty() {
+        out.push(',');
+    }
+    if let Some(value) = value {
+        let value = value.to_string();
+        if value.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&value.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&value);
+        }
+    }
+}
+
+impl EntryInfo {
+    fn to_csv(&self) -> String {
+        let kind = match self.kind {
+            ManifestEntryType::File => "file",
+            ManifestEntryType::Dir => "dir",
+            ManifestEntryType::Symlink => "symlink",
+            ManifestEntryType::Unknown => "unknown",
+        };
+        let mut out = String::new();
+        csv_field(&mut out, Some(&self.path));
+        csv_field(&mut out, Some(kind));
+        csv_field(&mut out, self.size);
+        csv_field(&mut out, self.compressed_size);
+        csv_field(&mut out, self.target.as_ref());
+        csv_field(&mut out, self.object_type);
+        csv_field(&mut out, self.blake3.as_ref());
+        csv_field(&mut out, self.object_hash.as_ref());
+        csv_field(&mut out, self.metadata.mode.map(|x| format!("{x:o}")));
+        csv_field(&mut out, self.metadata.uid);
+        csv_field(&mut out, self.metadata.gid);
+        csv_field(&mut out, self.metadata.user.as_ref());
+        csv_field(&mut out, self.metadata.group.as_ref());
+        csv_field(&mut out, self.metadata.mtime);
+        out
+    }
+}
+
+impl<S: Read + Seek> ArchiveReader<S> {
+    fn entry_info(
+        &mut self,
+        path: String,
+        data: ObjectId,
+        metadata: ObjectId,
+    ) -> Result<EntryInfo> {
+        let object_hash = self
+            .objects
+            .object_hash(data)?
+            .map(|x| blake3::Hash::from(x).to_hex().to_string());
+        let metadata = self.read_metadata(metadata).map_err(Error::in_archive)?;
+        let mut info = EntryInfo {
+            path,
+            kind: ManifestEntryType::Dir,
+            size: None,
+            compressed_size: None,
+            target: None,
+            object_type: None,
+            blake3: None,
+            object_hash,
+            metadata,
+        };
+        match self.read_object(data)? {
+            DiarObject::Directory(_) => {}
+            DiarObject::BlobPlain(_) => {
+                let mut hasher = blake3::Hasher::new();
+                let size = self
+                    .copy_file(data, &mut hasher)
+                    .map_err(|e| e.with_path(&info.path))?;
+                info.kind = ManifestEntryType::File;
+                info.size = Some(size);
+                info.compressed_size = Some(self.objects.data_length(data)?);
+                info.blake3 = Some(hasher.finalize().to_hex().to_string());
+            }
+            DiarObject::Symlink(link) => {
+                info.kind = ManifestEntryType::Symlink;
+                info.target = Some(link.target);
+            }
+            DiarObject::Unknown(obj) => {
+                info.kind = ManifestEntryType::Unknown;
+                info.object_type = Some(obj.object_type);
+            }
+            _ => return corrupt(&"directory entry is not a file, directory or symlink"),
+        }
+        Ok(info)
+    }
+
+    fn visit_entries(
+        &mut self,
+        dir: ObjectId,
+        prefix: &str,
+        walk: &mut Traversal,
+        f: &mut impl FnMut(EntryInfo) -> Result<()>,
+    ) -> Result<()> {
+        walk.enter(dir)?;
+        let mut entries = self.read_dir(dir)?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        for entry in entries {
+            walk.visit_entry()?;
+            let path = if prefix.is_empty() {
+                entry.name
+            } else {
+                format!("{prefix}/{}", entry.name)
+            };
+            let info = self.entry_info(path.clone(), entry.data, entry.metadata)?;
+            let is_dir = info.kind == ManifestEntryType::Dir;
+            f(info)?;
+            if is_dir {
+                self.visit_entries(entry.data, &path, walk, f)?;
+            }
+        }
+        walk.leave();
+        Ok(())
+    }
+
+    /// Lists every entry in the archive, in sorted order.
+    ///
+    /// This decompresses every file to find its size and hash.
+    pub fn entries(&mut self) -> Result<Vec<EntryInfo>> {
+        let mut entries = Vec::new();
+        self.visit_entries(self.root_dir, "", &mut self.traversal(), &mut |info| {
+            entries.push(info);
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    /// Writes a description of every entry in the archive, in sorted order.
+    ///
+    /// This decompresses every file to find its size and hash.
+    pub fn export_manifest(&mut self, out: &mut impl Write, format: ManifestFormat) -> Result<()> {
+        if format == ManifestFormat::Csv {
+            writeln!(out, "{CSV_HEADER}")?;
+        }
+        self.visit_entries(self.root_dir, "", &mut self.traversal(), &mut |info| {
+            match format {
+                ManifestFormat::JsonLines => {
+                    serde_json::to_writer(&mut *out, &info)?;
+                    writeln!(out)?;
+                }
+                ManifestFormat::Csv => writeln!(out, "{}", info.to_csv())?,
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+}
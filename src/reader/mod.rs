@@ -1 +1,649 @@
+use crate::{
+    encryption::{Cipher, EncryptionKey},
+    errors::*,
+    metadata::EntryMetadata,
+    object_io::ObjectReader,
+    objects::*,
+    signature::{verify_embedded, SignatureStatus, VerifyingKey},
+};
+use derive_setters::Setters;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io,
+    io::{BufReader, Read, Seek, Write},
+    path::Path,
+    sync::Arc,
+};
+use twox_hash::RandomXxh3HashBuilder64;
+use zstd::Decoder;
 
+mod checkpoint;
+mod manifest;
+mod reuse;
+mod salvage;
+mod snapshot;
+mod verify;
+
+pub use manifest::{EntryInfo, ManifestFormat};
+pub(crate) use reuse::{object_ref, ArchiveFilters, ObjectLocation};
+pub use salvage::SalvageReport;
+pub use snapshot::SnapshotInfo;
+pub use verify::{VerifyIssue, VerifyReport};
+
+#[derive(Clone, Debug, Default, Setters)]
+#[non_exhaustive]
+pub struct ReaderConfiguration {
+    /// If set, archives without a valid embedded signature from this key are refused.
+    #[setters(strip_option)]
+    pub require_signature: Option<VerifyingKey>,
+    /// The key used to decrypt encrypted archives.
+    #[setters(strip_option)]
+    pub decryption_key: Option<EncryptionKey>,
+    /// Limits on the resources reading the archive may use.
+    pub limits: ReaderLimits,
+}
+
+/// Limits on the resources used while reading an archive, for archives from untrusted sources.
+///
+/// Exceeding a limit fails with [`ErrorKind::LimitExceeded`]. Limits set to `None` are not
+/// enforced.
+#[derive(Clone, Debug, Setters)]
+#[non_exhaustive]
+pub struct ReaderLimits {
+    /// The largest decompressed size of a single file or directory listing. Defaults to 64 GiB.
+    #[setters(strip_option)]
+    pub max_entry_size: Option<u64>,
+    /// The most data the reader may decompress in total. Defaults to 1 TiB.
+    #[setters(strip_option)]
+    pub max_total_size: Option<u64>,
+    /// The most entries a single extraction or listing may visit. Defaults to 10 million.
+    #[setters(strip_option)]
+    pub max_entries: Option<u64>,
+    /// The deepest directories may be nested. Defaults to 1024.
+    pub max_depth: usize,
+    /// The base 2 logarithm of the largest zstd window allowed, which bounds the memory used to
+    /// decompress each file. Defaults to 30, or 1 GiB.
+    pub max_window_log: u32,
+}
+impl Default for ReaderLimits {
+    fn default() -> Self {
+        ReaderLimits {
+            max_entry_size: Some(64 << 30),
+            max_total_size: Some(1 << 40),
+            max_entries: Some(10_000_000),
+            max_depth: 1024,
+            max_window_log: 30,
+        }
+    }
+}
+
+enum Filter {
+    Zstd(Arc<Vec<u8>>),
+    Decrypt(Arc<Cipher>),
+}
+
+/// An entry in a directory of an archive.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub data: ObjectId,
+    pub metadata: ObjectId,
+}
+
+/// The most directories [`ArchiveReader::lookup`] keeps parsed at once.
+const MAX_CACHED_DIRS: usize = 1024;
+
+/// Reads the contents of a diar archive.
+pub struct ArchiveReader<S> {
+    objects: ObjectReader<S>,
+    root: ObjRoot,
+    root_dir: ObjectId,
+    path_index: Option<ObjectId>,
+    /// The entries of the path index, if it is sealed and so cannot be searched where it is
+    /// stored.
+    unsealed_index: Option<Arc<Vec<PathIndexEntry>>>,
+    decryption_key: Option<EncryptionKey>,
+    limits: ReaderLimits,
+    decompressed: u64,
+    dicts: HashMap<ObjectId, Arc<Vec<u8>>, RandomXxh3HashBuilder64>,
+    dicts_loading: HashSet<ObjectId>,
+    ciphers: HashMap<ObjectId, Arc<Cipher>, RandomXxh3HashBuilder64>,
+    /// Directories parsed by [`ArchiveReader::lookup`], so that lookups sharing a parent do not
+    /// read it again.
+    dirs: HashMap<ObjectId, Arc<ObjDirectory>, RandomXxh3HashBuilder64>,
+}
+impl ArchiveReader<BufReader<File>> {
+    /// Opens an archive file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_config(path, &ReaderConfiguration::default())
+    }
+
+    /// Opens an archive file with the given configuration.
+    pub fn open_with_config(path: impl AsRef<Path>, cfg: &ReaderConfiguration) -> Result<Self> {
+        ArchiveReader::new_with_config(BufReader::new(File::open(path)?), cfg)
+    }
+}
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Opens an archive that ends at the end of the given stream.
+    pub fn new(stream: S) -> Result<Self> {
+        Self::new_with_config(stream, &ReaderConfiguration::default())
+    }
+
+    /// Opens an archive that ends at the end of the given stream with the given configuration.
+    pub fn new_with_config(stream: S, cfg: &ReaderConfiguration) -> Result<Self> {
+        let mut objects = ObjectReader::open(stream)?;
+        let root = match objects.read_object(objects.root())? {
+            DiarObject::Root(root) => root,
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ => return corrupt(&"trailer does not point to a root object"),
+        };
+        let archive = match objects.read_object(root.main)? {
+            DiarObject::Archive(archive) => archive,
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ => return corrupt(&"root object does not point to an archive"),
+        };
+        let mut reader = ArchiveReader::from_objects(objects, cfg);
+        reader.root = root;
+        reader.set_archive(&archive)?;
+
+        if let Some(key) = &cfg.require_signature {
+            match reader.verify_signature(key)? {
+                SignatureStatus::Valid => {}
+                SignatureStatus::Unsigned => return Error::signature_missing(),
+                SignatureStatus::Invalid => return Error::signature_invalid(),
+            }
+        }
+
+        Ok(reader)
+    }
+
+    fn from_objects(objects: ObjectReader<S>, cfg: &ReaderConfiguration) -> Self {
+        ArchiveReader {
+            objects,
+            root: ObjRoot {
+                main: ObjectId::NONE,
+                alt: Default::default(),
+                metadata: Default::default(),
+            },
+            root_dir: ObjectId::NONE,
+            path_index: None,
+            unsealed_index: None,
+            decryption_key: cfg.decryption_key.clone(),
+            limits: cfg.limits.clone(),
+            decompressed: 0,
+            dicts: Default::default(),
+            dicts_loading: Default::default(),
+            ciphers: Default::default(),
+            dirs: Default::default(),
+        }
+    }
+
+    /// Makes the root directory and path index of an archive object the ones used for lookups
+    /// and extraction.
+    fn set_archive(&mut self, archive: &ObjArchive) -> Result<()> {
+        let path_index = match archive.metadata.get(&MetadataTag::PathIndex) {
+            None => None,
+            Some(Metadata::ObjectRef(id)) => Some(*id),
+            Some(_) => return corrupt(&"path index reference has the wrong type"),
+        };
+        self.root_dir = archive.root;
+        self.path_index = path_index;
+        self.unsealed_index = None;
+        Ok(())
+    }
+
+    /// Returns the format version and features the archive was written with.
+    pub fn format(&self) -> FormatInfo {
+        self.objects.format()
+    }
+
+    /// Returns whether the archive's contents are covered by object hashes.
+    pub fn is_hashed(&self) -> bool {
+        self.objects.is_hashed()
+    }
+
+    /// Returns the root hash stored in the archive's trailer, if it is hashed.
+    pub fn root_hash(&self) -> Result<Option<ObjectHash>> {
+        self.objects.object_hash(self.objects.root())
+    }
+
+    /// Checks the signature embedded in the archive against the given key.
+    pub fn verify_signature(&self, key: &VerifyingKey) -> Result<SignatureStatus> {
+        let Some(main) = self.objects.object_hash(self.root.main)? else {
+            return Ok(SignatureStatus::Unsigned);
+        };
+        let mut alt = Vec::new();
+        for (name, id) in &self.root.alt {
+            match self.objects.object_hash(*id)? {
+                Some(hash) => alt.push((name.as_str(), hash)),
+                None => return corrupt(&"archive object is missing its hash"),
+            }
+        }
+        let signature = self.root.metadata.get(&MetadataTag::Ed25519Signature);
+        Ok(verify_embedded(key, signature, &main, &alt))
+    }
+
+    /// Returns the root directory of the archive.
+    pub fn root_dir(&self) -> ObjectId {
+        self.root_dir
+    }
+
+    /// Reads an object, decoding it first if it is sealed.
+    pub(crate) fn read_object(&mut self, id: ObjectId) -> Result<DiarObject> {
+        match self.objects.read_object(id)? {
+            DiarObject::Sealed(sealed) => self.unseal(id, &sealed),
+            obj => Ok(obj),
+        }
+    }
+    fn unseal(&mut self, id: ObjectId, sealed: &ObjSealed) -> Result<DiarObject> {
+        let mut data = Vec::new();
+        let mut stream = self.open_data(id, &sealed.filters)?;
+        stream
+            .read_to_end(&mut data)
+            .map_err(Error::decompression_failed)?;
+        drop(stream);
+        self.objects.read_sealed(data)
+    }
+
+    /// Returns whether the given object is a directory.
+    pub fn is_dir(&mut self, id: ObjectId) -> Result<bool> {
+        Ok(matches!(self.read_object(id)?, DiarObject::Directory(_)))
+    }
+
+    /// Lists the entries of a directory.
+    pub fn read_dir(&mut self, id: ObjectId) -> Result<Vec<DirEntry>> {
+        match self.read_object(id)? {
+            DiarObject::Directory(dir) => Ok(dir
+                .entries
+                .into_iter()
+                .map(|x| DirEntry { name: x.name, data: x.data, metadata: x.metadata })
+                .collect()),
+            _ => Error::wrong_object_type("object is not a directory"),
+        }
+    }
+
+    /// Returns whether the given object is a symbolic link.
+    pub fn is_symlink(&mut self, id: ObjectId) -> Result<bool> {
+        Ok(matches!(self.read_object(id)?, DiarObject::Symlink(_)))
+    }
+
+    /// Returns the raw type of an object this version does not understand, or `None` if the
+    /// object is of a known type.
+    pub fn unknown_type(&mut self, id: ObjectId) -> Result<Option<u32>> {
+        match self.read_object(id)? {
+            DiarObject::Unknown(obj) => Ok(Some(obj.object_type)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the target of a symbolic link.
+    pub fn read_link(&mut self, id: ObjectId) -> Result<String> {
+        match self.read_object(id)? {
+            DiarObject::Symlink(link) => Ok(link.target),
+            _ => Error::wrong_object_type("object is not a symbolic link"),
+        }
+    }
+
+    /// Reads the metadata of a directory entry.
+    pub fn read_metadata(&mut self, id: ObjectId) -> Result<EntryMetadata> {
+        if id == ObjectId::NONE {
+            return Ok(EntryMetadata::default());
+        }
+        match self.read_object(id)? {
+            DiarObject::Metadata(metadata) => EntryMetadata::from_map(&metadata.metadata),
+            _ => Error::wrong_object_type("object is not metadata"),
+        }
+    }
+
+    /// Returns whether the archive contains an index of every path in it.
+    pub fn has_path_index(&self) -> bool {
+        self.path_index.is_some()
+    }
+
+    /// Finds the object at a `/`-separated path relative to the root directory.
+    ///
+    /// If the archive has a path index, it is searched in place instead of reading every
+    /// directory along the path.
+    pub fn lookup(&mut self, path: &str) -> Result<Option<ObjectId>> {
+        if let Some(index) = self.path_index {
+            let path = path
+                .split('/')
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>();
+            if path.is_empty() {
+                return Ok(Some(self.root_dir));
+            }
+            let hash = path_hash(&path.join("/"));
+            if self.unsealed_index.is_none() && self.objects.is_sealed(index)? {
+                let DiarObject::PathIndex(unsealed) = self.read_object(index)? else {
+                    return corrupt(&"path index reference does not point to a path index");
+                };
+                self.unsealed_index = Some(Arc::new(unsealed.entries));
+            }
+            let entry = match &self.unsealed_index {
+                Some(entries) => match entries.binary_search_by(|x| x.hash.cmp(&hash)) {
+                    Ok(idx) => Some(entries[idx].clone()),
+                    Err(_) => None,
+                },
+                None => self.objects.search_path_index(index, &hash)?,
+            };
+            return Ok(entry.map(|x| x.data));
+        }
+
+        let mut current = self.root_dir;
+        for component in path.split('/').filter(|x| !x.is_empty()) {
+            let Some(dir) = self.load_dir(current)? else {
+                return Ok(None);
+            };
+            let entry = if dir.sorted {
+                let idx = dir
+                    .entries
+                    .binary_search_by(|x| x.name.as_str().cmp(component));
+                idx.ok().map(|idx| &dir.entries[idx])
+            } else {
+                dir.entries.iter().find(|x| x.name == component)
+            };
+            match entry {
+                Some(entry) => current = entry.data,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
+    /// Reads a directory for [`ArchiveReader::lookup`], returning `None` if the object is not a
+    /// directory.
+    fn load_dir(&mut self, id: ObjectId) -> Result<Option<Arc<ObjDirectory>>> {
+        if let Some(dir) = self.dirs.get(&id) {
+            return Ok(Some(dir.clone()));
+        }
+        let DiarObject::Directory(dir) = self.read_object(id)? else {
+            return Ok(None);
+        };
+        if self.dirs.len() >= MAX_CACHED_DIRS {
+            self.dirs.clear();
+        }
+        let dir = Arc::new(dir);
+        self.dirs.insert(id, dir.clone());
+        Ok(Some(dir))
+    }
+
+    pub(crate) fn load_dict(
+        &mut self,
+        filter: ObjectId,
+        sources: &[ObjectId],
+    ) -> Result<Arc<Vec<u8>>> {
+        if let Some(dict) = self.dicts.get(&filter) {
+            return Ok(dict.clone());
+        }
+        ensure_valid(self.dicts_loading.insert(filter), &"dictionary depends on itself")?;
+        let mut dict = Vec::new();
+        let result = sources.iter().try_for_each(|x| {
+            self.copy_file(*x, &mut dict)
+                .map_err(Error::in_archive)
+                .map(|_| ())
+        });
+        self.dicts_loading.remove(&filter);
+        result?;
+        let dict = Arc::new(dict);
+        self.dicts.insert(filter, dict.clone());
+        Ok(dict)
+    }
+
+    fn load_cipher(
+        &mut self,
+        filter: ObjectId,
+        obj: &ObjFilterXChaCha20Poly1305,
+    ) -> Result<Arc<Cipher>> {
+        if let Some(cipher) = self.ciphers.get(&filter) {
+            return Ok(cipher.clone());
+        }
+        let Some(key) = &self.decryption_key else {
+            return Error::key_required();
+        };
+        let cipher = Arc::new(Cipher::from_filter(key, obj)?);
+        self.ciphers.insert(filter, cipher.clone());
+        Ok(cipher)
+    }
+
+    fn traversal(&self) -> Traversal {
+        Traversal {
+            ancestors: Vec::new(),
+            entries: 0,
+            max_depth: self.limits.max_depth,
+            max_entries: self.limits.max_entries,
+        }
+    }
+
+    /// Opens the data of an object, undoing the given list of filters.
+    fn open_data(&mut self, id: ObjectId, filter_ids: &[ObjectId]) -> Result<Box<dyn Read + '_>> {
+        let mut filters = Vec::new();
+        for filter in filter_ids {
+            match self.objects.read_object(*filter)? {
+                DiarObject::FilterZstd(zstd) => {
+                    filters.push(Filter::Zstd(self.load_dict(*filter, &zstd.dict_sources)?));
+                }
+                DiarObject::FilterXChaCha20Poly1305(obj) => {
+                    filters.push(Filter::Decrypt(self.load_cipher(*filter, &obj)?));
+                }
+                DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+                _ => return corrupt(&"filter list contains an object that is not a filter"),
+            }
+        }
+
+        let mut stream: Box<dyn Read + '_> = Box::new(self.objects.read_data(id)?);
+        for filter in filters.iter().rev() {
+            stream = match filter {
+                Filter::Zstd(dict) => {
+                    let mut zstd = Decoder::with_dictionary(BufReader::new(stream), dict)?;
+                    zstd.window_log_max(self.limits.max_window_log)?;
+                    Box::new(WindowLimitReader(zstd))
+                }
+                Filter::Decrypt(cipher) => Box::new(cipher.reader(stream)?),
+            };
+        }
+        Ok(Box::new(LimitReader {
+            stream,
+            read: 0,
+            max_read: self.limits.max_entry_size,
+            total: &mut self.decompressed,
+            max_total: self.limits.max_total_size,
+        }))
+    }
+
+    /// Decompresses the contents of a file into the given stream, returning its length.
+    ///
+    /// Errors decoding the file's data are reported as [`ErrorKind::DecompressionFailed`], and
+    /// errors reading the archive or writing to `out` as [`ErrorKind::IoError`].
+    pub fn copy_file(&mut self, id: ObjectId, out: &mut impl Write) -> Result<u64> {
+        let DiarObject::BlobPlain(blob) = self.objects.read_object(id)? else {
+            return Error::wrong_object_type("object is not a file");
+        };
+        let mut stream = self.open_data(id, &blob.filters)?;
+        let mut buf = vec![0; 1024 * 64];
+        let mut len = 0;
+        loop {
+            let read = match stream.read(&mut buf) {
+                Ok(0) => return Ok(len),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::decompression_failed(e)),
+            };
+            out.write_all(&buf[..read])?;
+            len += read as u64;
+        }
+    }
+
+    /// Decompresses the contents of a file.
+    pub fn read_file(&mut self, id: ObjectId) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.copy_file(id, &mut data)?;
+        Ok(data)
+    }
+
+    /// Extracts a file or directory to the given path.
+    ///
+    /// On Unix, symbolic links are recreated and the file modes stored in the archive are
+    /// applied. Ownership and modification times are not restored. Entries of types this version
+    /// does not understand are skipped with a warning.
+    pub fn extract(&mut self, id: ObjectId, target: impl AsRef<Path>) -> Result<()> {
+        let mut walk = self.traversal();
+        self.extract_at(id, target.as_ref(), "", &mut walk)
+    }
+    fn extract_at(
+        &mut self,
+        id: ObjectId,
+        target: &Path,
+        path: &str,
+        walk: &mut Traversal,
+    ) -> Result<()> {
+        match self.read_object(id)? {
+            DiarObject::Directory(dir) => {
+                walk.enter(id)?;
+                std::fs::create_dir_all(target)?;
+                let mut names = HashSet::new();
+                for entry in dir.entries {
+                    check_entry_name(&entry.name, &mut names)?;
+                    walk.visit_entry()?;
+
+                    let entry_path = match path {
+                        "" => entry.name.clone(),
+                        _ => format!("{path}/{}", entry.name),
+                    };
+                    if let Some(ty) = self.unknown_type(entry.data)? {
+                        warn!("Skipping {entry_path} with unknown object type {ty:#x}");
+                        continue;
+                    }
+                    let target = target.join(&entry.name);
+                    self.extract_at(entry.data, &target, &entry_path, walk)?;
+                    let metadata = self
+                        .read_metadata(entry.metadata)
+                        .map_err(Error::in_archive)?;
+                    if let Some(mode) = metadata.mode {
+                        if !self.is_symlink(entry.data)? {
+                            set_mode(&target, mode)?;
+                        }
+                    }
+                }
+                walk.leave();
+            }
+            DiarObject::BlobPlain(_) => {
+                let mut file = File::create(target)?;
+                self.copy_file(id, &mut file)
+                    .map_err(|e| e.with_path(path))?;
+            }
+            DiarObject::Symlink(link) => create_symlink(&link.target, target)?,
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ if path.is_empty() => {
+                return Error::wrong_object_type("object is not a file or directory")
+            }
+            _ => return corrupt(&"directory entry is not a file, directory or symbolic link"),
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a directory entry can be safely extracted, and is not a duplicate.
+fn check_entry_name(name: &str, names: &mut HashSet<String>) -> Result<()> {
+    ensure_valid(
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']),
+        &"directory entry has an unsafe name",
+    )?;
+    ensure_valid(names.insert(name.to_string()), &"duplicate directory entry")
+}
+
+/// The state of a walk over the directories of an archive.
+struct Traversal {
+    ancestors: Vec<ObjectId>,
+    entries: u64,
+    max_depth: usize,
+    max_entries: Option<u64>,
+}
+impl Traversal {
+    /// Enters a directory, checking that it is not one of its own ancestors.
+    fn enter(&mut self, dir: ObjectId) -> Result<()> {
+        if self.ancestors.len() >= self.max_depth {
+            return Error::limit_exceeded(ResourceLimit::Depth);
+        }
+        ensure_valid(!self.ancestors.contains(&dir), &"directory contains itself")?;
+        self.ancestors.push(dir);
+        Ok(())
+    }
+    fn leave(&mut self) {
+        self.ancestors.pop();
+    }
+    fn visit_entry(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.max_entries.is_some_and(|max| self.entries > max) {
+            return Error::limit_exceeded(ResourceLimit::EntryCount);
+        }
+        Ok(())
+    }
+}
+
+/// Counts the bytes read from a decoded stream, failing once it passes the reader's limits.
+struct LimitReader<'a, R> {
+    stream: R,
+    read: u64,
+    max_read: Option<u64>,
+    total: &'a mut u64,
+    max_total: Option<u64>,
+}
+impl<'a, R: Read> Read for LimitReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stream.read(buf)?;
+        self.read += len as u64;
+        *self.total += len as u64;
+        if self.max_read.is_some_and(|max| self.read > max) {
+            return Err(io::Error::other(ResourceLimit::EntrySize));
+        }
+        if self.max_total.is_some_and(|max| *self.total > max) {
+            return Err(io::Error::other(ResourceLimit::TotalSize));
+        }
+        Ok(len)
+    }
+}
+
+/// The zstd error code for frames whose window is larger than the decoder allows, from
+/// `zstd_errors.h`.
+const ZSTD_ERROR_WINDOW_TOO_LARGE: usize = 16;
+
+/// Reports zstd frames that need a larger window than [`ReaderLimits::max_window_log`] as
+/// exceeding [`ResourceLimit::ZstdWindow`], rather than as corrupt data.
+struct WindowLimitReader<R>(R);
+impl<R: Read> Read for WindowLimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| {
+            let code = ZSTD_ERROR_WINDOW_TOO_LARGE.wrapping_neg();
+            let window_error = zstd::zstd_safe::get_error_name(code);
+            match e.get_ref() {
+                Some(inner) if inner.to_string() == window_error => {
+                    io::Error::other(ResourceLimit::ZstdWindow)
+                }
+                _ => e,
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(link: &str, target: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(link, target)?;
+    Ok(())
+}
+#[cfg(not(unix))]
+fn create_symlink(_: &str, _: &Path) -> Result<()> {
+    error(&"symbolic links can only be extracted on Unix")
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    Ok(())
+}
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: u32) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use crate::{
+    errors::*,
+    objects::*,
+    reader::ArchiveReader,
+    recovery::{recovery_record_configuration, recovery_record_start, RecoveryConfiguration},
+};
+use std::{
+    io,
+    io::{Read, Seek, Write},
+};
+
+/// The location of an object in an existing archive, which a writer adding to the archive refers
+/// to it by.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ObjectLocation {
+    pub offset: u64,
+    pub hash: Option<ObjectHash>,
+}
+
+/// The filters the files of an archive were written with.
+pub(crate) struct ArchiveFilters {
+    pub compression: ObjectLocation,
+    pub encryption: Option<(ObjectLocation, ObjFilterXChaCha20Poly1305)>,
+    /// The compression dictionary used for files.
+    pub dictionary: Vec<u8>,
+}
+
+pub(crate) fn object_ref(metadata: &MetadataMap, tag: MetadataTag) -> Result<Option<ObjectId>> {
+    match metadata.get(&tag) {
+        None => Ok(None),
+        Some(Metadata::ObjectRef(id)) => Ok(Some(*id)),
+        Some(_) => corrupt(&"archive metadata reference has the wrong type"),
+    }
+}
+
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Returns where an object is stored.
+    pub(crate) fn location(&self, id: ObjectId) -> Result<ObjectLocation> {
+        Ok(ObjectLocation {
+            offset: self.objects.object_offset(id)?,
+            hash: self.objects.object_hash(id)?,
+        })
+    }
+
+    /// Returns where the root object the trailer points to is stored.
+    pub(crate) fn root_location(&self) -> Result<ObjectLocation> {
+        self.location(self.objects.root())
+    }
+
+    /// Returns the root object of the archive.
+    pub(crate) fn root_object(&self) -> &ObjRoot {
+        &self.root
+    }
+
+    /// Reads an archive object the root object points to.
+    pub(crate) fn read_archive(&mut self, id: ObjectId) -> Result<ObjArchive> {
+        match self.read_object(id)? {
+            DiarObject::Archive(archive) => Ok(archive),
+            _ => corrupt(&"root object does not point to an archive"),
+        }
+    }
+
+    /// Reads the content index of a snapshot, returning no entries if it does not have one.
+    pub(crate) fn content_index(
+        &mut self,
+        archive: &ObjArchive,
+    ) -> Result<Vec<ContentIndexEntry>> {
+        let Some(id) = object_ref(&archive.metadata, MetadataTag::ContentIndex)? else {
+            return Ok(Vec::new());
+        };
+        match self.read_object(id)? {
+            DiarObject::ContentIndex(index) => Ok(index.entries),
+            _ => corrupt(&"content index reference does not point to a content index"),
+        }
+    }
+
+    /// Opens the contents of a file to be read as a stream.
+    ///
+    /// Unlike [`ArchiveReader::copy_file`], errors decoding the file are returned as they are, and
+    /// should be reported with [`Error::decompression_failed`].
+    pub(crate) fn open_file(&mut self, id: ObjectId) -> Result<Box<dyn Read + '_>> {
+        let DiarObject::BlobPlain(blob) = self.objects.read_object(id)? else {
+            return corrupt(&"object is not a file");
+        };
+        self.open_data(id, &blob.filters)
+    }
+
+    /// Reads an object as it is stored, without decoding it if it is sealed.
+    pub(crate) fn read_stored(&mut self, id: ObjectId) -> Result<DiarObject> {
+        self.objects.read_object(id)
+    }
+
+    /// Copies the data of an object as it is stored, without undoing its filters.
+    pub(crate) fn copy_stored_data(&mut self, id: ObjectId, out: &mut impl Write) -> Result<u64> {
+        Ok(io::copy(&mut self.objects.read_data(id)?, out)?)
+    }
+
+    /// Returns the entries of the archive's path index, if it has one.
+    pub(crate) fn path_index_entries(&mut self) -> Result<Option<Vec<PathIndexEntry>>> {
+        match self.path_index {
+            Some(id) => match self.read_object(id)? {
+                DiarObject::PathIndex(index) => Ok(Some(index.entries)),
+                _ => corrupt(&"path index reference does not point to a path index"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the offset of the start of the archive in the stream, and the offset its objects
+    /// end at, before any recovery record and the trailer.
+    pub(crate) fn objects_range(&mut self) -> Result<(u64, u64)> {
+        let end = match recovery_record_start(self.objects.stream_mut())? {
+            Some(start) => start,
+            None => self.objects.length(),
+        };
+        Ok((self.objects.base(), end))
+    }
+
+    /// Returns the configuration the archive's recovery record was written with, if it has one.
+    pub(crate) fn recovery_configuration(&mut self) -> Result<Option<RecoveryConfiguration>> {
+        recovery_record_configuration(self.objects.stream_mut())
+    }
+
+    /// Returns whether the archive's listings are sealed, judging by its root directory.
+    pub(crate) fn has_sealed_listings(&mut self) -> Result<bool> {
+        Ok(matches!(self.objects.read_object(self.root_dir)?, DiarObject::Sealed(_)))
+    }
+
+    /// Reads the filters recorded in the metadata of an archive object, returning `None` if the
+    /// archive was written before they were recorded.
+    pub(crate) fn read_filters(
+        &mut self,
+        metadata: &MetadataMap,
+    ) -> Result<Option<ArchiveFilters>> {
+        let Some(filter) = object_ref(metadata, MetadataTag::CompressionFilter)? else {
+            return Ok(None);
+        };
+        let DiarObject::FilterZstd(zstd) = self.objects.read_object(filter)? else {
+            return corrupt(&"compression filter is not a zstd filter");
+        };
+        let dictionary = self.load_dict(filter, &zstd.dict_sources)?.to_vec();
+        let encryption = match object_ref(metadata, MetadataTag::EncryptionFilter)? {
+            None => None,
+            Some(id) => match self.objects.read_object(id)? {
+                DiarObject::FilterXChaCha20Poly1305(obj) => Some((self.location(id)?, obj)),
+                _ => return corrupt(&"encryption filter is not an encryption filter"),
+            },
+        };
+        Ok(Some(ArchiveFilters {
+            compression: self.location(filter)?,
+            encryption,
+            dictionary,
+        }))
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    errors::*,
+    object_io::ObjectReader,
+    objects::*,
+    reader::{
+        check_entry_name, create_symlink, set_mode, ArchiveReader, ReaderConfiguration, Traversal,
+        VerifyIssue,
+    },
+    recovery::recovery_record_start,
+};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    ops::Range,
+    path::Path,
+};
+
+/// The result of [`ArchiveReader::salvage`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct SalvageReport {
+    /// The number of intact objects found by scanning the archive.
+    pub objects: u64,
+    /// Whether the root directory of the archive was found. If not, every recovered entry is
+    /// placed in `lost+found`.
+    pub found_root: bool,
+    /// The number of files extracted.
+    pub files: u64,
+    /// The total size of the files extracted.
+    pub bytes: u64,
+    /// The offsets of files and directories whose place in the directory tree was lost. They are
+    /// extracted to `lost+found/<offset>`.
+    pub orphans: Vec<u64>,
+    /// The ranges of the archive that contained no intact object, including an incomplete object
+    /// at its end.
+    pub damaged: Vec<Range<u64>>,
+    /// The entries that could not be recovered.
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl ArchiveReader<BufReader<File>> {
+    /// Recovers what it can from a damaged archive file into the given directory.
+    pub fn salvage_file(
+        path: impl AsRef<Path>,
+        cfg: &ReaderConfiguration,
+        target: impl AsRef<Path>,
+    ) -> Result<SalvageReport> {
+        ArchiveReader::salvage(BufReader::new(File::open(path)?), cfg, target)
+    }
+}
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Recovers what it can from a damaged archive into the given directory.
+    ///
+    /// The archive's trailer is not used. Instead, objects are found by scanning forward from the
+    /// start of the archive, which requires it to have been written with sync markers. This
+    /// recovers archives whose writer stopped before finishing them.
+    ///
+    /// Sync markers are opt-in: only archives written with
+    /// [`sync_markers`](crate::writer::CompressConfiguration::sync_markers) or a checkpoint
+    /// interval set can be salvaged, and other archives are refused with an error.
+    ///
+    /// Entries that cannot be extracted are recorded in the report rather than stopping the
+    /// recovery.
+    pub fn salvage(
+        stream: S,
+        cfg: &ReaderConfiguration,
+        target: impl AsRef<Path>,
+    ) -> Result<SalvageReport> {
+        let mut objects = ObjectReader::open_unterminated(stream)?;
+        // parity data in a recovery record may contain copies of objects, so it is not scanned
+        let end = match recovery_record_start(objects.stream_mut()) {
+            Ok(Some(start)) => start,
+            _ => objects.length(),
+        };
+        let (offsets, damaged) = objects.scan(end)?;
+        let mut reader = ArchiveReader::from_objects(objects, cfg);
+        let mut report = SalvageReport { damaged, ..Default::default() };
+
+        // objects are written after everything they refer to, so reading them backwards finds
+        // every reference to an object before the object itself
+        let mut main = None;
+        let mut archives = Vec::new();
+        let mut orphans = Vec::new();
+        for &offset in offsets.iter().rev() {
+            let referenced = reader.objects.is_referenced(offset);
+            let id = match reader.objects.object_at_offset(offset) {
+                Ok(id) => id,
+                Err(error) => {
+                    report
+                        .issues
+                        .push(VerifyIssue { offset: Some(offset), path: None, error });
+                    continue;
+                }
+            };
+            let obj = match reader.read_object(id) {
+                Ok(obj) => obj,
+                // problems with referenced objects are reported along with the entry using them
+                Err(_) if referenced => continue,
+                Err(error) => {
+                    report
+                        .issues
+                        .push(VerifyIssue { offset: Some(offset), path: None, error });
+                    continue;
+                }
+            };
+            report.objects += 1;
+            match obj {
+                DiarObject::Root(root) if !referenced => main = main.or(Some(root.main)),
+                // checkpoints of unfinished archives have no root directory
+                DiarObject::Archive(archive) if archive.root != ObjectId::NONE => {
+                    archives.push((id, archive.root));
+                }
+                DiarObject::Directory(_) | DiarObject::BlobPlain(_) | DiarObject::Symlink(_)
+                    if !referenced =>
+                {
+                    orphans.push((offset, id));
+                }
+                _ => {}
+            }
+        }
+
+        // use the archive the root object points to, or the last one written without it
+        let archive = archives
+            .iter()
+            .find(|x| Some(x.0) == main)
+            .or(archives.first());
+        let target = target.as_ref();
+        if let Some(&(_, root_dir)) = archive {
+            report.found_root = true;
+            let mut walk = reader.traversal();
+            reader.salvage_at(root_dir, target, "", &mut walk, &mut report);
+        }
+        if !orphans.is_empty() {
+            std::fs::create_dir_all(target.join("lost+found"))?;
+        }
+        for (offset, id) in orphans.into_iter().rev() {
+            report.orphans.push(offset);
+            let path = format!("lost+found/{offset}");
+            let mut walk = reader.traversal();
+            reader.salvage_at(id, &target.join(&path), &path, &mut walk, &mut report);
+        }
+        Ok(report)
+    }
+
+    fn salvage_at(
+        &mut self,
+        id: ObjectId,
+        target: &Path,
+        path: &str,
+        walk: &mut Traversal,
+        report: &mut SalvageReport,
+    ) -> bool {
+        match self.salvage_entry(id, target, path, walk, report) {
+            Ok(()) => true,
+            Err(error) => {
+                let offset = self.objects.object_offset(id).ok();
+                let path = Some(path.to_string()).filter(|x| !x.is_empty());
+                report.issues.push(VerifyIssue { offset, path, error });
+                false
+            }
+        }
+    }
+    fn salvage_entry(
+        &mut self,
+        id: ObjectId,
+        target: &Path,
+        path: &str,
+        walk: &mut Traversal,
+        report: &mut SalvageReport,
+    ) -> Result<()> {
+        match self.read_object(id)? {
+            DiarObject::Directory(dir) => {
+                // errors are recorded and salvaging carries on, so the directory must be left
+                // even if salvaging it failed
+                walk.enter(id)?;
+                let result = self.salvage_dir(id, dir, target, path, walk, report);
+                walk.leave();
+                result?;
+            }
+            DiarObject::BlobPlain(_) => {
+                let mut file = File::create(target)?;
+                match self.copy_file(id, &mut file) {
+                    Ok(len) => {
+                        report.files += 1;
+                        report.bytes += len;
+                    }
+                    Err(e) => {
+                        drop(file);
+                        let _ = std::fs::remove_file(target);
+                        return Err(e.with_path(path));
+                    }
+                }
+            }
+            DiarObject::Symlink(link) => create_symlink(&link.target, target)?,
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ => return corrupt(&"object is not a file or directory"),
+        }
+        Ok(())
+    }
+    fn salvage_dir(
+        &mut self,
+        id: ObjectId,
+        dir: ObjDirectory,
+        target: &Path,
+        path: &str,
+        walk: &mut Traversal,
+        report: &mut SalvageReport,
+    ) -> Result<()> {
+        std::fs::create_dir_all(target)?;
+        let mut names = HashSet::new();
+        for entry in dir.entries {
+            let entry_path = match path {
+                "" => entry.name.clone(),
+                _ => format!("{path}/{}", entry.name),
+            };
+            if let Err(error) = check_entry_name(&entry.name, &mut names) {
+                let offset = self.objects.object_offset(id).ok();
+                report
+                    .issues
+                    .push(VerifyIssue { offset, path: Some(entry_path), error });
+                continue;
+            }
+            walk.visit_entry()?;
+
+            let target = target.join(&entry.name);
+            if self.salvage_at(entry.data, &target, &entry_path, walk, report) {
+                // damaged metadata does not prevent the entry itself from being recovered
+                if let Ok(Some(mode)) = self.read_metadata(entry.metadata).map(|x| x.mode) {
+                    if !self.is_symlink(entry.data)? {
+                        set_mode(&target, mode)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{errors::*, objects::*, reader::ArchiveReader};
+use std::io::{Read, Seek};
+
+/// A snapshot stored in an archive.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// When the snapshot was created, in seconds since the Unix epoch, if it was recorded.
+    pub created: Option<i64>,
+    /// Whether this is the snapshot the archive is opened to, usually the latest one.
+    pub is_main: bool,
+}
+
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Lists the snapshots stored in the archive, ordered by name.
+    ///
+    /// Archives written without snapshots have none, and only contain the contents they are
+    /// opened to.
+    pub fn snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        let mut snapshots = Vec::new();
+        for (name, id) in self.root.alt.clone() {
+            let archive = self.read_archive(id)?;
+            let created = match archive.metadata.get(&MetadataTag::CreationTime) {
+                None => None,
+                Some(Metadata::VarInt(time)) => Some(*time),
+                Some(_) => return corrupt(&"snapshot creation time has the wrong type"),
+            };
+            snapshots.push(SnapshotInfo { name, created, is_main: id == self.root.main });
+        }
+        Ok(snapshots)
+    }
+
+    /// Switches to the snapshot with the given name, so that [`ArchiveReader::root_dir`], lookups
+    /// and extraction refer to its contents.
+    pub fn open_snapshot(&mut self, name: &str) -> Result<()> {
+        let Some(&id) = self.root.alt.get(name) else {
+            return Error::not_found(format!("snapshot {name:?}"));
+        };
+        let archive = self.read_archive(id)?;
+        self.set_archive(&archive)
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    errors::*,
+    objects::*,
+    reader::ArchiveReader,
+    recovery::{check_recovery_record, RecoveryReport},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    io::{Read, Seek},
+    ops::Range,
+};
+
+/// A problem found while verifying an archive.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct VerifyIssue {
+    /// The offset of the object the problem was found in, if it is known.
+    pub offset: Option<u64>,
+    /// The path of the entry the object belongs to, if it is known.
+    pub path: Option<String>,
+    /// The problem itself.
+    pub error: Error,
+}
+
+/// The result of [`ArchiveReader::verify`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct VerifyReport {
+    /// Whether the archive was written with object hashes, which were checked.
+    pub hashed: bool,
+    /// The number of objects reachable from the trailer that were read successfully.
+    pub objects: u64,
+    /// The number of blobs that were decompressed successfully.
+    pub blobs: u64,
+    /// The total decompressed size of those blobs.
+    pub bytes: u64,
+    /// The offsets and types of objects this version does not understand. Only their hashes
+    /// are checked.
+    pub unknown_objects: Vec<(u64, u32)>,
+    /// The ranges of the archive not used by any reachable object.
+    ///
+    /// These are not errors by themselves, but take up space and are not covered by any check.
+    pub unreachable: Vec<Range<u64>>,
+    /// Every problem found, in the order they were found.
+    pub issues: Vec<VerifyIssue>,
+    /// The result of checking the archive's recovery record, if it has one.
+    pub recovery: Option<RecoveryReport>,
+}
+impl VerifyReport {
+    /// Returns whether no problems were found.
+    pub fn is_ok(&self) -> bool {
+        let recovery_ok = match &self.recovery {
+            Some(recovery) => recovery.is_intact(),
+            None => true,
+        };
+        self.issues.is_empty() && recovery_ok
+    }
+}
+
+struct Frame {
+    id: ObjectId,
+    refs: Vec<ObjectId>,
+    next: usize,
+}
+
+struct Verifier {
+    report: VerifyReport,
+    extents: Vec<Range<u64>>,
+    paths: HashMap<ObjectId, String>,
+}
+
+impl<S: Read + Seek> ArchiveReader<S> {
+    /// Checks the entire archive, walking every object reachable from the trailer.
+    ///
+    /// Every object is decoded, every blob is decompressed, object hashes are compared where
+    /// present, and reference cycles and unused parts of the archive are detected. Problems are
+    /// collected into the report rather than stopping the check.
+    ///
+    /// The limit on the size of each entry still applies, but
+    /// [`max_total_size`](super::ReaderLimits::max_total_size) does not, as checking a large
+    /// archive means decompressing all of it. Data decompressed here is also not counted
+    /// towards that limit for later reads.
+    pub fn verify(&mut self) -> VerifyReport {
+        let max_total = self.limits.max_total_size.take();
+        let decompressed = self.decompressed;
+        let report = self.verify_all();
+        self.limits.max_total_size = max_total;
+        self.decompressed = decompressed;
+        report
+    }
+    fn verify_all(&mut self) -> VerifyReport {
+        let mut state = Verifier {
+            report: VerifyReport { hashed: self.objects.is_hashed(), ..Default::default() },
+            extents: Vec::new(),
+            paths: HashMap::new(),
+        };
+        state.paths.insert(self.root_dir, String::new());
+
+        let mut stack = Vec::new();
+        let mut on_stack = HashSet::new();
+        let mut done = HashSet::new();
+        let root = self.objects.root();
+        stack.push(Frame { id: root, refs: self.verify_object(&mut state, root), next: 0 });
+        on_stack.insert(root);
+        while let Some(frame) = stack.last_mut() {
+            if frame.next == frame.refs.len() {
+                on_stack.remove(&frame.id);
+                done.insert(frame.id);
+                stack.pop();
+                continue;
+            }
+            let id = frame.refs[frame.next];
+            frame.next += 1;
+            if on_stack.contains(&id) {
+                let error = corrupt::<()>(&"object is part of a reference cycle").unwrap_err();
+                state.issue(self.objects.object_offset(id).ok(), None, error);
+            } else if done.insert(id) {
+                let refs = self.verify_object(&mut state, id);
+                stack.push(Frame { id, refs, next: 0 });
+                on_stack.insert(id);
+            }
+        }
+
+        match check_recovery_record(self.objects.stream_mut()) {
+            Ok(recovery) => state.report.recovery = recovery,
+            Err(error) => state.issue(None, None, error),
+        }
+        let end = match &state.report.recovery {
+            Some(recovery) => recovery.covered,
+            None => self.objects.length(),
+        };
+        state.report.unreachable = self.unreachable(state.extents, end);
+        state.report
+    }
+
+    /// Checks a single object, returning the objects it refers to.
+    fn verify_object(&mut self, state: &mut Verifier, id: ObjectId) -> Vec<ObjectId> {
+        match self.verify_object_inner(state, id) {
+            Ok(refs) => {
+                state.report.objects += 1;
+                refs
+            }
+            Err(error) => {
+                let path = state.paths.get(&id).cloned();
+                let error = error.with_path(path.as_deref().unwrap_or(""));
+                state.issue(self.objects.object_offset(id).ok(), path, error);
+                Vec::new()
+            }
+        }
+    }
+    fn verify_object_inner(
+        &mut self,
+        state: &mut Verifier,
+        id: ObjectId,
+    ) -> Result<Vec<ObjectId>> {
+        state.extents.push(self.objects.object_extent(id)?);
+        let offset = self.objects.object_offset(id)?;
+        let obj = self.objects.read_object(id)?;
+        let mut refs = obj.references();
+        let obj = match obj {
+            DiarObject::Sealed(sealed) => {
+                let obj = self.unseal(id, &sealed)?;
+                refs.extend(obj.references());
+                obj
+            }
+            obj => obj,
+        };
+        match &obj {
+            DiarObject::BlobPlain(_) => {
+                state.report.bytes += self.copy_file(id, &mut io::sink())?;
+                state.report.blobs += 1;
+            }
+            DiarObject::Directory(dir) => {
+                if let Some(path) = state.paths.get(&id).cloned() {
+                    for entry in &dir.entries {
+                        let child = match path.as_str() {
+                            "" => entry.name.clone(),
+                            _ => format!("{path}/{}", entry.name),
+                        };
+                        state.paths.entry(entry.data).or_insert(child);
+                    }
+                }
+            }
+            DiarObject::Unknown(unknown) => {
+                io::copy(&mut self.objects.read_data(id)?, &mut io::sink())?;
+                state
+                    .report
+                    .unknown_objects
+                    .push((offset, unknown.object_type));
+            }
+            _ => {}
+        }
+        Ok(refs)
+    }
+
+    /// Finds the parts of the archive before `end` not covered by any of the given object extents.
+    fn unreachable(&self, mut extents: Vec<Range<u64>>, end: u64) -> Vec<Range<u64>> {
+        extents.sort_by_key(|x| x.start);
+        let mut gaps = Vec::new();
+        let mut pos = self.objects.objects_start();
+        for extent in extents {
+            if extent.start > pos {
+                gaps.push(pos..extent.start);
+            }
+            pos = pos.max(extent.end);
+        }
+        if pos < end {
+            gaps.push(pos..end);
+        }
+        gaps
+    }
+}
+
+impl Verifier {
+    fn issue(&mut self, offset: Option<u64>, path: Option<String>, error: Error) {
+        self.report.issues.push(VerifyIssue { offset, path, error });
+    }
+}
@@ -0,0 +1,644 @@
+use crate::{
+    errors::*,
+    object_io::{ObjectReader, END_HEADER, END_HEADER_HASHED, END_LENGTH, HASH_LENGTH},
+};
+use byteorder::*;
+use derive_setters::Setters;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::{
+    io,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
+
+// A recovery record is written after the objects of an archive, and laid out as:
+//
+//   [index] [parity shards] [index] [locator] [spacing] [locator] [trailer]
+//
+// Both locators hold the same bytes, and everything in them is measured from the first one.
+
+const LOCATOR_MAGIC: u64 = u64::from_le_bytes(*b"DiarRcv1");
+/// The length of a locator: the distance back from the first locator to the first copy of the
+/// index, the length and checksum of the index, and the magic number.
+const LOCATOR_LENGTH: u64 = 40;
+/// The space between the two copies of the locator, so that damage to a single disk sector cannot
+/// destroy both.
+const LOCATOR_SPACING: u64 = 4096;
+const CHECKSUM_LENGTH: usize = 16;
+/// The most shards a single Reed-Solomon group can have over GF(2^8).
+const MAX_SHARDS: u64 = 256;
+
+type Checksum = [u8; CHECKSUM_LENGTH];
+/// Writes repaired data at a position in the stream.
+type WriteAt<'a, S> = &'a mut dyn FnMut(&mut S, u64, &[u8]) -> Result<()>;
+
+fn checksum(data: &[u8]) -> Checksum {
+    blake3::hash(data).as_bytes()[..CHECKSUM_LENGTH]
+        .try_into()
+        .unwrap()
+}
+
+/// The options used when adding a recovery record to an archive.
+#[derive(Clone, Debug, Setters)]
+#[non_exhaustive]
+pub struct RecoveryConfiguration {
+    /// The size of the parity data, as a percentage of the size of the archive. Defaults to 5.
+    ///
+    /// Each group of shards can be repaired as long as no more than this share of it is damaged.
+    pub percent: u32,
+    /// The size of the stripes the archive is split into. Defaults to 64 KiB.
+    ///
+    /// Any damage within a stripe requires the whole stripe to be rebuilt, so smaller stripes
+    /// survive more scattered damage, at the cost of a larger index.
+    pub shard_size: u32,
+}
+impl Default for RecoveryConfiguration {
+    fn default() -> Self {
+        RecoveryConfiguration { percent: 5, shard_size: 64 * 1024 }
+    }
+}
+
+/// The result of checking or repairing the recovery record of an archive.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct RecoveryReport {
+    /// The length of the start of the archive covered by the recovery record.
+    pub covered: u64,
+    /// The number of data and parity shards in the recovery record.
+    pub shards: u64,
+    /// The number of shards whose contents did not match their checksum.
+    pub damaged_shards: u64,
+    /// The number of damaged shards that were rebuilt and written back.
+    pub repaired_shards: u64,
+    /// The ranges of the archive that are damaged, but cannot be rebuilt as too many other shards
+    /// in the same group are also damaged.
+    pub unrecoverable: Vec<Range<u64>>,
+    /// Whether one of the two copies of the recovery record's index or locator was damaged.
+    pub index_damaged: bool,
+    /// Whether the trailer of the archive was damaged.
+    pub trailer_damaged: bool,
+}
+impl RecoveryReport {
+    /// Returns whether no damage was found.
+    pub fn is_intact(&self) -> bool {
+        self.damaged_shards == 0 && !self.index_damaged && !self.trailer_damaged
+    }
+
+    /// Returns whether all of the damage found can be repaired.
+    pub fn is_repairable(&self) -> bool {
+        self.unrecoverable.is_empty()
+    }
+}
+
+/// How the covered part of an archive is split into shards.
+///
+/// Shards are interleaved between groups, so that a run of damaged bytes is spread over as many
+/// groups as possible.
+#[derive(Copy, Clone, Debug)]
+struct Layout {
+    covered: u64,
+    shard_size: u64,
+    data_shards: u64,
+    parity_shards: u64,
+    groups: u64,
+}
+impl Layout {
+    fn new(covered: u64, cfg: &RecoveryConfiguration) -> Result<Layout> {
+        ensure(
+            (1..=100).contains(&cfg.percent),
+            &"recovery record percentage must be between 1 and 100",
+        )?;
+        ensure(cfg.shard_size > 0, &"recovery record shard size must not be zero")?;
+        let percent = cfg.percent as u64;
+        let shard_size = cfg.shard_size as u64;
+        let shards = covered.div_ceil(shard_size).max(1);
+        let data_shards = (MAX_SHARDS * 100 / (100 + percent)).min(shards);
+        let parity_shards = (data_shards * percent)
+            .div_ceil(100)
+            .min(MAX_SHARDS - data_shards);
+        let groups = shards.div_ceil(data_shards);
+        Ok(Layout { covered, shard_size, data_shards, parity_shards, groups })
+    }
+
+    fn shards(&self) -> u64 {
+        self.covered.div_ceil(self.shard_size)
+    }
+    fn group_shards(&self) -> u64 {
+        self.data_shards + self.parity_shards
+    }
+    fn parity_length(&self) -> u64 {
+        self.groups * self.parity_shards * self.shard_size
+    }
+    /// Returns the range of the archive covered by a data shard, which is empty for the padding
+    /// shards at the end of the last groups.
+    fn data_range(&self, group: u64, idx: u64) -> Range<u64> {
+        let start = (idx * self.groups + group) * self.shard_size;
+        start.min(self.covered)..(start + self.shard_size).min(self.covered)
+    }
+    fn checksum_idx(&self, group: u64, idx: u64) -> usize {
+        (group * self.group_shards() + idx) as usize
+    }
+}
+
+/// The index of a recovery record, stored twice: the layout of its shards, their checksums, and a
+/// copy of the archive's trailer.
+struct Index {
+    layout: Layout,
+    trailer: Vec<u8>,
+    checksums: Vec<Checksum>,
+}
+impl Index {
+    fn encoded_length(layout: &Layout, trailer_len: u64) -> u64 {
+        let checksums = layout.groups * layout.group_shards() * CHECKSUM_LENGTH as u64;
+        8 + 4 + 4 + 4 + 8 + 4 + trailer_len + checksums
+    }
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_u64::<LE>(self.layout.covered)?;
+        data.write_u32::<LE>(self.layout.shard_size as u32)?;
+        data.write_u32::<LE>(self.layout.data_shards as u32)?;
+        data.write_u32::<LE>(self.layout.parity_shards as u32)?;
+        data.write_u64::<LE>(self.layout.groups)?;
+        data.write_u32::<LE>(self.trailer.len() as u32)?;
+        data.write_all(&self.trailer)?;
+        for checksum in &self.checksums {
+            data.write_all(checksum)?;
+        }
+        Ok(data)
+    }
+    fn decode(data: &[u8]) -> Result<Index> {
+        let mut data = Cursor::new(data);
+        let covered = data.read_u64::<LE>()?;
+        let shard_size = data.read_u32::<LE>()? as u64;
+        let data_shards = data.read_u32::<LE>()? as u64;
+        let parity_shards = data.read_u32::<LE>()? as u64;
+        let groups = data.read_u64::<LE>()?;
+        let layout = Layout { covered, shard_size, data_shards, parity_shards, groups };
+        ensure_valid(
+            shard_size > 0
+                && data_shards > 0
+                && parity_shards > 0
+                && data_shards + parity_shards <= MAX_SHARDS
+                && groups == layout.shards().max(1).div_ceil(data_shards),
+            &"recovery record layout is invalid",
+        )?;
+
+        let trailer_len = data.read_u32::<LE>()? as u64;
+        ensure_valid(
+            trailer_len == END_LENGTH || trailer_len == END_LENGTH + HASH_LENGTH,
+            &"recovery record contains an invalid trailer",
+        )?;
+        let mut trailer = vec![0; trailer_len as usize];
+        data.read_exact(&mut trailer)?;
+
+        let count = groups * layout.group_shards();
+        let remaining = data.get_ref().len() as u64 - data.position();
+        ensure_valid(
+            remaining == count * CHECKSUM_LENGTH as u64,
+            &"recovery record index has the wrong length",
+        )?;
+        let mut checksums = vec![Checksum::default(); count as usize];
+        for checksum in &mut checksums {
+            data.read_exact(checksum)?;
+        }
+        Ok(Index { layout, trailer, checksums })
+    }
+}
+
+/// Points from the end of a recovery record back to its index.
+struct Locator {
+    distance: u64,
+    index_len: u64,
+    checksum: Checksum,
+}
+impl Locator {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_u64::<LE>(self.distance)?;
+        data.write_u64::<LE>(self.index_len)?;
+        data.write_all(&self.checksum)?;
+        data.write_u64::<LE>(LOCATOR_MAGIC)?;
+        Ok(data)
+    }
+    /// Decodes a locator, returning `None` if it does not end with the magic number.
+    fn decode(data: &[u8]) -> Result<Option<Locator>> {
+        let mut data = Cursor::new(data);
+        let distance = data.read_u64::<LE>()?;
+        let index_len = data.read_u64::<LE>()?;
+        let mut checksum = Checksum::default();
+        data.read_exact(&mut checksum)?;
+        if data.read_u64::<LE>()? != LOCATOR_MAGIC {
+            return Ok(None);
+        }
+        Ok(Some(Locator { distance, index_len, checksum }))
+    }
+}
+
+/// The location of a recovery record found at the end of an archive.
+struct Record {
+    /// The position of the start of the archive in the stream.
+    base: u64,
+    index: Index,
+    index_data: Vec<u8>,
+    index_positions: [u64; 2],
+    locator_data: Vec<u8>,
+    locator_positions: [u64; 2],
+    /// Whether a copy of the index or of the locator was damaged.
+    index_damaged: bool,
+    trailer_position: u64,
+    trailer_damaged: bool,
+}
+impl Record {
+    fn parity_position(&self, group: u64, idx: u64) -> u64 {
+        let layout = &self.index.layout;
+        let start = self.index_positions[0] + self.index_data.len() as u64;
+        start + (group * layout.parity_shards + idx) * layout.shard_size
+    }
+}
+
+/// Finds the trailer at the end of a stream, returning its position. If the trailer is damaged,
+/// every position it could start at is returned.
+fn trailer_positions(stream: &mut (impl Read + Seek), end: u64) -> Result<Vec<u64>> {
+    if end < END_LENGTH {
+        return Ok(Vec::new());
+    }
+    stream.seek(SeekFrom::Start(end - END_LENGTH))?;
+    Ok(match stream.read_u64::<LE>()? {
+        END_HEADER => vec![end - END_LENGTH],
+        END_HEADER_HASHED if end >= END_LENGTH + HASH_LENGTH => {
+            vec![end - END_LENGTH - HASH_LENGTH]
+        }
+        _ => [END_LENGTH, END_LENGTH + HASH_LENGTH]
+            .into_iter()
+            .filter_map(|len| end.checked_sub(len))
+            .collect(),
+    })
+}
+
+/// Reads into `buf` from a position in the stream, returning whether it was filled before the
+/// end of the stream was reached.
+fn read_full(stream: &mut (impl Read + Seek), pos: u64, buf: &mut [u8]) -> Result<bool> {
+    stream.seek(SeekFrom::Start(pos))?;
+    let mut filled = 0;
+    while filled < buf.len() {
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads data from a position in the stream, returning `None` if the stream ends before it.
+fn read_at(stream: &mut (impl Read + Seek), pos: u64, len: u64) -> Result<Option<Vec<u8>>> {
+    let end = stream.seek(SeekFrom::End(0))?;
+    match pos.checked_add(len) {
+        Some(data_end) if data_end <= end => {}
+        _ => return Ok(None),
+    }
+    let mut data = vec![0; len as usize];
+    Ok(read_full(stream, pos, &mut data)?.then_some(data))
+}
+
+fn write_at(stream: &mut (impl Write + Seek), pos: u64, data: &[u8]) -> Result<()> {
+    stream.seek(SeekFrom::Start(pos))?;
+    stream.write_all(data)?;
+    Ok(())
+}
+
+/// Reads the copies of the index a locator points to, returning their positions, the first
+/// intact copy, and whether the other copy was damaged.
+#[allow(clippy::type_complexity)]
+fn read_index(
+    stream: &mut (impl Read + Seek),
+    first_locator: u64,
+    locator: &Locator,
+) -> Result<Option<([u64; 2], Vec<u8>, bool)>> {
+    let Locator { distance, index_len, checksum: expected } = *locator;
+    if index_len > distance || distance > first_locator {
+        return Ok(None);
+    }
+    let index_positions = [first_locator - distance, first_locator - index_len];
+    let mut copies = Vec::new();
+    for pos in index_positions {
+        if let Some(data) = read_at(stream, pos, index_len)? {
+            if checksum(&data) == expected {
+                copies.push(data);
+            }
+        }
+    }
+    let damaged = copies.len() < 2;
+    Ok(copies
+        .into_iter()
+        .next()
+        .map(|x| (index_positions, x, damaged)))
+}
+
+fn find_record(stream: &mut (impl Read + Seek)) -> Result<Option<Record>> {
+    let end = stream.seek(SeekFrom::End(0))?;
+    for trailer_position in trailer_positions(stream, end)? {
+        let Some(first) = trailer_position.checked_sub(2 * LOCATOR_LENGTH + LOCATOR_SPACING)
+        else {
+            continue;
+        };
+        let locator_positions = [first, trailer_position - LOCATOR_LENGTH];
+        let mut locators = Vec::new();
+        for pos in locator_positions {
+            if let Some(data) = read_at(stream, pos, LOCATOR_LENGTH)? {
+                if let Some(locator) = Locator::decode(&data)? {
+                    locators.push((data, locator));
+                }
+            }
+        }
+        if locators.is_empty() {
+            continue;
+        }
+
+        // either locator may be damaged without its magic number being touched, so each one is
+        // tried until an index matching its checksum is found
+        let locator_damaged = locators.len() < 2 || locators[0].0 != locators[1].0;
+        let mut found = None;
+        for (data, locator) in locators {
+            if let Some(index) = read_index(stream, first, &locator)? {
+                found = Some((data, index));
+                break;
+            }
+        }
+        let Some((locator_data, (index_positions, index_data, index_damaged))) = found else {
+            return corrupt(&"both copies of the recovery record index are damaged");
+        };
+        let index = Index::decode(&index_data)?;
+
+        let trailer_len = index.trailer.len() as u64;
+        let length_field = &index.trailer[index.trailer.len() - 16..index.trailer.len() - 8];
+        let length = u64::from_le_bytes(length_field.try_into().unwrap());
+        let base = match trailer_position.checked_sub(length) {
+            Some(base) if base + index.layout.covered <= index_positions[0] => base,
+            _ => return corrupt(&"recovery record covers more than the archive"),
+        };
+        let trailer_damaged = end != trailer_position + trailer_len
+            || read_at(stream, trailer_position, trailer_len)?.as_ref() != Some(&index.trailer);
+        return Ok(Some(Record {
+            base,
+            index,
+            index_data,
+            index_positions,
+            locator_data,
+            locator_positions,
+            index_damaged: index_damaged || locator_damaged,
+            trailer_position,
+            trailer_damaged,
+        }));
+    }
+    Ok(None)
+}
+
+fn reed_solomon(layout: &Layout) -> Result<ReedSolomon> {
+    match ReedSolomon::new(layout.data_shards as usize, layout.parity_shards as usize) {
+        Ok(rs) => Ok(rs),
+        Err(_) => corrupt(&"recovery record layout is invalid"),
+    }
+}
+
+/// Adds a recovery record to an archive that ends at the end of the given stream, replacing any
+/// it already has.
+///
+/// The record stores Reed-Solomon parity data for the archive's objects, which
+/// [`repair_recovery_record`] uses to rebuild damaged parts of the archive in place. Readers that
+/// do not know about recovery records ignore it.
+///
+/// The record is written after everything already in the stream, so that the archive stays
+/// readable if this is interrupted. A record that is replaced is left in place as unused space.
+pub fn add_recovery_record<S: Read + Write + Seek>(
+    stream: &mut S,
+    cfg: &RecoveryConfiguration,
+) -> Result<()> {
+    let (base, covered, trailer_position) = match find_record(stream)? {
+        Some(record) => {
+            ensure(!record.trailer_damaged, &"archive trailer is damaged, repair it first")?;
+            (record.base, record.index.layout.covered, record.trailer_position)
+        }
+        None => {
+            let reader = ObjectReader::open(&mut *stream)?;
+            (reader.base(), reader.length(), reader.base() + reader.length())
+        }
+    };
+    let end = stream.seek(SeekFrom::End(0))?;
+    let mut trailer = vec![0; (end - trailer_position) as usize];
+    if !read_full(stream, trailer_position, &mut trailer)? {
+        return Error::truncated_trailer();
+    }
+
+    let layout = Layout::new(covered, cfg)?;
+    let index_len = Index::encoded_length(&layout, trailer.len() as u64);
+    let index_positions = [end, end + index_len + layout.parity_length()];
+    let first_locator = index_positions[1] + index_len;
+    let locator_positions = [first_locator, first_locator + LOCATOR_LENGTH + LOCATOR_SPACING];
+    let new_trailer = locator_positions[1] + LOCATOR_LENGTH;
+    let length_field = trailer.len() - 16;
+    trailer[length_field..length_field + 8].copy_from_slice(&(new_trailer - base).to_le_bytes());
+
+    // the new trailer is written first, so that the archive can still be opened while the record
+    // is incomplete, with the space before the trailer left unused
+    write_at(stream, new_trailer, &trailer)?;
+    stream.flush()?;
+
+    let rs = reed_solomon(&layout)?;
+    let shard_size = layout.shard_size as usize;
+    let mut data = vec![vec![0; shard_size]; layout.data_shards as usize];
+    let mut parity = vec![vec![0; shard_size]; layout.parity_shards as usize];
+    let mut checksums = Vec::new();
+    let parity_start = index_positions[0] + index_len;
+    for group in 0..layout.groups {
+        for (idx, shard) in data.iter_mut().enumerate() {
+            let range = layout.data_range(group, idx as u64);
+            shard.fill(0);
+            let len = (range.end - range.start) as usize;
+            if !read_full(stream, base + range.start, &mut shard[..len])? {
+                return corrupt(&"archive is shorter than its trailer states");
+            }
+            checksums.push(checksum(shard));
+        }
+        if rs.encode_sep(&data, &mut parity).is_err() {
+            return error(&"failed to compute recovery record parity");
+        }
+        stream.seek(SeekFrom::Start(
+            parity_start + group * layout.parity_shards * layout.shard_size,
+        ))?;
+        for shard in &parity {
+            stream.write_all(shard)?;
+            checksums.push(checksum(shard));
+        }
+    }
+
+    let index = Index { layout, trailer, checksums }.encode()?;
+    for pos in index_positions {
+        write_at(stream, pos, &index)?;
+    }
+    stream.flush()?;
+
+    // the record is only found once its locators are written
+    let distance = first_locator - index_positions[0];
+    let locator = Locator { distance, index_len, checksum: checksum(&index) }.encode()?;
+    for pos in locator_positions {
+        write_at(stream, pos, &locator)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+/// Checks every shard covered by a recovery record against its checksum, rebuilding damaged
+/// shards with `repair` if it is given.
+fn check_shards<S: Read + Seek>(
+    stream: &mut S,
+    record: &Record,
+    report: &mut RecoveryReport,
+    mut repair: Option<WriteAt<'_, S>>,
+) -> Result<()> {
+    let layout = record.index.layout;
+    let rs = reed_solomon(&layout)?;
+    let mut buf = vec![0; layout.shard_size as usize];
+    for group in 0..layout.groups {
+        let mut shards = Vec::new();
+        let mut damaged = Vec::new();
+        for idx in 0..layout.group_shards() {
+            // bytes missing from the end of a truncated stream are treated as damage
+            buf.fill(0);
+            let complete = if idx < layout.data_shards {
+                let range = layout.data_range(group, idx);
+                let len = (range.end - range.start) as usize;
+                read_full(stream, record.base + range.start, &mut buf[..len])?
+            } else {
+                let pos = record.parity_position(group, idx - layout.data_shards);
+                read_full(stream, pos, &mut buf)?
+            };
+            let expected = record.index.checksums[layout.checksum_idx(group, idx)];
+            if complete && checksum(&buf) == expected {
+                shards.push(Some(buf.clone()));
+            } else {
+                shards.push(None);
+                damaged.push(idx);
+            }
+        }
+        report.damaged_shards += damaged.len() as u64;
+        if damaged.is_empty() {
+            continue;
+        }
+
+        let rebuilt = damaged.len() as u64 <= layout.parity_shards
+            && rs.reconstruct(&mut shards).is_ok()
+            && damaged.iter().all(|idx| {
+                let shard = shards[*idx as usize].as_ref().unwrap();
+                checksum(shard) == record.index.checksums[layout.checksum_idx(group, *idx)]
+            });
+        if !rebuilt {
+            for idx in damaged.into_iter().filter(|x| *x < layout.data_shards) {
+                let range = layout.data_range(group, idx);
+                if !range.is_empty() {
+                    report.unrecoverable.push(range);
+                }
+            }
+            continue;
+        }
+        let Some(repair) = &mut repair else {
+            continue;
+        };
+        for idx in damaged {
+            let shard = shards[idx as usize].as_ref().unwrap();
+            if idx < layout.data_shards {
+                let range = layout.data_range(group, idx);
+                let len = (range.end - range.start) as usize;
+                repair(stream, record.base + range.start, &shard[..len])?;
+            } else {
+                let pos = record.parity_position(group, idx - layout.data_shards);
+                repair(stream, pos, shard)?;
+            }
+            report.repaired_shards += 1;
+        }
+    }
+    report.unrecoverable.sort_by_key(|x| x.start);
+    report.unrecoverable.dedup_by(|next, prev| {
+        let adjacent = next.start == prev.end;
+        if adjacent {
+            prev.end = next.end;
+        }
+        adjacent
+    });
+    Ok(())
+}
+
+fn new_report(record: &Record) -> RecoveryReport {
+    let layout = &record.index.layout;
+    RecoveryReport {
+        covered: layout.covered,
+        shards: layout.shards() + layout.groups * layout.parity_shards,
+        index_damaged: record.index_damaged,
+        trailer_damaged: record.trailer_damaged,
+        ..Default::default()
+    }
+}
+
+/// Returns the configuration an archive's recovery record was written with, if it has one.
+pub(crate) fn recovery_record_configuration(
+    stream: &mut (impl Read + Seek),
+) -> Result<Option<RecoveryConfiguration>> {
+    Ok(find_record(stream)?.map(|record| {
+        let layout = record.index.layout;
+        let percent = (layout.parity_shards * 100).div_ceil(layout.data_shards);
+        RecoveryConfiguration {
+            percent: percent.clamp(1, 100) as u32,
+            shard_size: layout.shard_size as u32,
+        }
+    }))
+}
+
+/// Returns the length of the start of an archive covered by its recovery record, without checking
+/// the record's shards.
+pub(crate) fn recovery_record_start(stream: &mut (impl Read + Seek)) -> Result<Option<u64>> {
+    Ok(find_record(stream)?.map(|x| x.index.layout.covered))
+}
+
+/// Checks an archive that ends at the end of the given stream against its recovery record,
+/// returning `None` if it does not have one.
+pub fn check_recovery_record(mut stream: impl Read + Seek) -> Result<Option<RecoveryReport>> {
+    let Some(record) = find_record(&mut stream)? else {
+        return Ok(None);
+    };
+    let mut report = new_report(&record);
+    check_shards(&mut stream, &record, &mut report, None)?;
+    Ok(Some(report))
+}
+
+/// Repairs an archive that ends at the end of the given stream in place, using its recovery
+/// record.
+///
+/// Damaged shards are rebuilt and written back, along with damaged copies of the record's index
+/// and a damaged trailer. Shards in groups with more damage than the record can rebuild are left
+/// as they are, and listed in the report.
+pub fn repair_recovery_record<S: Read + Write + Seek>(mut stream: S) -> Result<RecoveryReport> {
+    let Some(record) = find_record(&mut stream)? else {
+        return error(&"archive does not have a recovery record");
+    };
+    let mut report = new_report(&record);
+    let mut write = |stream: &mut S, pos: u64, data: &[u8]| -> Result<()> {
+        stream.seek(SeekFrom::Start(pos))?;
+        stream.write_all(data)?;
+        Ok(())
+    };
+    check_shards(&mut stream, &record, &mut report, Some(&mut write))?;
+    if record.index_damaged {
+        for pos in record.index_positions {
+            write(&mut stream, pos, &record.index_data)?;
+        }
+        for pos in record.locator_positions {
+            write(&mut stream, pos, &record.locator_data)?;
+        }
+    }
+    if record.trailer_damaged {
+        write(&mut stream, record.trailer_position, &record.index.trailer)?;
+    }
+    stream.flush()?;
+    Ok(report)
+}
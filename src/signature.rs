@@ -0,0 +1,101 @@
+use crate::{errors::*, object_io::ObjectReader, objects::*};
+use ed25519_dalek::Signer;
+use std::io::{Read, Seek};
+
+pub use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+
+const EMBEDDED_CONTEXT: &[u8] = b"diar embedded signature v1\0";
+const DETACHED_CONTEXT: &[u8] = b"diar detached signature v1\0";
+
+/// The result of checking the signature embedded in an archive.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SignatureStatus {
+    /// The archive does not contain a signature.
+    Unsigned,
+    /// The archive is signed by the given key.
+    Valid,
+    /// The archive contains a signature that does not match its contents or the given key.
+    Invalid,
+}
+
+/// Builds the message covered by an embedded signature.
+///
+/// The signature is stored in the root object, so it cannot cover the root's own hash. Instead,
+/// it covers the hashes of every archive the root points to.
+fn embedded_message(main: &ObjectHash, alt: &[(&str, ObjectHash)]) -> Vec<u8> {
+    let mut alt = alt.to_vec();
+    alt.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut message = EMBEDDED_CONTEXT.to_vec();
+    message.extend_from_slice(main);
+    for (name, hash) in alt {
+        message.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        message.extend_from_slice(name.as_bytes());
+        message.extend_from_slice(&hash);
+    }
+    message
+}
+
+fn detached_message(root: &ObjectHash) -> Vec<u8> {
+    let mut message = DETACHED_CONTEXT.to_vec();
+    message.extend_from_slice(root);
+    message
+}
+
+pub(crate) fn sign_embedded(
+    key: &SigningKey,
+    main: &ObjectHash,
+    alt: &[(&str, ObjectHash)],
+) -> Metadata {
+    let signature = key.sign(&embedded_message(main, alt));
+    Metadata::Bytes(signature.to_bytes().to_vec())
+}
+
+pub(crate) fn verify_embedded(
+    key: &VerifyingKey,
+    signature: Option<&Metadata>,
+    main: &ObjectHash,
+    alt: &[(&str, ObjectHash)],
+) -> SignatureStatus {
+    let signature = match signature {
+        None => return SignatureStatus::Unsigned,
+        Some(Metadata::Bytes(data)) => match Signature::from_slice(data) {
+            Ok(signature) => signature,
+            Err(_) => return SignatureStatus::Invalid,
+        },
+        Some(_) => return SignatureStatus::Invalid,
+    };
+    match key.verify_strict(&embedded_message(main, alt), &signature) {
+        Ok(()) => SignatureStatus::Valid,
+        Err(_) => SignatureStatus::Invalid,
+    }
+}
+
+fn root_hash(stream: impl Read + Seek) -> Result<ObjectHash> {
+    let reader = ObjectReader::open(stream)?;
+    match reader.object_hash(reader.root())? {
+        Some(hash) => Ok(hash),
+        None => error(&"archive must be written with object hashes to be signed"),
+    }
+}
+
+/// Creates a detached signature for an archive, to be distributed alongside it.
+///
+/// The signature covers the root hash stored in the trailer, and so the entire archive.
+pub fn sign_detached(stream: impl Read + Seek, key: &SigningKey) -> Result<Signature> {
+    Ok(key.sign(&detached_message(&root_hash(stream)?)))
+}
+
+/// Checks a detached signature created by [`sign_detached`].
+///
+/// This only checks the root hash in the trailer. The objects themselves are checked against it
+/// as they are read.
+pub fn verify_detached(
+    stream: impl Read + Seek,
+    key: &VerifyingKey,
+    signature: &Signature,
+) -> Result<bool> {
+    Ok(key
+        .verify_strict(&detached_message(&root_hash(stream)?), signature)
+        .is_ok())
+}
@@ -0,0 +1,436 @@
+use crate::{
+    errors::*,
+    object_io::{format_header, DiarIo, Truncate},
+    objects::*,
+    reader::{ArchiveFilters, ArchiveReader, DirEntry, ObjectLocation, ReaderConfiguration},
+    recovery::{add_recovery_record, RecoveryConfiguration},
+    writer::{
+        diar_builder::*,
+        dir_tree::{DirNode, DirNodeData},
+    },
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use zstd::dict::EncoderDictionary;
+
+/// Adds the contents of a directory to an existing archive file in place.
+///
+/// See [`append_nodes_to_file`].
+pub fn append_to_file(
+    dir: &Path,
+    archive: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let nodes = DirNode::from_path_with_options(dir, &cfg.walk_options)?;
+    append_nodes_to_file(&nodes, archive, cfg)
+}
+
+/// Adds a directory tree built in memory to an existing archive file in place.
+///
+/// The tree is merged into the root directory of the archive: directories in both are merged,
+/// and other entries in the tree replace the entries at the same path in the archive. Everything
+/// else in the archive is kept by referring to the objects already in it, so only the new files
+/// and the directories above them are written, followed by a new root object and trailer.
+///
+/// The configuration must use the same encryption key as the archive. Files are compressed with
+/// the archive's dictionary when it records one. The archive keeps its object hashes, sync
+/// markers, path index and encrypted listings, and `checkpoint_interval` and `resume` are
+/// ignored. A signed archive can only be added to if `signing_key` is set, so that it is signed
+/// again.
+///
+/// A recovery record is written again with the same settings once the new objects are written.
+/// The old record, and objects no longer referenced, are left in the archive until it is repacked
+/// with [`repack_file`]. If appending fails, the archive is restored to its previous contents, but
+/// if the process is interrupted, it is left without a trailer, and can then only be salvaged if
+/// it was written with sync markers.
+///
+/// [`repack_file`]: crate::writer::repack_file
+pub fn append_nodes_to_file(
+    nodes: &DirNode,
+    archive: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let mut archive = InPlace::open(archive.as_ref(), nodes, cfg, 0)?;
+    let old_filters = archive.filters.take();
+    let old_root = archive.reader.root_object().clone();
+    let old_index = match archive.cfg.path_index {
+        true => archive.reader.path_index_entries()?,
+        false => None,
+    };
+    let cfg = &archive.cfg.clone();
+    archive.write(|writer, reader| {
+        let mut merge = Merge {
+            reader,
+            imported: HashMap::new(),
+            old_index: old_index.map(|x| {
+                x.into_iter()
+                    .map(|x| (x.hash, (x.data, x.metadata)))
+                    .collect()
+            }),
+            ancestors: HashSet::new(),
+        };
+        merge.append(writer, nodes, cfg, old_filters, &old_root)
+    })
+}
+
+/// An existing archive file opened to be written to in place.
+pub(super) struct InPlace {
+    path: PathBuf,
+    pub reader: ArchiveReader<BufReader<File>>,
+    /// The configuration, adjusted to how the archive is stored.
+    pub cfg: CompressConfiguration,
+    /// The filters recorded in the archive, if it records them.
+    pub filters: Option<ArchiveFilters>,
+    old_format: FormatInfo,
+    format: FormatInfo,
+    base: u64,
+    end: u64,
+    /// The length of the archive file before anything was added to it.
+    file_len: u64,
+    /// The configuration of the archive's recovery record, if it has one.
+    recovery: Option<RecoveryConfiguration>,
+    root: ObjectLocation,
+}
+impl InPlace {
+    /// Opens an archive file to add `nodes` to it, checking that the configuration matches how it
+    /// was written. `optional_features` are added to the features of the archive.
+    pub(super) fn open(
+        path: &Path,
+        nodes: &DirNode,
+        cfg: &CompressConfiguration,
+        optional_features: u64,
+    ) -> Result<Self> {
+        ensure(nodes.is_dir(), &"only a directory can be added to an archive")?;
+        let mut cfg = prepare_config(cfg)?;
+        let mut reader_cfg = ReaderConfiguration::default();
+        if let Some(key) = &cfg.encryption_key {
+            reader_cfg = reader_cfg.decryption_key(key.clone());
+        }
+        let mut reader = ArchiveReader::open_with_config(path, &reader_cfg)?;
+
+        let old_format = reader.format();
+        let encrypted = old_format.required_features & FormatInfo::REQUIRED_ENCRYPTION != 0;
+        ensure(
+            reader.is_hashed() || !hash_objects(&cfg),
+            &"archive was written without object hashes",
+        )?;
+        ensure(
+            encrypted == cfg.encryption_key.is_some(),
+            &"archive does not match the encryption settings",
+        )?;
+        ensure(reader.is_dir(reader.root_dir())?, &"archive root is not a directory")?;
+        let signed = reader
+            .root_object()
+            .metadata
+            .contains_key(&MetadataTag::Ed25519Signature);
+        ensure(
+            !signed || cfg.signing_key.is_some(),
+            &"archive is signed, and can only be added to with a key to sign it again",
+        )?;
+        let sealed = reader.has_sealed_listings()?;
+        ensure(
+            sealed || !cfg.encrypt_listings,
+            &"archive listings are not encrypted, and must be repacked to encrypt them",
+        )?;
+        cfg.encrypt_listings = sealed;
+        cfg.path_index |= reader.has_path_index();
+
+        // the new tree may add features, and the archive keeps the ones that describe how it is
+        // stored
+        let new_format = format_info(nodes, &cfg);
+        let kept = FormatInfo::OPTIONAL_OBJECT_HASHES | FormatInfo::OPTIONAL_SYNC_MARKERS;
+        let replaced = FormatInfo::OPTIONAL_PATH_INDEX | FormatInfo::OPTIONAL_SIGNATURE;
+        let format = FormatInfo::new(
+            old_format.required_features | new_format.required_features,
+            (old_format.optional_features & !replaced)
+                | (new_format.optional_features & !kept)
+                | optional_features,
+        );
+        ensure(
+            format_header(&format)?.len() == format_header(&old_format)?.len(),
+            &"archive format header cannot be updated in place",
+        )?;
+
+        let archive = reader.read_archive(reader.root_object().main)?;
+        let filters = reader.read_filters(&archive.metadata)?;
+        let (base, end) = reader.objects_range()?;
+        let recovery = reader.recovery_configuration()?;
+        let file_len = std::fs::metadata(path)?.len();
+        let root = reader.root_location()?;
+        let path = path.to_path_buf();
+        Ok(InPlace {
+            path,
+            reader,
+            cfg,
+            filters,
+            old_format,
+            format,
+            base,
+            end,
+            file_len,
+            recovery,
+            root,
+        })
+    }
+
+    fn resume(&self, mut file: File) -> Result<DiarIo<BufWriter<File>>> {
+        // a recovery record is kept until the new objects are written after it, so that the
+        // archive can be restored with it if writing fails
+        let start = match self.recovery {
+            Some(_) => self.file_len - self.base,
+            None => self.end,
+        };
+        file.truncate(self.base + start)?;
+        file.seek(SeekFrom::Start(self.base + start))?;
+        let sync_markers = self.format.optional_features & FormatInfo::OPTIONAL_SYNC_MARKERS != 0;
+        let hashed = self.reader.is_hashed();
+        Ok(DiarIo::resume(BufWriter::new(file), start, hashed, sync_markers))
+    }
+
+    /// Truncates the archive at the end of its objects, or at the end of its recovery record if
+    /// it has one, and calls `write` to add new objects and finish it. If that fails, the archive
+    /// is restored to its previous contents.
+    pub(super) fn write(
+        mut self,
+        write: impl FnOnce(
+            &mut DiarIo<BufWriter<File>>,
+            &mut ArchiveReader<BufReader<File>>,
+        ) -> Result<()>,
+    ) -> Result<()> {
+        trace!("Truncating archive at offset {}...", self.end);
+        let file = File::options().read(true).write(true).open(&self.path)?;
+        let mut writer = self.resume(file)?;
+        let mut file = match write(&mut writer, &mut self.reader) {
+            Ok(()) => writer
+                .into_inner()
+                .into_inner()
+                .map_err(|e| e.into_error())?,
+            Err(e) => {
+                trace!("Writing failed, restoring the previous trailer...");
+                let (file, _) = writer.into_inner().into_parts();
+                if self.recovery.is_some() {
+                    file.set_len(self.file_len)?;
+                    return Err(e);
+                }
+                let mut writer = self.resume(file)?;
+                let root = writer.import_object(self.root.offset, self.root.hash)?;
+                writer.finish(root)?;
+                return Err(e);
+            }
+        };
+        if self.format != self.old_format {
+            file.seek(SeekFrom::Start(self.base))?;
+            file.write_all(&format_header(&self.format)?)?;
+        }
+        if let Some(recovery) = &self.recovery {
+            trace!("Writing the recovery record again...");
+            add_recovery_record(&mut file, recovery)?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// The state used while merging a tree into the directories of an existing archive.
+struct Merge<'a, R> {
+    reader: &'a mut ArchiveReader<R>,
+    /// The objects of the existing archive registered with the writer, by their id in `reader`.
+    imported: HashMap<ObjectId, ObjectId>,
+    /// The path index of the existing archive, without the entries that were replaced.
+    old_index: Option<HashMap<PathHash, (ObjectId, ObjectId)>>,
+    /// The directories of the existing archive being read, to reject directories that contain
+    /// themselves.
+    ancestors: HashSet<ObjectId>,
+}
+impl<R: Read + Seek> Merge<'_, R> {
+    fn append<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        nodes: &DirNode,
+        cfg: &CompressConfiguration,
+        old_filters: Option<ArchiveFilters>,
+        old_root: &ObjRoot,
+    ) -> Result<()> {
+        let filters = match old_filters {
+            Some(filters) => import_filters(writer, filters, cfg)?,
+            None => write_filters(writer, nodes, cfg)?,
+        };
+        let dict = EncoderDictionary::new(&filters.dictionary, LEVEL);
+        let mut tree = TreeWriter {
+            cfg,
+            filter_obj: filters.compression,
+            dict: &dict,
+            encryption: filters.encryption.as_ref(),
+            index: Vec::new(),
+            resumed: HashMap::new(),
+            checkpoint: None,
+            contents: None,
+        };
+
+        trace!("Compressing data...");
+        let root_dir = self.reader.root_dir();
+        let root_dir = self.merge_dir(writer, &mut tree, root_dir, nodes, "")?;
+        trace!(" - Done!");
+
+        let index = match cfg.path_index {
+            true => Some(self.merge_index(writer, tree.index)?),
+            false => None,
+        };
+        let mut alt = BTreeMap::new();
+        for (name, id) in &old_root.alt {
+            alt.insert(name.clone(), self.import(writer, *id)?);
+        }
+        let extras = ArchiveExtras { alt, ..Default::default() };
+        finish_archive(writer, cfg, &filters, root_dir, index, extras)
+    }
+
+    /// Registers an object of the existing archive with the writer.
+    fn import(&mut self, writer: &mut DiarIo<impl Write>, id: ObjectId) -> Result<ObjectId> {
+        if id == ObjectId::NONE {
+            return Ok(ObjectId::NONE);
+        }
+        if let Some(imported) = self.imported.get(&id) {
+            return Ok(*imported);
+        }
+        let location = self.reader.location(id)?;
+        let imported = writer.import_object(location.offset, location.hash)?;
+        self.imported.insert(id, imported);
+        Ok(imported)
+    }
+
+    /// Reads a directory of the existing archive, which must be left with [`Merge::leave_dir`]
+    /// once everything below it was read.
+    fn enter_dir(&mut self, id: ObjectId) -> Result<Vec<DirEntry>> {
+        ensure_valid(self.ancestors.insert(id), &"directory contains itself")?;
+        self.reader.read_dir(id)
+    }
+    fn leave_dir(&mut self, id: ObjectId) {
+        self.ancestors.remove(&id);
+    }
+
+    /// Writes a directory with the entries of a directory in the existing archive, merged with
+    /// the children of a node.
+    fn merge_dir<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &mut TreeWriter,
+        old: ObjectId,
+        node: &DirNode,
+        path: &str,
+    ) -> Result<ObjectId> {
+        let DirNodeData::DirNode { contents } = &node.data else {
+            return error(&"only a directory can be merged into a directory");
+        };
+        let old_entries: BTreeMap<String, DirEntry> = self
+            .enter_dir(old)?
+            .into_iter()
+            .map(|x| (x.name.clone(), x))
+            .collect();
+        let names: BTreeSet<&String> = old_entries.keys().chain(contents.keys()).collect();
+
+        let mut entries = Vec::new();
+        for name in names {
+            let path = join_path(path, name);
+            let entry = match (old_entries.get(name), contents.get(name)) {
+                (Some(old), None) => {
+                    if tree.cfg.path_index && self.old_index.is_none() {
+                        self.index_old(writer, tree, old.data, old.metadata, &path)?;
+                    }
+                    DirectoryEntry {
+                        name: name.clone(),
+                        data: self.import(writer, old.data)?,
+                        metadata: self.import(writer, old.metadata)?,
+                    }
+                }
+                (Some(old), Some(node)) if node.is_dir() && self.reader.is_dir(old.data)? => {
+                    let data = self.merge_dir(writer, tree, old.data, node, &path)?;
+                    let metadata = match node.metadata.is_empty() {
+                        true => self.import(writer, old.metadata)?,
+                        false => write_entry_metadata(writer, tree, node)?,
+                    };
+                    if tree.cfg.path_index {
+                        tree.index
+                            .push(PathIndexEntry { hash: path_hash(&path), data, metadata });
+                    }
+                    DirectoryEntry { name: name.clone(), data, metadata }
+                }
+                (old, Some(node)) => {
+                    if let Some(old) = old {
+                        self.remove_old(old.data, &path)?;
+                    }
+                    write_entry(writer, tree, name, node, &path)?
+                }
+                (None, None) => unreachable!(),
+            };
+            entries.push(entry);
+        }
+        self.leave_dir(old);
+
+        let dir = DiarObject::Directory(ObjDirectory { sorted: true, entries });
+        write_listing(writer, tree.cfg, &dir, tree.encryption)
+    }
+
+    /// Adds an entry of the existing archive and everything below it to the new path index, for
+    /// archives that did not have one.
+    fn index_old<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &mut TreeWriter,
+        data: ObjectId,
+        metadata: ObjectId,
+        path: &str,
+    ) -> Result<()> {
+        if self.reader.is_dir(data)? {
+            for entry in self.enter_dir(data)? {
+                let path = join_path(path, &entry.name);
+                self.index_old(writer, tree, entry.data, entry.metadata, &path)?;
+            }
+            self.leave_dir(data);
+        }
+        let hash = path_hash(path);
+        let data = self.import(writer, data)?;
+        let metadata = self.import(writer, metadata)?;
+        tree.index.push(PathIndexEntry { hash, data, metadata });
+        Ok(())
+    }
+
+    /// Removes the entries below a replaced directory of the existing archive from its path
+    /// index. The entry itself is replaced when the new index is built.
+    fn remove_old(&mut self, data: ObjectId, path: &str) -> Result<()> {
+        if self.old_index.is_none() || !self.reader.is_dir(data)? {
+            return Ok(());
+        }
+        for entry in self.enter_dir(data)? {
+            let path = join_path(path, &entry.name);
+            if let Some(index) = &mut self.old_index {
+                index.remove(&path_hash(&path));
+            }
+            self.remove_old(entry.data, &path)?;
+        }
+        self.leave_dir(data);
+        Ok(())
+    }
+
+    /// Combines the remaining entries of the existing path index with the new entries.
+    fn merge_index(
+        &mut self,
+        writer: &mut DiarIo<impl Write>,
+        new: Vec<PathIndexEntry>,
+    ) -> Result<Vec<PathIndexEntry>> {
+        let mut entries = HashMap::new();
+        for (hash, (data, metadata)) in self.old_index.take().unwrap_or_default() {
+            entries.insert(hash, (self.import(writer, data)?, self.import(writer, metadata)?));
+        }
+        for entry in new {
+            entries.insert(entry.hash, (entry.data, entry.metadata));
+        }
+        Ok(entries
+            .into_iter()
+            .map(|(hash, (data, metadata))| PathIndexEntry { hash, data, metadata })
+            .collect())
+    }
+}
@@ -70,7 +70,7 @@ impl ContentHash {
         bcj_arm_thumb_code(&mut data);
 
         // calculate the cdc chunking
-        let mut cdc = FastCDC::new(&data, 1024, 1024 * 16, 1024 * 32);
+        let cdc = FastCDC::new(&data, 1024, 1024 * 16, 1024 * 32);
         let mut hash_data = [0; HASH_COUNT];
         for chunk in cdc {
             let block = &data[chunk.offset..chunk.offset + chunk.length];
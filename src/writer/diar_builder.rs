@@ -1,16 +1,22 @@
 use crate::{
+    encryption::{Cipher, EncryptionKey},
     errors::*,
-    names::KnownName,
-    object_io::DiarIo,
+    object_io::{DiarIo, ObjectReader, Truncate},
     objects::*,
+    reader::{ArchiveFilters, ArchiveReader, ObjectLocation, ReaderConfiguration},
+    signature::{sign_embedded, SigningKey},
     writer::{
         dict_builder::{BuildSamples, BuildSamplesConfiguration},
-        dir_tree::{DataSource, DirNode, DirNodeData},
+        dir_tree::{DataSource, DirNode, DirNodeData, WalkOptions},
+        snapshot::{ContentHasher, ContentIndex},
     },
 };
+use derive_setters::Setters;
 use std::{
-    collections::HashMap,
-    io::{Seek, Write},
+    collections::{BTreeMap, HashMap},
+    fs::{File, TryLockError},
+    io,
+    io::{BufWriter, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 use zstd::{
@@ -19,112 +25,762 @@ use zstd::{
     Encoder,
 };
 
-const LEVEL: CompressionLevel = 6;
+pub(super) const LEVEL: CompressionLevel = 6;
 
-fn write_compressed_blob<S: Write + Seek>(
-    target: &mut DiarIo<&mut S>,
+#[derive(Clone, Debug, Default, Setters)]
+#[non_exhaustive]
+pub struct CompressConfiguration {
+    /// Whether to store a hash of every object, allowing readers to detect corruption.
+    pub hash_objects: bool,
+    /// A key used to sign the archive. This implies `hash_objects`.
+    #[setters(strip_option)]
+    pub signing_key: Option<SigningKey>,
+    /// A key used to encrypt the contents of every file.
+    ///
+    /// Encryption uses random salts and nonces, so encrypted archives are not reproducible.
+    #[setters(strip_option)]
+    pub encryption_key: Option<EncryptionKey>,
+    /// Whether to also encrypt directory listings, entry metadata and symlink targets. This
+    /// requires `encryption_key`.
+    pub encrypt_listings: bool,
+    /// The options used to find files when compressing a directory on the filesystem.
+    pub walk_options: WalkOptions,
+    /// Modification times later than this are replaced with it, in seconds since the Unix
+    /// epoch. If unset, the `SOURCE_DATE_EPOCH` environment variable is used.
+    #[setters(strip_option)]
+    pub mtime_clamp: Option<i64>,
+    /// The seed used when choosing the samples the compression dictionary is trained on.
+    pub training_seed: u64,
+    /// Whether to write an index of every path in the archive, allowing readers to find files
+    /// without reading every directory above them.
+    pub path_index: bool,
+    /// Whether to write a sync marker after every object, so that the contents of archives that
+    /// were never finished, or whose trailer was damaged, can still be salvaged.
+    ///
+    /// This is off by default, and archives written without it cannot be salvaged with
+    /// [`ArchiveReader::salvage`](crate::reader::ArchiveReader::salvage).
+    pub sync_markers: bool,
+    /// If set, a checkpoint is written whenever this many bytes were written since the last one,
+    /// allowing [`compress_to_file`] to resume an interrupted run. This implies `sync_markers`.
+    ///
+    /// When writing to a file, every checkpoint is synced to disk before writing continues.
+    #[setters(strip_option)]
+    pub checkpoint_interval: Option<u64>,
+    /// Whether [`compress_to_file`] continues from the last checkpoint of a partial archive left
+    /// by an interrupted run, rather than starting over.
+    ///
+    /// Files written before the checkpoint are not read again, so changes to them since the
+    /// interrupted run are not picked up.
+    pub resume: bool,
+    /// The creation time recorded for snapshots, in seconds since the Unix epoch. If unset, the
+    /// `SOURCE_DATE_EPOCH` environment variable is used, or the current time if it is not set
+    /// either.
+    #[setters(strip_option)]
+    pub snapshot_time: Option<i64>,
+}
+
+pub(super) struct EncryptionFilter {
+    id: ObjectId,
+    cipher: Cipher,
+}
+impl EncryptionFilter {
+    /// Derives the cipher of an encryption filter object registered with the writer as `id`.
+    pub(super) fn from_filter(
+        id: ObjectId,
+        key: &EncryptionKey,
+        filter: &ObjFilterXChaCha20Poly1305,
+    ) -> Result<Self> {
+        Ok(EncryptionFilter { id, cipher: Cipher::from_filter(key, filter)? })
+    }
+}
+
+/// The zstd window used when the size of the data is not known.
+pub(super) const DEFAULT_WINDOW_LOG: u32 = 23;
+
+/// Picks the smallest zstd window that covers data of the given size, so readers need no more
+/// memory to decompress it than necessary.
+///
+/// A length hint of zero means the size is unknown, so the default window is used instead.
+fn window_log(len_hint: u64) -> u32 {
+    match len_hint {
+        0 => DEFAULT_WINDOW_LOG,
+        len => (u64::BITS - (len - 1).leading_zeros()).clamp(10, 30),
+    }
+}
+
+fn compress_stream(
+    target: &mut dyn Write,
+    dict: Option<&EncoderDictionary>,
+    len_hint: u64,
+    callback: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let mut zstd = match dict {
+        None => Encoder::new(target, LEVEL)?,
+        Some(dict) => Encoder::with_prepared_dictionary(target, dict)?,
+    };
+    zstd.set_parameter(CParameter::CompressionLevel(LEVEL))?;
+    zstd.set_parameter(CParameter::WindowLog(window_log(len_hint)))?;
+    zstd.set_parameter(CParameter::HashLog(30))?;
+    zstd.set_parameter(CParameter::EnableDedicatedDictSearch(true))?;
+    callback(&mut zstd)?;
+    zstd.finish()?;
+    Ok(())
+}
+
+pub(super) fn write_compressed_blob(
+    target: &mut DiarIo<impl Write>,
     dict: Option<&EncoderDictionary>,
     zstd_filter_id: ObjectId,
-    callback: impl FnOnce(&mut Encoder<&mut &mut S>) -> Result<()>,
+    encryption: Option<&EncryptionFilter>,
+    len_hint: u64,
+    callback: impl FnOnce(&mut dyn Write) -> Result<()>,
 ) -> Result<ObjectId> {
-    target.write_object_with_data(
-        &DiarObject::BlobPlain(ObjBlobPlain { filters: vec![zstd_filter_id] }),
-        |x| {
-            let mut zstd = match dict {
-                None => Encoder::new(x, LEVEL)?,
-                Some(dict) => Encoder::with_prepared_dictionary(x, dict)?,
-            };
-            zstd.set_parameter(CParameter::CompressionLevel(LEVEL))?;
-            zstd.set_parameter(CParameter::WindowLog(30))?;
-            zstd.set_parameter(CParameter::HashLog(30))?;
-            zstd.set_parameter(CParameter::EnableDedicatedDictSearch(true))?;
-            callback(&mut zstd)?;
-            zstd.finish()?;
-            Ok(())
-        },
-    )
+    let mut filters = vec![zstd_filter_id];
+    filters.extend(encryption.map(|x| x.id));
+    target.write_object_with_data(&DiarObject::BlobPlain(ObjBlobPlain { filters }), |x| {
+        match encryption {
+            None => compress_stream(x, dict, len_hint, callback),
+            Some(encryption) => {
+                let mut x = encryption.cipher.writer(x)?;
+                compress_stream(&mut x, dict, len_hint, callback)?;
+                x.finish()?;
+                Ok(())
+            }
+        }
+    })
 }
 
+/// Writes the contents of a file, also passing them to `hasher` if it is given.
 fn write_file(
-    target: &mut DiarIo<&mut (impl Write + Seek)>,
+    target: &mut DiarIo<impl Write>,
     contents: &DataSource,
     filter_obj: ObjectId,
     dict: &EncoderDictionary,
+    encryption: Option<&EncryptionFilter>,
+    hasher: Option<&mut ContentHasher>,
 ) -> Result<ObjectId> {
-    write_compressed_blob(target, Some(dict), filter_obj, |x| {
-        contents.write_to_stream(x)?;
+    let len_hint = contents.len_hint();
+    write_compressed_blob(target, Some(dict), filter_obj, encryption, len_hint, |x| {
+        match hasher {
+            Some(hasher) => contents.write_to_stream(&mut hasher.tee(x))?,
+            None => contents.write_to_stream(x)?,
+        }
         Ok(())
     })
 }
 
-fn write_dir(
-    target: &mut DiarIo<&mut (impl Write + Seek)>,
+/// The state used while writing the directory tree.
+pub(super) struct TreeWriter<'a> {
+    pub cfg: &'a CompressConfiguration,
+    pub filter_obj: ObjectId,
+    pub dict: &'a EncoderDictionary<'a>,
+    pub encryption: Option<&'a EncryptionFilter>,
+    pub index: Vec<PathIndexEntry>,
+    /// Files and symlinks written before the checkpoint a run was resumed from.
+    pub resumed: HashMap<PathHash, (ObjectId, ObjectId)>,
+    pub checkpoint: Option<CheckpointState>,
+    /// Files written before, by their contents, when writing a snapshot.
+    pub contents: Option<ContentIndex>,
+}
+
+/// Tracks the entries written since the last checkpoint.
+pub(super) struct CheckpointState {
+    interval: u64,
+    last_position: u64,
+    previous: ObjectId,
+    entries: Vec<PathIndexEntry>,
+    /// The file being written, if any, which is synced after every checkpoint.
+    sync: Option<File>,
+}
+
+/// Writes a checkpoint if enough was written since the last one, after the given entry.
+fn checkpoint(
+    target: &mut DiarIo<impl Write>,
+    tree: &mut TreeWriter,
+    entry: PathIndexEntry,
+) -> Result<()> {
+    let Some(state) = &mut tree.checkpoint else {
+        return Ok(());
+    };
+    state.entries.push(entry);
+    if target.position() - state.last_position < state.interval {
+        return Ok(());
+    }
+
+    let mut entries = std::mem::take(&mut state.entries);
+    entries.sort_by_key(|x| x.hash);
+    let index = DiarObject::PathIndex(ObjPathIndex { entries });
+    let index = write_listing(target, tree.cfg, &index, tree.encryption)?;
+
+    let mut metadata = MetadataMap::default();
+    metadata.insert(MetadataTag::Checkpoint, Metadata::ObjectRef(index));
+    if state.previous != ObjectId::NONE {
+        metadata.insert(MetadataTag::PreviousCheckpoint, Metadata::ObjectRef(state.previous));
+    }
+    insert_filters(&mut metadata, tree.filter_obj, tree.encryption);
+    let archive = DiarObject::Archive(ObjArchive { root: ObjectId::NONE, metadata });
+    let archive = target.write_object(&archive)?;
+    target.write_object(&DiarObject::Root(ObjRoot {
+        main: archive,
+        alt: Default::default(),
+        metadata: Default::default(),
+    }))?;
+    target.flush()?;
+    if let Some(file) = &state.sync {
+        file.sync_data()?;
+    }
+
+    state.previous = archive;
+    state.last_position = target.position();
+    Ok(())
+}
+
+pub(super) fn write_dir(
+    target: &mut DiarIo<impl Write>,
+    tree: &mut TreeWriter,
     node: &DirNode,
-    filter_obj: ObjectId,
-    dict: &EncoderDictionary,
+    path: &str,
 ) -> Result<ObjectId> {
+    let (cfg, encryption) = (tree.cfg, tree.encryption);
     match &node.data {
-        DirNodeData::FileNode { contents, .. } => write_file(target, contents, filter_obj, dict),
+        DirNodeData::FileNode { contents, .. } => match &mut tree.contents {
+            Some(index) => index.write_file(target, contents, |target, hasher| {
+                write_file(target, contents, tree.filter_obj, tree.dict, encryption, hasher)
+            }),
+            None => write_file(target, contents, tree.filter_obj, tree.dict, encryption, None),
+        },
         DirNodeData::DirNode { contents, .. } => {
             let mut entries = Vec::new();
             for (name, node) in contents {
-                let id = write_dir(target, node, filter_obj, dict)?;
-                entries.push(DirectoryEntry {
-                    name: name.to_string(),
-                    data: id,
-                    metadata: ObjectId::NONE,
-                });
+                entries.push(write_entry(target, tree, name, node, &join_path(path, name))?);
             }
 
-            target.write_object(&DiarObject::Directory(ObjDirectory { entries }))
+            // the tree stores its children in a `BTreeMap`, so they are always sorted
+            let dir = DiarObject::Directory(ObjDirectory { sorted: true, entries });
+            write_listing(target, cfg, &dir, encryption)
+        }
+        DirNodeData::SymlinkNode { target: link } => {
+            let link = DiarObject::Symlink(ObjSymlink { target: link.clone() });
+            write_listing(target, cfg, &link, encryption)
         }
     }
 }
 
-pub fn compress(dir: &Path, mut target: impl Write + Seek) -> Result<()> {
-    let nodes = DirNode::from_path(dir)?;
-    let mut writer = DiarIo::create(&mut target)?;
+pub(super) fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+/// Writes a node and its metadata, returning its entry in the directory containing it.
+pub(super) fn write_entry(
+    target: &mut DiarIo<impl Write>,
+    tree: &mut TreeWriter,
+    name: &str,
+    node: &DirNode,
+    path: &str,
+) -> Result<DirectoryEntry> {
+    let hash = path_hash(path);
 
-    // test
-    if !PathBuf::from("dict").exists() {
-        std::fs::create_dir(PathBuf::from("dict"))?;
+    // files written before the checkpoint this run resumed from
+    let resumed = tree.resumed.get(&hash).filter(|_| !node.is_dir());
+    if let Some(&(id, metadata)) = resumed {
+        if tree.cfg.path_index {
+            tree.index.push(PathIndexEntry { hash, data: id, metadata });
+        }
+        return Ok(DirectoryEntry { name: name.to_string(), data: id, metadata });
     }
 
+    let id = write_dir(target, tree, node, path)?;
+    let metadata = write_entry_metadata(target, tree, node)?;
+    if tree.cfg.path_index {
+        tree.index.push(PathIndexEntry { hash, data: id, metadata });
+    }
+    if !node.is_dir() {
+        checkpoint(target, tree, PathIndexEntry { hash, data: id, metadata })?;
+    }
+    Ok(DirectoryEntry { name: name.to_string(), data: id, metadata })
+}
+
+/// Writes the metadata of a node, if it has any.
+pub(super) fn write_entry_metadata(
+    target: &mut DiarIo<impl Write>,
+    tree: &TreeWriter,
+    node: &DirNode,
+) -> Result<ObjectId> {
+    if node.metadata.is_empty() {
+        return Ok(ObjectId::NONE);
+    }
+    let mut metadata = node.metadata.clone();
+    if let (Some(mtime), Some(clamp)) = (metadata.mtime, tree.cfg.mtime_clamp) {
+        metadata.mtime = Some(mtime.min(clamp));
+    }
+    let metadata = ObjMetadata { metadata: metadata.to_map() };
+    write_listing(target, tree.cfg, &DiarObject::Metadata(metadata), tree.encryption)
+}
+
+/// Writes an object describing the layout of the archive, sealing it if listings are encrypted.
+pub(super) fn write_listing(
+    target: &mut DiarIo<impl Write>,
+    cfg: &CompressConfiguration,
+    obj: &DiarObject,
+    encryption: Option<&EncryptionFilter>,
+) -> Result<ObjectId> {
+    match encryption {
+        Some(encryption) if cfg.encrypt_listings => {
+            target.write_sealed_object(obj, &[encryption.id], |data, x| {
+                let mut x = encryption.cipher.writer(x)?;
+                x.write_all(data)?;
+                x.finish()?;
+                Ok(())
+            })
+        }
+        _ => target.write_object(obj),
+    }
+}
+
+pub fn compress(dir: &Path, target: impl Write) -> Result<()> {
+    compress_with_config(dir, target, &CompressConfiguration::default())
+}
+
+pub fn compress_with_config(
+    dir: &Path,
+    target: impl Write,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    compress_nodes(&DirNode::from_path_with_options(dir, &cfg.walk_options)?, target, cfg)
+}
+
+pub(super) fn format_info(nodes: &DirNode, cfg: &CompressConfiguration) -> FormatInfo {
+    let mut required = 0;
+    let mut optional = 0;
+    if cfg.encryption_key.is_some() {
+        required |= FormatInfo::REQUIRED_ENCRYPTION;
+    }
+    if cfg.path_index {
+        optional |= FormatInfo::OPTIONAL_PATH_INDEX;
+    }
+    if cfg.signing_key.is_some() {
+        optional |= FormatInfo::OPTIONAL_SIGNATURE;
+    }
+    if cfg.sync_markers || cfg.checkpoint_interval.is_some() {
+        optional |= FormatInfo::OPTIONAL_SYNC_MARKERS;
+    }
+    for (_, node) in nodes.walk() {
+        if node.is_symlink() {
+            required |= FormatInfo::REQUIRED_SYMLINKS;
+        }
+        if !node.metadata().is_empty() {
+            optional |= FormatInfo::OPTIONAL_ENTRY_METADATA;
+        }
+    }
+    FormatInfo::new(required, optional)
+}
+
+/// Reads the `SOURCE_DATE_EPOCH` environment variable, returning `None` if it is not set.
+pub(super) fn source_date_epoch() -> Result<Option<i64>> {
+    let Some(value) = std::env::var_os("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    match value.to_str().and_then(|x| x.trim().parse().ok()) {
+        Some(value) => Ok(Some(value)),
+        None => Error::invalid_source_date_epoch(value.to_string_lossy()),
+    }
+}
+
+/// Compresses a directory tree built in memory.
+///
+/// Unless encryption is enabled, compressing the same tree with the same configuration always
+/// produces the same bytes.
+pub fn compress_nodes(
+    nodes: &DirNode,
+    target: impl Write,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let cfg = &prepare_config(cfg)?;
+    let mut writer =
+        DiarIo::create(BufWriter::new(target), hash_objects(cfg), &format_info(nodes, cfg))?;
+    let filters = write_filters(&mut writer, nodes, cfg)?;
+    write_archive(&mut writer, nodes, cfg, filters, None, None)?;
+    Ok(())
+}
+
+/// Compresses a directory into an archive file.
+///
+/// The archive is written to a temporary file next to `target`, which is renamed once it is
+/// complete, so `target` never contains a partial archive. If `checkpoint_interval` and `resume`
+/// are set, a temporary file left by an interrupted run is continued from its last checkpoint.
+pub fn compress_to_file(
+    dir: &Path,
+    target: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let nodes = DirNode::from_path_with_options(dir, &cfg.walk_options)?;
+    compress_nodes_to_file(&nodes, target, cfg)
+}
+
+/// Compresses a directory tree built in memory into an archive file, like [`compress_to_file`].
+pub fn compress_nodes_to_file(
+    nodes: &DirNode,
+    target: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let target = target.as_ref();
+    let partial = partial_path(target);
+    let cfg = &prepare_config(cfg)?;
+    let resumed = match cfg.resume {
+        true => resume_partial(nodes, &partial, cfg)?,
+        false => None,
+    };
+    let file = match resumed {
+        Some(ResumedFile::Finished) => {
+            std::fs::rename(&partial, target)?;
+            return Ok(());
+        }
+        Some(ResumedFile::Checkpoint(mut writer, sync, filters, checkpoint)) => {
+            write_archive(&mut *writer, nodes, cfg, filters, Some(checkpoint), Some(sync))?;
+            writer.into_inner()
+        }
+        None => {
+            let file = create_partial(&partial)?;
+            let sync = file.try_clone()?;
+            let file = BufWriter::new(file);
+            let mut writer = DiarIo::create(file, hash_objects(cfg), &format_info(nodes, cfg))?;
+            let filters = write_filters(&mut writer, nodes, cfg)?;
+            write_archive(&mut writer, nodes, cfg, filters, None, Some(sync))?;
+            writer.into_inner()
+        }
+    };
+    finish_partial(file, &partial, target)
+}
+
+/// Returns the temporary file an archive file is written to before it is complete.
+pub(super) fn partial_path(target: &Path) -> PathBuf {
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Locks the temporary file an archive file is written to, so that concurrent runs writing the
+/// same archive cannot clobber each other.
+fn lock_partial(file: &File) -> Result<()> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => error(&"archive is already being written by another run"),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Creates the temporary file an archive file is written to, and locks it.
+pub(super) fn create_partial(partial: &Path) -> Result<File> {
+    // the file is only truncated once it is locked, as another run may still be writing it
+    let file = File::options()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(partial)?;
+    lock_partial(&file)?;
+    file.set_len(0)?;
+    Ok(file)
+}
+
+/// Moves a complete archive from its temporary file to its final path.
+pub(super) fn finish_partial(file: BufWriter<File>, partial: &Path, target: &Path) -> Result<()> {
+    let file = file.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(partial, target)?;
+    Ok(())
+}
+
+pub(super) fn prepare_config(cfg: &CompressConfiguration) -> Result<CompressConfiguration> {
+    ensure(
+        !cfg.encrypt_listings || cfg.encryption_key.is_some(),
+        &"encrypt_listings requires an encryption key",
+    )?;
+    ensure(cfg.checkpoint_interval != Some(0), &"checkpoint_interval must not be zero")?;
+    let mut cfg = cfg.clone();
+    if cfg.mtime_clamp.is_none() {
+        cfg.mtime_clamp = source_date_epoch()?;
+    }
+    Ok(cfg)
+}
+
+pub(super) fn hash_objects(cfg: &CompressConfiguration) -> bool {
+    cfg.hash_objects || cfg.signing_key.is_some()
+}
+
+/// The filters every file in an archive is written with.
+pub(super) struct Filters {
+    pub dictionary: Vec<u8>,
+    pub compression: ObjectId,
+    pub encryption: Option<EncryptionFilter>,
+}
+
+/// Writes the compression dictionary and the filters files are written with.
+pub(super) fn write_filters(
+    writer: &mut DiarIo<impl Write>,
+    nodes: &DirNode,
+    cfg: &CompressConfiguration,
+) -> Result<Filters> {
     trace!("Building samples...");
-    let mut samples = BuildSamples::new(&BuildSamplesConfiguration::default());
-    samples.add_nodes(&nodes)?;
+    let samples_cfg = BuildSamplesConfiguration::default().seed(cfg.training_seed);
+    let mut samples = BuildSamples::new(&samples_cfg);
+    samples.add_nodes(nodes)?;
 
     trace!("Building dictionary...");
     let data = samples.build_dictionary()?;
-    std::fs::write("dict/test_dict.dict", &data)?;
+    write_dictionary_filters(writer, data, cfg)
+}
+
+/// Writes the given compression dictionary and the filters files are written with.
+pub(super) fn write_dictionary_filters(
+    writer: &mut DiarIo<impl Write>,
+    data: Vec<u8>,
+    cfg: &CompressConfiguration,
+) -> Result<Filters> {
+    let encryption = match &cfg.encryption_key {
+        Some(key) => {
+            trace!("Deriving encryption key...");
+            let (cipher, filter) = Cipher::new_filter(key)?;
+            let id = writer.write_object(&DiarObject::FilterXChaCha20Poly1305(filter))?;
+            Some(EncryptionFilter { id, cipher })
+        }
+        None => None,
+    };
 
     trace!("Writing dictionary object...");
     let plain_zstd_filter =
         writer.write_object(&DiarObject::FilterZstd(ObjFilterZstd { dict_sources: vec![] }))?;
-    let dict_data = write_compressed_blob(&mut writer, None, plain_zstd_filter, |x| {
-        x.write_all(&data)?;
-        Ok(())
-    })?;
+    let dict_len = data.len() as u64;
+    let dict_data = write_compressed_blob(
+        writer,
+        None,
+        plain_zstd_filter,
+        encryption.as_ref(),
+        dict_len,
+        |x| {
+            x.write_all(&data)?;
+            Ok(())
+        },
+    )?;
     let dict_obj = writer
         .write_object(&DiarObject::FilterZstd(ObjFilterZstd { dict_sources: vec![dict_data] }))?;
+    Ok(Filters { dictionary: data, compression: dict_obj, encryption })
+}
+
+/// Registers the filters of an existing archive with a writer adding to it.
+pub(super) fn import_filters(
+    writer: &mut DiarIo<impl Write>,
+    filters: ArchiveFilters,
+    cfg: &CompressConfiguration,
+) -> Result<Filters> {
+    let mut import = |x: ObjectLocation| writer.import_object(x.offset, x.hash);
+    let encryption = match (&filters.encryption, &cfg.encryption_key) {
+        (Some((location, filter)), Some(key)) => {
+            Some(EncryptionFilter::from_filter(import(*location)?, key, filter)?)
+        }
+        (None, None) => None,
+        _ => return error(&"archive does not match the encryption settings"),
+    };
+    let compression = import(filters.compression)?;
+    Ok(Filters { dictionary: filters.dictionary, compression, encryption })
+}
+
+enum ResumedFile {
+    /// The partial archive was already finished, and only needs to be renamed.
+    Finished,
+    /// The partial archive was truncated back to its last checkpoint. The file is also returned
+    /// unbuffered, to sync checkpoints with.
+    Checkpoint(Box<DiarIo<BufWriter<File>>>, File, Filters, ResumeState),
+}
+
+/// The files written before a checkpoint, and the checkpoint itself.
+struct ResumeState {
+    entries: HashMap<PathHash, (ObjectId, ObjectId)>,
+    archive: ObjectId,
+    position: u64,
+}
+
+/// Opens a partial archive left by an interrupted run, and truncates it back to its last
+/// checkpoint. Returns `None` if there is no partial archive, or it cannot be continued: it has
+/// no checkpoint, was started with different format features, or is finished but fails
+/// verification.
+fn resume_partial(
+    nodes: &DirNode,
+    partial: &Path,
+    cfg: &CompressConfiguration,
+) -> Result<Option<ResumedFile>> {
+    let mut file = match File::options().read(true).write(true).open(partial) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    lock_partial(&file)?;
+
+    let mut format = format_info(nodes, cfg);
+    if hash_objects(cfg) {
+        format.optional_features |= FormatInfo::OPTIONAL_OBJECT_HASHES;
+    }
+    let mut reader_cfg = ReaderConfiguration::default();
+    if let Some(key) = &cfg.encryption_key {
+        reader_cfg = reader_cfg.decryption_key(key.clone());
+    }
+    if ObjectReader::open(&mut file).is_ok() {
+        let finished = match ArchiveReader::new_with_config(&mut file, &reader_cfg) {
+            Ok(mut reader) => reader.format() == format && reader.verify().is_ok(),
+            Err(_) => false,
+        };
+        return Ok(finished.then_some(ResumedFile::Finished));
+    }
+
+    let checkpoint = match ArchiveReader::find_checkpoint(&mut file, &reader_cfg) {
+        Ok(Some(checkpoint)) => checkpoint,
+        Ok(None) => return Ok(None),
+        Err(e) if e.is_corruption() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if checkpoint.format != format {
+        trace!("Partial archive was started with different format features, starting over...");
+        return Ok(None);
+    }
+    trace!("Resuming from checkpoint at offset {}...", checkpoint.end);
+
+    file.truncate(checkpoint.end)?;
+    file.seek(SeekFrom::Start(checkpoint.end))?;
+    let sync = file.try_clone()?;
+    let sync_markers = format.optional_features & FormatInfo::OPTIONAL_SYNC_MARKERS != 0;
+    let mut writer =
+        DiarIo::resume(BufWriter::new(file), checkpoint.end, hash_objects(cfg), sync_markers);
+    let filters = import_filters(&mut writer, checkpoint.filters, cfg)?;
+    let mut import = |x: ObjectLocation| writer.import_object(x.offset, x.hash);
+    let mut entries = HashMap::new();
+    for entry in &checkpoint.entries {
+        let metadata = match entry.metadata {
+            Some(metadata) => import(metadata)?,
+            None => ObjectId::NONE,
+        };
+        entries.insert(entry.hash, (import(entry.data)?, metadata));
+    }
+    let archive = import(checkpoint.archive)?;
+
+    let state = ResumeState { entries, archive, position: checkpoint.end };
+    Ok(Some(ResumedFile::Checkpoint(Box::new(writer), sync, filters, state)))
+}
+
+/// Writes the directory tree and the objects describing the archive, and finishes it.
+fn write_archive<W: Write>(
+    writer: &mut DiarIo<W>,
+    nodes: &DirNode,
+    cfg: &CompressConfiguration,
+    filters: Filters,
+    resumed: Option<ResumeState>,
+    sync: Option<File>,
+) -> Result<()> {
+    let encryption = filters.encryption.as_ref();
 
     trace!("Compressing data...");
-    let dict = EncoderDictionary::new(&data, LEVEL);
-    let root_obj = write_dir(&mut writer, &nodes, dict_obj, &dict)?;
+    let dict = EncoderDictionary::new(&filters.dictionary, LEVEL);
+    let checkpoint = cfg.checkpoint_interval.map(|interval| CheckpointState {
+        interval,
+        last_position: resumed.as_ref().map_or(writer.position(), |x| x.position),
+        previous: resumed.as_ref().map_or(ObjectId::NONE, |x| x.archive),
+        entries: Vec::new(),
+        sync,
+    });
+    let mut tree = TreeWriter {
+        cfg,
+        filter_obj: filters.compression,
+        dict: &dict,
+        encryption,
+        index: Vec::new(),
+        resumed: resumed.map(|x| x.entries).unwrap_or_default(),
+        checkpoint,
+        contents: None,
+    };
+    let root_dir = write_dir(writer, &mut tree, nodes, "")?;
     trace!(" - Done!");
 
+    let index = cfg.path_index.then_some(tree.index);
+    finish_archive(writer, cfg, &filters, root_dir, index, ArchiveExtras::default())
+}
+
+/// What an archive records besides its root directory, path index and filters.
+#[derive(Default)]
+pub(super) struct ArchiveExtras {
+    /// Metadata added to the archive object.
+    pub metadata: MetadataMap,
+    /// The other archives the root object lists.
+    pub alt: BTreeMap<String, ObjectId>,
+    /// If set, the archive itself is also listed in `alt` under this name.
+    pub name: Option<String>,
+}
+
+/// Writes the objects describing an archive with the given root directory and path index, and
+/// finishes it.
+pub(super) fn finish_archive<W: Write>(
+    writer: &mut DiarIo<W>,
+    cfg: &CompressConfiguration,
+    filters: &Filters,
+    root_dir: ObjectId,
+    index: Option<Vec<PathIndexEntry>>,
+    extras: ArchiveExtras,
+) -> Result<()> {
     trace!("Finishing archive...");
-    let archive_obj = writer.write_object(&DiarObject::Archive(ObjArchive {
-        root: root_obj,
-        metadata: Default::default(),
-    }))?;
+    let ArchiveExtras { metadata, mut alt, name } = extras;
+    let archive_obj = write_archive_object(writer, cfg, filters, root_dir, index, metadata)?;
+    if let Some(name) = name {
+        alt.insert(name, archive_obj);
+    }
+    let mut root_metadata = MetadataMap::default();
+    if let Some(key) = &cfg.signing_key {
+        let mut alt_hashes = Vec::new();
+        for (name, id) in &alt {
+            alt_hashes.push((name.as_str(), writer.get_object_hash(*id)?));
+        }
+        let signature = sign_embedded(key, &writer.get_object_hash(archive_obj)?, &alt_hashes);
+        root_metadata.insert(MetadataTag::Ed25519Signature, signature);
+    }
     let root_obj = writer.write_object(&DiarObject::Root(ObjRoot {
         main: archive_obj,
-        alt: Default::default(),
-        metadata: Default::default(),
+        alt,
+        metadata: root_metadata,
     }))?;
 
     writer.finish(root_obj)?;
 
     Ok(())
 }
+
+/// Writes an archive object with the given root directory, path index and metadata.
+pub(super) fn write_archive_object<W: Write>(
+    writer: &mut DiarIo<W>,
+    cfg: &CompressConfiguration,
+    filters: &Filters,
+    root_dir: ObjectId,
+    index: Option<Vec<PathIndexEntry>>,
+    mut metadata: MetadataMap,
+) -> Result<ObjectId> {
+    let encryption = filters.encryption.as_ref();
+    if let Some(mut entries) = index {
+        entries.sort_by_key(|x| x.hash);
+        let index = DiarObject::PathIndex(ObjPathIndex { entries });
+        let index = write_listing(writer, cfg, &index, encryption)?;
+        metadata.insert(MetadataTag::PathIndex, Metadata::ObjectRef(index));
+    }
+    insert_filters(&mut metadata, filters.compression, encryption);
+    writer.write_object(&DiarObject::Archive(ObjArchive { root: root_dir, metadata }))
+}
+
+/// Records the filters files are written with in the metadata of an archive object.
+fn insert_filters(
+    metadata: &mut MetadataMap,
+    compression: ObjectId,
+    encryption: Option<&EncryptionFilter>,
+) {
+    metadata.insert(MetadataTag::CompressionFilter, Metadata::ObjectRef(compression));
+    if let Some(encryption) = encryption {
+        metadata.insert(MetadataTag::EncryptionFilter, Metadata::ObjectRef(encryption.id));
+    }
+}
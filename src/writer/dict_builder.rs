@@ -3,12 +3,10 @@ use crate::{
     writer::{dir_tree::DirNodeData, DirNode},
 };
 use derive_setters::Setters;
-use gearhash::Table;
 use priority_queue::DoublePriorityQueue;
 use std::{
     borrow::Borrow,
     cmp,
-    collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
 };
 use twox_hash::Xxh3Hash64;
@@ -36,6 +34,9 @@ pub struct BuildSamplesConfiguration {
 
     pub basic_dict_samples_count: usize,
     pub basic_dict_samples_max_size: usize,
+
+    /// The seed used for the hashes that decide which samples are kept.
+    pub seed: u64,
 }
 impl Default for BuildSamplesConfiguration {
     fn default() -> Self {
@@ -46,6 +47,7 @@ impl Default for BuildSamplesConfiguration {
             chunker: ChunkConfig::new(0x0000000000008835, 16, 256), // avg 1/64 chance
             basic_dict_samples_count: 128,
             basic_dict_samples_max_size: 1024 * 32,
+            seed: 0,
         }
     }
 }
@@ -105,6 +107,7 @@ struct ChunkBuilder {
     alloc_cells: Vec<PriorityCell>,
     hash_count: Vec<u64>,
     chunker: ChunkConfig,
+    seed: u64,
 }
 impl ChunkBuilder {
     fn new(cfg: &BuildSamplesConfiguration) -> Self {
@@ -123,6 +126,7 @@ impl ChunkBuilder {
             alloc_cells,
             hash_count: vec![0; cfg.hash_table_size],
             chunker: cfg.chunker,
+            seed: cfg.seed,
         }
     }
 
@@ -131,13 +135,13 @@ impl ChunkBuilder {
             // generate 3 different hashes from the input data chunk; we don't use gear here
             let mut hasher = Xxh3Hash64::with_seed(mask.wrapping_mul(0x092887b6049aa1fd));
             hasher.write(chunk);
-            let mut hash_a = hasher.finish();
+            let hash_a = hasher.finish();
 
-            let mut hasher = DefaultHasher::new();
+            let mut hasher = Xxh3Hash64::with_seed(self.seed);
             hasher.write_u64(hash_a ^ 0x13b75835cec06997);
-            let mut hash_b = hasher.finish();
+            let hash_b = hasher.finish();
             hasher.write_u64(hash_a ^ 0x907c1340fc4f2ba7);
-            let mut hash_c = hasher.finish();
+            let hash_c = hasher.finish();
 
             // find the minimum hash count among the three
             macro_rules! bloom_insert {
@@ -186,7 +190,7 @@ impl ChunkBuilder {
         drop((self.alloc_cells, self.hash_count));
 
         let mut dictionary_data = Vec::new();
-        while let Some((chunk, usage)) = self.priority_queue.pop_max() {
+        while let Some((chunk, _)) = self.priority_queue.pop_max() {
             dictionary_data.extend(&chunk.data);
             if dictionary_data.len() >= max_size {
                 break;
@@ -210,7 +214,7 @@ impl SamplesBuilder {
     fn new(cfg: &BuildSamplesConfiguration) -> SamplesBuilder {
         SamplesBuilder {
             samples: vec![],
-            hash: Xxh3Hash64::with_seed(1234),
+            hash: Xxh3Hash64::with_seed(cfg.seed ^ 1234),
             processed: 0,
             samples_count: cfg.basic_dict_samples_count,
             samples_max_size: cfg.basic_dict_samples_max_size,
@@ -232,8 +236,15 @@ impl SamplesBuilder {
         self.processed += 1;
     }
 
-    fn build_dictionary(mut self, max_size: usize) -> Result<Vec<u8>> {
-        Ok(zstd::dict::from_samples(&self.samples, max_size)?)
+    fn build_dictionary(self, max_size: usize) -> Result<Vec<u8>> {
+        match zstd::dict::from_samples(&self.samples, max_size) {
+            Ok(dict) => Ok(dict),
+            Err(e) => {
+                // zstd refuses to train on too few or too small samples
+                debug!("Could not train dictionary from samples: {e}");
+                Ok(Vec::new())
+            }
+        }
     }
 }
 
@@ -267,6 +278,7 @@ impl BuildSamples {
                     self.add_nodes(node)?;
                 }
             }
+            DirNodeData::SymlinkNode { .. } => {}
         }
         Ok(self)
     }
@@ -1,149 +1,715 @@
-use crate::errors::*;
+use crate::{errors::*, metadata::EntryMetadata};
+use derive_setters::Setters;
+use ignore::{gitignore::Gitignore, overrides::OverrideBuilder, Match};
 use jwalk::WalkDirGeneric;
+use regex::Regex;
 use std::{
-    borrow::Cow,
-    collections::HashMap,
-    fs::File,
-    io::{Read, Write},
+    collections::BTreeMap,
+    fmt::{Debug, Formatter},
+    fs::{File, Metadata},
+    io,
+    io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Options controlling which files are included when building a tree from the filesystem.
+#[derive(Clone, Debug, Default, Setters)]
+#[non_exhaustive]
+pub struct WalkOptions {
+    /// Glob patterns for files to include, in `.gitignore` syntax. If empty, every file is
+    /// included. Otherwise, directories that contain no included files are left out.
+    pub include: Vec<String>,
+    /// Glob patterns for files and directories to exclude, in `.gitignore` syntax.
+    pub exclude: Vec<String>,
+    /// Whether to respect `.gitignore` and `.ignore` files, and skip `.git` directories.
+    pub respect_ignore_files: bool,
+    /// Whether to skip files and directories with names starting with `.`.
+    pub skip_hidden: bool,
+    /// Whether to skip directories containing a `CACHEDIR.TAG` file.
+    pub skip_cache_dirs: bool,
+    /// The maximum depth to descend to. The contents of the root directory are at depth 1.
+    #[setters(strip_option)]
+    pub max_depth: Option<usize>,
+    /// Whether to skip the contents of directories on a different filesystem than the root.
+    pub one_file_system: bool,
+    /// Rules used to rename files, applied in order to each path relative to the root.
+    pub transforms: Vec<PathTransform>,
+    /// A directory to place the tree in, applied after `transforms`.
+    #[setters(into)]
+    pub prefix: String,
+}
+
+/// A rule that renames the paths in a tree, similar to `tar --transform`.
+#[derive(Clone, Debug)]
+pub struct PathTransform {
+    pattern: Regex,
+    replacement: String,
+}
+impl PathTransform {
+    /// Creates a rule replacing the first match of a regular expression in each path.
+    ///
+    /// The replacement may refer to capture groups as `$1` or `${name}`. Paths that are empty
+    /// after replacement are removed from the tree.
+    pub fn new(pattern: &str, replacement: &str) -> Result<PathTransform> {
+        Ok(PathTransform { pattern: Regex::new(pattern)?, replacement: replacement.to_string() })
+    }
+
+    /// Creates a rule replacing a leading directory with another.
+    pub fn prefix(from: &str, to: &str) -> PathTransform {
+        let from = from.trim_matches('/');
+        let to = to.trim_matches('/').replace('$', "$$");
+        let pattern = Regex::new(&format!("^{}(/|$)", regex::escape(from))).unwrap();
+        let replacement = if to.is_empty() {
+            String::new()
+        } else {
+            format!("{to}$1")
+        };
+        PathTransform { pattern, replacement }
+    }
+
+    /// Applies this rule to a path.
+    pub fn apply(&self, path: &str) -> String {
+        self.pattern
+            .replace(path, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// The ignore files that apply to a directory being walked.
+#[derive(Clone, Debug, Default)]
+struct WalkState {
+    ignores: Vec<Arc<Gitignore>>,
+}
+impl WalkState {
+    fn load_ignores(&mut self, dir: &Path) {
+        for name in [".gitignore", ".ignore"] {
+            let path = dir.join(name);
+            if path.is_file() {
+                let (ignore, err) = Gitignore::new(&path);
+                if let Some(err) = err {
+                    warn!("Error in ignore file {}: {err}", path.display());
+                }
+                self.ignores.push(Arc::new(ignore));
+            }
+        }
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // later ignore files are more specific, and take precedence
+        for ignore in self.ignores.iter().rev() {
+            match ignore.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+fn is_cache_dir(path: &Path) -> bool {
+    let mut buf = [0; CACHEDIR_TAG_SIGNATURE.len()];
+    match File::open(path.join("CACHEDIR.TAG")) {
+        Ok(mut file) => file.read_exact(&mut buf).is_ok() && buf == CACHEDIR_TAG_SIGNATURE,
+        Err(_) => false,
+    }
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+#[cfg(not(unix))]
+fn device_id(_: &Metadata) -> Option<u64> {
+    None
+}
+
 #[derive(Debug)]
 pub struct DirNode {
     pub(crate) data: DirNodeData,
+    pub(crate) metadata: EntryMetadata,
 }
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum DirNodeData {
     FileNode { contents: DataSource },
-    DirNode { contents: HashMap<String, DirNode> },
+    DirNode { contents: BTreeMap<String, DirNode> },
+    SymlinkNode { target: String },
 }
 impl DirNode {
-    // TODO: Create files from data sources.
+    fn new(data: DirNodeData) -> DirNode {
+        DirNode { data, metadata: EntryMetadata::default() }
+    }
+
+    /// Creates a new file with the given contents.
+    pub fn file(contents: DataSource) -> DirNode {
+        DirNode::new(DirNodeData::FileNode { contents })
+    }
 
     /// Creates a new empty directory.
     pub fn empty_dir() -> DirNode {
-        DirNode { data: DirNodeData::DirNode { contents: Default::default() } }
+        DirNode::new(DirNodeData::DirNode { contents: Default::default() })
     }
 
-    pub fn add_node(&mut self, name: &str, node: DirNode) {
-        if let DirNodeData::DirNode { contents, .. } = &mut self.data {
-            contents.insert(name.to_string(), node);
-        } else {
-            panic!("Attempted add_node on non-directory node.");
+    /// Creates a new symbolic link pointing to the given path.
+    pub fn symlink(target: impl Into<String>) -> DirNode {
+        DirNode::new(DirNodeData::SymlinkNode { target: target.into() })
+    }
+
+    /// Sets the metadata stored for this node.
+    pub fn with_metadata(mut self, metadata: EntryMetadata) -> DirNode {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Returns the metadata stored for this node.
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+
+    /// Returns the metadata stored for this node.
+    pub fn metadata_mut(&mut self) -> &mut EntryMetadata {
+        &mut self.metadata
+    }
+
+    /// Returns whether this node is a directory.
+    pub fn is_dir(&self) -> bool {
+        matches!(self.data, DirNodeData::DirNode { .. })
+    }
+
+    /// Returns whether this node is a file.
+    pub fn is_file(&self) -> bool {
+        matches!(self.data, DirNodeData::FileNode { .. })
+    }
+
+    /// Returns whether this node is a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.data, DirNodeData::SymlinkNode { .. })
+    }
+
+    /// Returns the contents of this node, if it is a file.
+    pub fn contents(&self) -> Option<&DataSource> {
+        match &self.data {
+            DirNodeData::FileNode { contents } => Some(contents),
+            _ => None,
+        }
+    }
+
+    /// Returns the target of this node, if it is a symbolic link.
+    pub fn symlink_target(&self) -> Option<&str> {
+        match &self.data {
+            DirNodeData::SymlinkNode { target } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Adds a node directly to this directory, replacing any existing node with the same name.
+    pub fn add_node(&mut self, name: &str, node: DirNode) -> Result<Option<DirNode>> {
+        ensure(is_valid_name(name), &"invalid file name")?;
+        match &mut self.data {
+            DirNodeData::DirNode { contents } => Ok(contents.insert(name.to_string(), node)),
+            _ => error(&"cannot add a node to a file"),
+        }
+    }
+
+    /// Returns the node at a `/`-separated path relative to this node.
+    pub fn get(&self, path: &str) -> Option<&DirNode> {
+        let mut node = self;
+        for name in split_path(path) {
+            match &node.data {
+                DirNodeData::DirNode { contents } => node = contents.get(name)?,
+                _ => return None,
+            }
+        }
+        Some(node)
+    }
+
+    /// Returns the node at a `/`-separated path relative to this node.
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut DirNode> {
+        let mut node = self;
+        for name in split_path(path) {
+            match &mut node.data {
+                DirNodeData::DirNode { contents } => node = contents.get_mut(name)?,
+                _ => return None,
+            }
         }
+        Some(node)
     }
 
+    /// Inserts a node at a `/`-separated path, creating any missing parent directories.
+    ///
+    /// Returns the node previously at that path, if any.
+    pub fn insert(&mut self, path: &str, node: DirNode) -> Result<Option<DirNode>> {
+        let (parent, name) = split_parent(path)?;
+        // Check every component before creating any, so a failed insert leaves the tree unchanged.
+        ensure(split_path(parent).all(is_valid_name), &"invalid file name")?;
+        let mut dir = self;
+        for component in split_path(parent) {
+            let DirNodeData::DirNode { contents } = &mut dir.data else {
+                return error(&"parent path is a file");
+            };
+            dir = contents
+                .entry(component.to_string())
+                .or_insert_with(DirNode::empty_dir);
+        }
+        dir.add_node(name, node)
+    }
+
+    /// Removes the node at a `/`-separated path, returning it.
+    pub fn remove(&mut self, path: &str) -> Result<DirNode> {
+        let (parent, name) = split_parent(path)?;
+        match self.get_mut(parent).map(|x| &mut x.data) {
+            Some(DirNodeData::DirNode { contents }) => match contents.remove(name) {
+                Some(node) => Ok(node),
+                None => error(&"path does not exist"),
+            },
+            Some(_) => error(&"parent path is a file"),
+            None => error(&"path does not exist"),
+        }
+    }
+
+    /// Moves the node at `from` to `to`, creating any missing parent directories.
+    ///
+    /// This fails if a node already exists at `to`.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        ensure(self.get(to).is_none(), &"destination path already exists")?;
+        let (mut from_iter, mut to_iter) = (split_path(from), split_path(to));
+        let is_subpath = loop {
+            match (from_iter.next(), to_iter.next()) {
+                (Some(a), Some(b)) if a == b => {}
+                (None, _) => break true,
+                _ => break false,
+            }
+        };
+        ensure(!is_subpath, &"cannot move a directory into itself")?;
+
+        // Check the destination before detaching the node, so a failed rename leaves the tree
+        // unchanged.
+        let (parent, _) = split_parent(to)?;
+        let mut dir = Some(&*self);
+        for component in split_path(parent) {
+            ensure(is_valid_name(component), &"invalid file name")?;
+            dir = match dir.map(|x| &x.data) {
+                Some(DirNodeData::DirNode { contents }) => contents.get(component),
+                Some(_) => return error(&"parent path is a file"),
+                None => None,
+            };
+        }
+        if let Some(dir) = dir {
+            ensure(dir.is_dir(), &"parent path is a file")?;
+        }
+
+        let node = self.remove(from)?;
+        self.insert(to, node)?;
+        Ok(())
+    }
+
+    /// Overlays another tree on top of this one.
+    ///
+    /// Directories present in both trees are merged recursively. Otherwise, nodes from `other`
+    /// replace the nodes at the same path in this tree.
+    pub fn merge(&mut self, other: DirNode) {
+        if !other.is_dir() || !other.metadata.is_empty() {
+            self.metadata = other.metadata;
+        }
+        match (&mut self.data, other.data) {
+            (DirNodeData::DirNode { contents }, DirNodeData::DirNode { contents: other }) => {
+                for (name, node) in other {
+                    match contents.get_mut(&name) {
+                        Some(existing) => existing.merge(node),
+                        None => {
+                            contents.insert(name, node);
+                        }
+                    }
+                }
+            }
+            (_, data) => self.data = data,
+        }
+    }
+
+    /// Removes every node for which `f` returns `false`, given its full path.
+    ///
+    /// When a directory is removed, its children are not visited.
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &DirNode) -> bool) {
+        fn retain_at(
+            node: &mut DirNode,
+            prefix: &str,
+            f: &mut impl FnMut(&str, &DirNode) -> bool,
+        ) {
+            if let DirNodeData::DirNode { contents } = &mut node.data {
+                contents.retain(|name, node| {
+                    let path = join_path(prefix, name);
+                    if f(&path, node) {
+                        retain_at(node, &path, f);
+                        true
+                    } else {
+                        false
+                    }
+                });
+            }
+        }
+        retain_at(self, "", &mut f)
+    }
+
+    /// Inserts a tree at a `/`-separated path, merging it with any directory already there.
+    ///
+    /// This allows trees built from several directories to be combined into one archive.
+    pub fn mount(&mut self, path: &str, node: DirNode) -> Result<()> {
+        match self.get_mut(path) {
+            Some(existing) => existing.merge(node),
+            None => {
+                self.insert(path, node)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds this tree with every file, symlink and empty directory moved to a new path.
+    ///
+    /// Directories with metadata are moved alongside their contents.
+    ///
+    /// Nodes for which `f` returns `None` are removed. It is an error for two nodes to be moved
+    /// to the same path.
+    pub fn map_paths(self, mut f: impl FnMut(&str) -> Option<String>) -> Result<DirNode> {
+        fn collect_leaves(node: DirNode, path: String, leaves: &mut Vec<(String, DirNode)>) {
+            match node.data {
+                DirNodeData::DirNode { contents } if !contents.is_empty() => {
+                    if !node.metadata.is_empty() {
+                        leaves.push((
+                            path.clone(),
+                            DirNode::empty_dir().with_metadata(node.metadata),
+                        ));
+                    }
+                    for (name, child) in contents {
+                        collect_leaves(child, join_path(&path, &name), leaves);
+                    }
+                }
+                data => leaves.push((path, DirNode { data, metadata: node.metadata })),
+            }
+        }
+        let mut leaves = Vec::new();
+        collect_leaves(self, String::new(), &mut leaves);
+
+        let mut tree = DirNode::empty_dir();
+        for (path, node) in leaves {
+            if path.is_empty() {
+                // the tree itself is an empty directory
+                continue;
+            }
+            let Some(path) = f(&path) else { continue };
+            if split_path(&path).next().is_none() {
+                ensure(node.is_dir(), &"a file cannot be moved to the root directory")?;
+                continue;
+            }
+            match tree.get_mut(&path) {
+                Some(existing) if existing.is_dir() && node.is_dir() => existing.merge(node),
+                Some(_) => return error(&"multiple nodes were moved to the same path"),
+                None => {
+                    tree.insert(&path, node)?;
+                }
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Iterates over every node in this tree alongside its full path, in sorted order.
+    ///
+    /// Directories are returned before their contents, and the node itself is not returned.
+    pub fn walk(&self) -> Walk<'_> {
+        let mut walk = Walk { stack: Vec::new() };
+        walk.push_children("", self);
+        walk
+    }
+
+    /// Builds a tree from the contents of a directory on the filesystem.
     pub fn from_path(path: impl AsRef<Path>) -> Result<DirNode> {
+        Self::from_path_with_options(path, &WalkOptions::default())
+    }
+
+    /// Builds a tree from the contents of a directory on the filesystem, filtering the files
+    /// included with the given options.
+    pub fn from_path_with_options(
+        path: impl AsRef<Path>,
+        options: &WalkOptions,
+    ) -> Result<DirNode> {
         let path = std::fs::canonicalize(path.as_ref())?;
         let path = path.as_path();
         trace!("Building directory tree for {}...", path.display());
 
+        let mut overrides = OverrideBuilder::new(path);
+        for glob in &options.include {
+            overrides.add(glob)?;
+        }
+        for glob in &options.exclude {
+            overrides.add(&format!("!{glob}"))?;
+        }
+        let overrides = overrides.build()?;
+        let root_device = device_id(&std::fs::metadata(path)?);
+
         // Find all directories and files in the paths.
-        let data: jwalk::Result<Vec<_>> = WalkDirGeneric::<(PathBuf, PathBuf)>::new(path)
+        let walk_options = options.clone();
+        let mut walk = WalkDirGeneric::<(WalkState, ())>::new(path)
             .follow_links(false)
-            .skip_hidden(false)
+            .skip_hidden(options.skip_hidden)
             .sort(true)
-            .root_read_dir_state(PathBuf::new())
-            .process_read_dir(|_depth, _path, read_dir_state, children| {
-                for child in children {
-                    if let Ok(child) = child {
-                        let mut new_path = read_dir_state.clone();
-                        new_path.push(&child.file_name);
-                        child.client_state = new_path;
-                    }
+            .process_read_dir(move |depth, dir, state, children| {
+                if depth.is_none() {
+                    // this is the root entry itself, which is never filtered
+                    return;
                 }
-            })
-            .into_iter()
-            .collect();
+                if walk_options.respect_ignore_files {
+                    state.load_ignores(dir);
+                }
+                children.retain_mut(|child| {
+                    let Ok(child) = child else {
+                        return true;
+                    };
+                    let path = child.path();
+                    let is_dir = child.file_type().is_dir();
+
+                    if overrides.matched(&path, is_dir).is_ignore() {
+                        return false;
+                    }
+                    if walk_options.respect_ignore_files
+                        && ((is_dir && child.file_name == ".git")
+                            || state.is_ignored(&path, is_dir))
+                    {
+                        return false;
+                    }
+                    if is_dir && walk_options.skip_cache_dirs && is_cache_dir(&path) {
+                        return false;
+                    }
+                    if is_dir && walk_options.one_file_system {
+                        let device = child.metadata().ok().and_then(|x| device_id(&x));
+                        if device != root_device {
+                            child.read_children_path = None;
+                        }
+                    }
+                    true
+                });
+            });
+        if let Some(max_depth) = options.max_depth {
+            walk = walk.max_depth(max_depth);
+        }
+        let data: jwalk::Result<Vec<_>> = walk.into_iter().collect();
         let data = data?;
 
         // Convert the linear directory data into the tree model.
         #[derive(Debug)]
-        struct DirStack(Vec<(String, DirNode)>);
+        struct DirStack(Vec<(String, DirNode)>, bool);
         impl DirStack {
             fn enter_dir(&mut self, name: String) {
                 self.0.push((name.to_string(), DirNode::empty_dir()));
             }
-            fn push_file(&mut self, name: String, node: DirNode) {
+            fn push_file(&mut self, name: String, node: DirNode) -> Result<()> {
                 match self.0.last_mut() {
-                    Some(x) => x.1.add_node(&name, node),
+                    Some(x) => {
+                        x.1.add_node(&name, node)?;
+                    }
                     None => self.0.push((name.to_string(), node)),
                 }
+                Ok(())
             }
-            fn pop_node(&mut self) {
+            fn pop_node(&mut self) -> Result<()> {
                 let (name, node) = self.0.pop().expect("pop_node on root node");
-                self.push_file(name, node);
+                let is_empty_dir = matches!(
+                    &node.data,
+                    DirNodeData::DirNode { contents } if contents.is_empty()
+                );
+                if self.1 && is_empty_dir {
+                    return Ok(());
+                }
+                self.push_file(name, node)
             }
         }
 
-        let mut dirs_stack = DirStack(Vec::new());
+        let mut dirs_stack = DirStack(Vec::new(), !options.include.is_empty());
         for t in data {
             let mut path = t.parent_path.to_path_buf();
-            path.push(t.file_name);
+            path.push(&t.file_name);
 
-            if t.depth < dirs_stack.0.len() {
-                dirs_stack.pop_node();
+            while t.depth < dirs_stack.0.len() {
+                dirs_stack.pop_node()?;
             }
 
-            let name = path.file_name().unwrap().to_str().unwrap().into(); // TODO: unwraps
+            let Some(name) = t.file_name.to_str() else {
+                return Error::non_utf8_name(path);
+            };
+            let name = name.to_string();
             if path.is_dir() {
                 dirs_stack.enter_dir(name);
             } else if path.is_file() {
-                dirs_stack.push_file(name, DirNode {
-                    data: DirNodeData::FileNode { contents: DataSource::from_path(&path)? },
-                });
+                dirs_stack.push_file(name, DirNode::file(DataSource::from_path(&path)?))?;
             } else {
                 warn!("Path {} is of unknown type!", path.display());
             }
         }
         while dirs_stack.0.len() > 1 {
-            dirs_stack.pop_node()
+            dirs_stack.pop_node()?;
         }
 
-        Ok(dirs_stack.0.pop().expect("Dir stack is empty?").1)
+        let mut tree = dirs_stack.0.pop().expect("Dir stack is empty?").1;
+        if !options.transforms.is_empty() {
+            tree = tree.map_paths(|path| {
+                let path = options
+                    .transforms
+                    .iter()
+                    .fold(path.to_string(), |path, transform| transform.apply(&path));
+                (!path.is_empty()).then_some(path)
+            })?;
+        }
+        if split_path(&options.prefix).next().is_some() {
+            let mut root = DirNode::empty_dir();
+            root.insert(&options.prefix, tree)?;
+            tree = root;
+        }
+        Ok(tree)
     }
 }
 
-#[derive(Debug)]
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|x| !x.is_empty())
+}
+fn split_parent(path: &str) -> Result<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    ensure(is_valid_name(name), &"invalid file name")?;
+    Ok((parent, name))
+}
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
+
+/// An iterator over the nodes of a tree, returned by [`DirNode::walk`].
+pub struct Walk<'a> {
+    stack: Vec<(String, &'a DirNode)>,
+}
+impl<'a> Walk<'a> {
+    fn push_children(&mut self, prefix: &str, node: &'a DirNode) {
+        if let DirNodeData::DirNode { contents } = &node.data {
+            for (name, child) in contents.iter().rev() {
+                self.stack.push((join_path(prefix, name), child));
+            }
+        }
+    }
+}
+impl<'a> Iterator for Walk<'a> {
+    type Item = (String, &'a DirNode);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, node) = self.stack.pop()?;
+        self.push_children(&path, node);
+        Some((path, node))
+    }
+}
+
+/// A function that opens a new stream over the contents of a file.
+pub type OpenFn = Box<dyn Fn() -> io::Result<Box<dyn Read>> + Send + Sync>;
+
 pub enum DataSource {
     Path { path: PathBuf, len_hint: u64 },
     Data { path_hint: PathBuf, data: Vec<u8> },
+    Range { path: PathBuf, offset: u64, len: u64 },
+    Reader { open: OpenFn, len_hint: u64 },
 }
 
 impl DataSource {
+    /// Reads the contents of a file on disk.
     pub fn from_path(path: &Path) -> Result<DataSource> {
         let path = path.to_path_buf();
         let len_hint = path.metadata()?.len();
         Ok(DataSource::Path { path, len_hint })
     }
 
+    /// Uses data held in memory.
+    pub fn from_data(data: impl Into<Vec<u8>>) -> DataSource {
+        DataSource::Data { path_hint: PathBuf::new(), data: data.into() }
+    }
+
+    /// Reads `len` bytes starting at `offset` in a file on disk.
+    pub fn from_range(path: &Path, offset: u64, len: u64) -> Result<DataSource> {
+        let file_len = path.metadata()?.len();
+        ensure(
+            offset.checked_add(len).is_some_and(|end| end <= file_len),
+            &"range is past the end of the file",
+        )?;
+        Ok(DataSource::Range { path: path.to_path_buf(), offset, len })
+    }
+
+    /// Reads from a stream created by `open`, which is called every time the contents are needed.
+    pub fn from_reader(
+        len_hint: u64,
+        open: impl Fn() -> io::Result<Box<dyn Read>> + Send + Sync + 'static,
+    ) -> DataSource {
+        DataSource::Reader { open: Box::new(open), len_hint }
+    }
+
     pub fn len_hint(&self) -> u64 {
         match self {
             DataSource::Path { len_hint, .. } => *len_hint,
             DataSource::Data { data, .. } => data.len() as u64,
+            DataSource::Range { len, .. } => *len,
+            DataSource::Reader { len_hint, .. } => *len_hint,
         }
     }
 
-    pub fn push_to_vec(&self, vec: &mut Vec<u8>) -> Result<()> {
-        match self {
-            DataSource::Path { path, .. } => {
-                File::open(path)?.read_to_end(vec)?;
+    fn open(&self) -> Result<Box<dyn Read + '_>> {
+        Ok(match self {
+            DataSource::Path { path, .. } => Box::new(File::open(path)?),
+            DataSource::Data { data, .. } => Box::new(data.as_slice()),
+            DataSource::Range { path, offset, len } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(*offset))?;
+                Box::new(file.take(*len))
             }
-            DataSource::Data { data, .. } => {
-                vec.extend_from_slice(data);
-            }
-        }
+            DataSource::Reader { open, .. } => open()?,
+        })
+    }
+
+    pub fn push_to_vec(&self, vec: &mut Vec<u8>) -> Result<()> {
+        self.open()?.read_to_end(vec)?;
         Ok(())
     }
-    pub fn write_to_stream(&self, out: &mut impl Write) -> Result<()> {
+    pub fn write_to_stream(&self, out: &mut (impl Write + ?Sized)) -> Result<()> {
+        std::io::copy(&mut self.open()?, out)?;
+        Ok(())
+    }
+}
+impl Debug for DataSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            DataSource::Path { path, .. } => {
-                std::io::copy(&mut File::open(path)?, out)?;
-            }
-            DataSource::Data { data, .. } => {
-                out.write_all(data)?;
+            DataSource::Path { path, len_hint } => f
+                .debug_struct("Path")
+                .field("path", path)
+                .field("len_hint", len_hint)
+                .finish(),
+            DataSource::Data { path_hint, data } => {
+                let len = data.len();
+                f.debug_struct("Data")
+                    .field("path_hint", path_hint)
+                    .field("len", &len)
+                    .finish()
             }
+            DataSource::Range { path, offset, len } => f
+                .debug_struct("Range")
+                .field("path", path)
+                .field("offset", offset)
+                .field("len", len)
+                .finish(),
+            DataSource::Reader { len_hint, .. } => f
+                .debug_struct("Reader")
+                .field("len_hint", len_hint)
+                .finish_non_exhaustive(),
         }
-        Ok(())
     }
 }
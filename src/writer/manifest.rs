@@ -0,0 +1,133 @@
+use crate::{errors::*, metadata::EntryMetadata, writer::dir_tree::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// The type of an entry in a manifest.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestEntryType {
+    File,
+    Dir,
+    Symlink,
+    /// An object of a type this version does not understand, only reported by the reader.
+    ///
+    /// Manifests that use this type are rejected when parsed.
+    #[serde(skip_deserializing)]
+    Unknown,
+}
+
+/// An entry in a manifest, describing a single path in the archive.
+///
+/// In JSON, the fields of [`EntryMetadata`] are given directly in the entry:
+///
+/// ```json
+/// {"path": "usr/bin/tool", "type": "file", "source": "build/tool", "mode": "755", "uid": 0}
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ManifestEntry {
+    /// The `/`-separated path of the entry in the archive.
+    pub path: String,
+    /// The type of the entry.
+    #[serde(rename = "type")]
+    pub kind: ManifestEntryType,
+    /// The file to read the contents of a file entry from.
+    ///
+    /// Relative paths are resolved against the directory containing the manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// The contents of a file entry, given inline.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// The target of a symlink entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// The metadata stored for this entry.
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
+}
+impl ManifestEntry {
+    fn to_node(&self, base: &Path) -> Result<DirNode> {
+        let node = match (self.kind, &self.source, &self.data, &self.target) {
+            (ManifestEntryType::File, Some(source), None, None) => {
+                DirNode::file(DataSource::from_path(&base.join(source))?)
+            }
+            (ManifestEntryType::File, None, Some(data), None) => {
+                DirNode::file(DataSource::from_data(data.as_bytes()))
+            }
+            (ManifestEntryType::File, ..) => {
+                return error(&"file entries must have exactly one of `source` and `data`")
+            }
+            (ManifestEntryType::Dir, None, None, None) => DirNode::empty_dir(),
+            (ManifestEntryType::Dir, ..) => {
+                return error(&"directory entries cannot have contents")
+            }
+            (ManifestEntryType::Symlink, None, None, Some(target)) => DirNode::symlink(target),
+            (ManifestEntryType::Symlink, ..) => {
+                return error(&"symlink entries must have only a `target`")
+            }
+            (ManifestEntryType::Unknown, ..) => {
+                return error(&"entries of unknown type cannot be added to an archive")
+            }
+        };
+        Ok(node.with_metadata(self.metadata.clone()))
+    }
+}
+
+/// A list of paths to build an archive from, as an alternative to scanning a directory.
+///
+/// Manifests are stored as JSON, with one [`ManifestEntry`] object per line. Parent directories
+/// are created as needed, and may be listed explicitly to give them metadata.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+impl Manifest {
+    /// Parses a manifest from a stream.
+    pub fn parse(stream: impl Read) -> Result<Manifest> {
+        let mut entries = Vec::new();
+        for entry in serde_json::Deserializer::from_reader(stream).into_iter() {
+            entries.push(entry?);
+        }
+        Ok(Manifest { entries })
+    }
+
+    /// Builds a tree from the entries of this manifest.
+    ///
+    /// Relative source paths are resolved against `base`.
+    pub fn to_tree(&self, base: &Path) -> Result<DirNode> {
+        let mut tree = DirNode::empty_dir();
+        for entry in &self.entries {
+            let node = entry.to_node(base)?;
+            // empty paths and "/" would otherwise replace the root, or be reported as duplicates
+            if !node.is_dir() && entry.path.split('/').all(str::is_empty) {
+                return Error::manifest_root_entry(&entry.path);
+            }
+            match tree.get_mut(&entry.path) {
+                Some(existing) if existing.is_dir() && node.is_dir() => {
+                    *existing.metadata_mut() = node.metadata().clone();
+                }
+                Some(_) => return error(&"path is listed more than once in manifest"),
+                None => {
+                    tree.insert(&entry.path, node)?;
+                }
+            }
+        }
+        Ok(tree)
+    }
+}
+
+impl DirNode {
+    /// Builds a tree from a manifest file.
+    ///
+    /// Relative source paths are resolved against the directory containing the manifest.
+    pub fn from_manifest(path: impl AsRef<Path>) -> Result<DirNode> {
+        let path = path.as_ref();
+        let manifest = Manifest::parse(BufReader::new(File::open(path)?))?;
+        manifest.to_tree(path.parent().unwrap_or(Path::new("")))
+    }
+}
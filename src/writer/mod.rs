@@ -1,7 +1,18 @@
+mod append;
 pub mod content_hash;
 mod diar_builder;
 mod dict_builder;
 mod dir_tree;
+mod manifest;
+mod repack;
+mod snapshot;
 
-pub use diar_builder::compress;
-pub use dir_tree::DirNode;
+pub use append::{append_nodes_to_file, append_to_file};
+pub use diar_builder::{
+    compress, compress_nodes, compress_nodes_to_file, compress_to_file, compress_with_config,
+    CompressConfiguration,
+};
+pub use dir_tree::{DataSource, DirNode, OpenFn, PathTransform, Walk, WalkOptions};
+pub use manifest::{Manifest, ManifestEntry, ManifestEntryType};
+pub use repack::{repack_file, RepackConfiguration, RepackReport};
+pub use snapshot::{snapshot_nodes_to_file, snapshot_to_file};
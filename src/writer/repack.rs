@@ -0,0 +1,521 @@
+use crate::{
+    errors::*,
+    object_io::DiarIo,
+    objects::*,
+    reader::{object_ref, ArchiveReader, ReaderConfiguration, SnapshotInfo},
+    writer::{
+        diar_builder::*,
+        dict_builder::{BuildSamples, BuildSamplesConfiguration},
+        dir_tree::DirNode,
+        snapshot::civil_date,
+    },
+};
+use derive_setters::Setters;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io,
+    io::{BufWriter, Read, Seek, Write},
+    path::Path,
+};
+use zstd::dict::EncoderDictionary;
+
+/// Maps a time in seconds since the Unix epoch to the period containing it.
+type Period = fn(i64) -> i64;
+
+/// How much of the start of each file the new dictionary is trained on.
+const TRAINING_SAMPLE_LEN: u64 = 1 << 20;
+
+/// The snapshots [`repack_file`] keeps, and how it stores their files.
+///
+/// A snapshot is kept if any rule selects it. `keep_last` keeps that many of the latest
+/// snapshots, and each other rule keeps the latest snapshot of that many of the latest hours,
+/// days, weeks, months or years that have one, in UTC. If no rule is set, every snapshot is kept.
+#[derive(Clone, Debug, Default, Setters)]
+#[non_exhaustive]
+pub struct RepackConfiguration {
+    pub keep_last: u32,
+    pub keep_hourly: u32,
+    pub keep_daily: u32,
+    /// Weeks start on Monday.
+    pub keep_weekly: u32,
+    pub keep_monthly: u32,
+    pub keep_yearly: u32,
+    /// Whether to train a new dictionary on the files that are kept and compress them again with
+    /// it, rather than copying them as they are stored.
+    pub recompress: bool,
+}
+
+/// The result of [`repack_file`].
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct RepackReport {
+    /// The names of the snapshots that were kept.
+    pub kept: Vec<String>,
+    /// The names of the snapshots that were removed.
+    pub removed: Vec<String>,
+    /// The length of the archive file before repacking.
+    pub old_length: u64,
+    /// The length of the archive file after repacking.
+    pub new_length: u64,
+}
+
+/// Rewrites an archive file with only the objects that are still used, removing the snapshots
+/// the retention rules do not keep.
+///
+/// The snapshot the archive is opened to is always kept, as are snapshots without a recorded
+/// creation time. The contents of the kept snapshots are copied to a new file, which replaces the
+/// archive once it is complete, leaving out the objects only used by removed snapshots, files and
+/// directories replaced by appending, and checkpoints. If repacking fails, the archive is left
+/// unchanged.
+///
+/// The configuration must use the same encryption key as the archive, and is otherwise applied
+/// as when compressing, except that the archive keeps its object hashes, sync markers, path index
+/// and encrypted listings. The archive is only signed again if `signing_key` is set, and any
+/// recovery record must be added again afterwards. Archives that do not record the filters their
+/// files were written with are always recompressed.
+pub fn repack_file(
+    archive: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+    repack: &RepackConfiguration,
+) -> Result<RepackReport> {
+    let path = archive.as_ref();
+    let mut cfg = prepare_config(cfg)?;
+    let mut reader_cfg = ReaderConfiguration::default();
+    if let Some(key) = &cfg.encryption_key {
+        reader_cfg = reader_cfg.decryption_key(key.clone());
+    }
+    let mut reader = ArchiveReader::open_with_config(path, &reader_cfg)?;
+
+    let old_format = reader.format();
+    let encrypted = old_format.required_features & FormatInfo::REQUIRED_ENCRYPTION != 0;
+    ensure(
+        encrypted == cfg.encryption_key.is_some(),
+        &"archive does not match the encryption settings",
+    )?;
+    cfg.hash_objects |= reader.is_hashed();
+    cfg.sync_markers |= old_format.optional_features & FormatInfo::OPTIONAL_SYNC_MARKERS != 0;
+    cfg.path_index |= reader.has_path_index();
+    cfg.encrypt_listings |= reader.has_sealed_listings()?;
+    let cfg = &cfg;
+
+    // the archive keeps the features that describe its contents
+    let mut format = format_info(&DirNode::empty_dir(), cfg);
+    format.required_features |= old_format.required_features;
+    format.optional_features |= old_format.optional_features
+        & (FormatInfo::OPTIONAL_ENTRY_METADATA | FormatInfo::OPTIONAL_SNAPSHOTS);
+
+    let root = reader.root_object().clone();
+    let snapshots = reader.snapshots()?;
+    let kept = select_snapshots(&snapshots, repack);
+    let mut report = RepackReport::default();
+    for snapshot in snapshots {
+        match kept.contains(&snapshot.name) {
+            true => report.kept.push(snapshot.name),
+            false => report.removed.push(snapshot.name),
+        }
+    }
+    report.old_length = std::fs::metadata(path)?.len();
+
+    let partial = partial_path(path);
+    let file = BufWriter::new(create_partial(&partial)?);
+    let written = write_repacked(&mut reader, file, cfg, &format, &root, &kept, repack.recompress);
+    drop(reader);
+    match written {
+        Ok(file) => finish_partial(file, &partial, path)?,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+    }
+    report.new_length = std::fs::metadata(path)?.len();
+    Ok(report)
+}
+
+/// Writes the kept snapshots of an archive to a new file.
+fn write_repacked<R: Read + Seek, W: Write>(
+    reader: &mut ArchiveReader<R>,
+    file: W,
+    cfg: &CompressConfiguration,
+    format: &FormatInfo,
+    root: &ObjRoot,
+    kept: &BTreeSet<String>,
+    recompress: bool,
+) -> Result<W> {
+    let mut writer = DiarIo::create(file, hash_objects(cfg), format)?;
+    let mut state =
+        Repack { reader, copied: HashMap::new(), ancestors: HashSet::new(), recompress };
+    let archives: Vec<ObjectId> = root
+        .alt
+        .iter()
+        .filter(|(name, id)| kept.contains(*name) && **id != root.main)
+        .map(|(_, id)| *id)
+        .chain([root.main])
+        .collect();
+    let filters = state.filters(&mut writer, cfg, root.main, &archives)?;
+    let dict = EncoderDictionary::new(&filters.dictionary, LEVEL);
+    let mut tree = TreeWriter {
+        cfg,
+        filter_obj: filters.compression,
+        dict: &dict,
+        encryption: filters.encryption.as_ref(),
+        index: Vec::new(),
+        resumed: HashMap::new(),
+        checkpoint: None,
+        contents: None,
+    };
+
+    trace!("Copying snapshots...");
+    let mut alt = BTreeMap::new();
+    let mut main_name = None;
+    for (name, id) in &root.alt {
+        if *id == root.main {
+            main_name = Some(name.clone());
+        } else if kept.contains(name) {
+            let (root_dir, index, metadata) = state.copy_archive(&mut writer, &mut tree, *id)?;
+            let archive =
+                write_archive_object(&mut writer, cfg, &filters, root_dir, index, metadata)?;
+            alt.insert(name.clone(), archive);
+        }
+    }
+    let (root_dir, index, metadata) = state.copy_archive(&mut writer, &mut tree, root.main)?;
+    trace!(" - Done!");
+
+    let extras = ArchiveExtras { metadata, alt, name: main_name };
+    finish_archive(&mut writer, cfg, &filters, root_dir, index, extras)?;
+    Ok(writer.into_inner())
+}
+
+/// Returns the names of the snapshots the retention rules keep.
+fn select_snapshots(snapshots: &[SnapshotInfo], cfg: &RepackConfiguration) -> BTreeSet<String> {
+    let rules: [(u32, Period); 5] = [
+        (cfg.keep_hourly, |time| time.div_euclid(3600)),
+        (cfg.keep_daily, |time| time.div_euclid(86400)),
+        // the Unix epoch was a Thursday
+        (cfg.keep_weekly, |time| (time.div_euclid(86400) + 3).div_euclid(7)),
+        (cfg.keep_monthly, |time| {
+            let (year, month, _) = civil_date(time);
+            year * 12 + month
+        }),
+        (cfg.keep_yearly, |time| civil_date(time).0),
+    ];
+    if cfg.keep_last == 0 && rules.iter().all(|(count, _)| *count == 0) {
+        return snapshots.iter().map(|x| x.name.clone()).collect();
+    }
+
+    let mut kept: BTreeSet<String> = snapshots
+        .iter()
+        .filter(|x| x.is_main || x.created.is_none())
+        .map(|x| x.name.clone())
+        .collect();
+    let mut dated: Vec<(i64, &str)> = snapshots
+        .iter()
+        .filter_map(|x| Some((x.created?, x.name.as_str())))
+        .collect();
+    dated.sort_by(|a, b| b.cmp(a));
+
+    kept.extend(
+        dated
+            .iter()
+            .take(cfg.keep_last as usize)
+            .map(|x| x.1.to_string()),
+    );
+    for (count, period) in rules {
+        let mut last = None;
+        let mut periods = 0;
+        for (time, name) in &dated {
+            if periods == count {
+                break;
+            }
+            if last != Some(period(*time)) {
+                last = Some(period(*time));
+                periods += 1;
+                kept.insert(name.to_string());
+            }
+        }
+    }
+    kept
+}
+
+/// The state used while copying the contents of an archive to a new file.
+struct Repack<'a, R> {
+    reader: &'a mut ArchiveReader<R>,
+    /// The objects already written to the new file, by their id in `reader`.
+    copied: HashMap<ObjectId, ObjectId>,
+    /// The directories being copied, to reject directories that contain themselves.
+    ancestors: HashSet<ObjectId>,
+    recompress: bool,
+}
+impl<R: Read + Seek> Repack<'_, R> {
+    /// Writes the filters files are written with, copying those of the archive the root object
+    /// points to unless files are recompressed.
+    fn filters<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        cfg: &CompressConfiguration,
+        main: ObjectId,
+        archives: &[ObjectId],
+    ) -> Result<Filters> {
+        let metadata = self.reader.read_archive(main)?.metadata;
+        let compression = object_ref(&metadata, MetadataTag::CompressionFilter)?;
+        let Some(compression) = compression.filter(|_| !self.recompress) else {
+            self.recompress = true;
+            let dictionary = self.train(cfg, archives)?;
+            return write_dictionary_filters(writer, dictionary, cfg);
+        };
+
+        let DiarObject::FilterZstd(zstd) = self.reader.read_stored(compression)? else {
+            return corrupt(&"compression filter is not a zstd filter");
+        };
+        let dictionary = self
+            .reader
+            .load_dict(compression, &zstd.dict_sources)?
+            .to_vec();
+        let encryption = object_ref(&metadata, MetadataTag::EncryptionFilter)?;
+        let encryption = match (encryption, &cfg.encryption_key) {
+            (Some(id), Some(key)) => {
+                let DiarObject::FilterXChaCha20Poly1305(filter) = self.reader.read_stored(id)?
+                else {
+                    return corrupt(&"encryption filter is not an encryption filter");
+                };
+                let id = self.copy_filter(writer, id)?;
+                Some(EncryptionFilter::from_filter(id, key, &filter)?)
+            }
+            (None, None) => None,
+            _ => return error(&"archive does not match the encryption settings"),
+        };
+        let compression = self.copy_filter(writer, compression)?;
+        Ok(Filters { dictionary, compression, encryption })
+    }
+
+    /// Trains a compression dictionary on the files of the given archives.
+    fn train(&mut self, cfg: &CompressConfiguration, archives: &[ObjectId]) -> Result<Vec<u8>> {
+        trace!("Building samples...");
+        let samples_cfg = BuildSamplesConfiguration::default().seed(cfg.training_seed);
+        let mut samples = BuildSamples::new(&samples_cfg);
+        let mut visited = HashSet::new();
+        let mut pending = Vec::new();
+        for id in archives {
+            pending.push(self.reader.read_archive(*id)?.root);
+        }
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            match self.reader.read_object(id)? {
+                DiarObject::Directory(dir) => {
+                    pending.extend(dir.entries.iter().rev().map(|x| x.data));
+                }
+                DiarObject::BlobPlain(_) => {
+                    let mut data = Vec::new();
+                    let stream = self.reader.open_file(id)?;
+                    stream
+                        .take(TRAINING_SAMPLE_LEN)
+                        .read_to_end(&mut data)
+                        .map_err(Error::decompression_failed)?;
+                    samples.push_file(&data);
+                }
+                _ => {}
+            }
+        }
+
+        trace!("Building dictionary...");
+        samples.build_dictionary()
+    }
+
+    /// Copies the tree of an archive object, returning its root directory, path index and
+    /// metadata.
+    fn copy_archive<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &mut TreeWriter,
+        id: ObjectId,
+    ) -> Result<(ObjectId, Option<Vec<PathIndexEntry>>, MetadataMap)> {
+        let archive = self.reader.read_archive(id)?;
+        let root_dir = self.copy_node(writer, tree, archive.root, "")?;
+        let index = std::mem::take(&mut tree.index);
+
+        let mut metadata = MetadataMap::default();
+        if let Some(time) = archive.metadata.get(&MetadataTag::CreationTime) {
+            metadata.insert(MetadataTag::CreationTime, time.clone());
+        }
+        if object_ref(&archive.metadata, MetadataTag::ContentIndex)?.is_some() {
+            let mut entries = Vec::new();
+            for mut entry in self.reader.content_index(&archive)? {
+                if let Some(&data) = self.copied.get(&entry.data) {
+                    entry.data = data;
+                    entries.push(entry);
+                }
+            }
+            let content_index = DiarObject::ContentIndex(ObjContentIndex { entries });
+            let content_index = write_listing(writer, tree.cfg, &content_index, tree.encryption)?;
+            metadata.insert(MetadataTag::ContentIndex, Metadata::ObjectRef(content_index));
+        }
+        Ok((root_dir, tree.cfg.path_index.then_some(index), metadata))
+    }
+
+    /// Copies a file, directory or symbolic link.
+    fn copy_node<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &mut TreeWriter,
+        id: ObjectId,
+        path: &str,
+    ) -> Result<ObjectId> {
+        if let Some(&copied) = self.copied.get(&id) {
+            // directories shared with an earlier snapshot are still read to build its path index
+            if !tree.cfg.path_index || !self.reader.is_dir(id)? {
+                return Ok(copied);
+            }
+        }
+        let copied = match self.reader.read_object(id)? {
+            DiarObject::Directory(dir) => self.copy_dir(writer, tree, id, dir, path)?,
+            DiarObject::BlobPlain(_) if self.recompress => {
+                self.recompress_blob(writer, tree, id)?
+            }
+            DiarObject::BlobPlain(_) => self.copy_blob(writer, id)?,
+            DiarObject::Symlink(link) => {
+                let link = DiarObject::Symlink(link);
+                write_listing(writer, tree.cfg, &link, tree.encryption)?
+            }
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ => return corrupt(&"directory entry is not a file, directory or symbolic link"),
+        };
+        Ok(*self.copied.entry(id).or_insert(copied))
+    }
+
+    fn copy_dir<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &mut TreeWriter,
+        id: ObjectId,
+        dir: ObjDirectory,
+        path: &str,
+    ) -> Result<ObjectId> {
+        ensure_valid(self.ancestors.insert(id), &"directory contains itself")?;
+        let mut entries = Vec::new();
+        for entry in dir.entries {
+            let path = join_path(path, &entry.name);
+            let data = self.copy_node(writer, tree, entry.data, &path)?;
+            let metadata = self.copy_metadata(writer, tree, entry.metadata)?;
+            if tree.cfg.path_index {
+                tree.index
+                    .push(PathIndexEntry { hash: path_hash(&path), data, metadata });
+            }
+            entries.push(DirectoryEntry { name: entry.name, data, metadata });
+        }
+        self.ancestors.remove(&id);
+
+        if let Some(&copied) = self.copied.get(&id) {
+            return Ok(copied);
+        }
+        let dir = DiarObject::Directory(ObjDirectory { sorted: dir.sorted, entries });
+        write_listing(writer, tree.cfg, &dir, tree.encryption)
+    }
+
+    fn copy_metadata<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &TreeWriter,
+        id: ObjectId,
+    ) -> Result<ObjectId> {
+        if id == ObjectId::NONE {
+            return Ok(ObjectId::NONE);
+        }
+        if let Some(&copied) = self.copied.get(&id) {
+            return Ok(copied);
+        }
+        let DiarObject::Metadata(metadata) = self.reader.read_object(id)? else {
+            return corrupt(&"entry metadata reference does not point to metadata");
+        };
+        ensure(
+            !metadata
+                .metadata
+                .values()
+                .any(|x| matches!(x, Metadata::ObjectRef(_))),
+            &"entry metadata referring to other objects cannot be repacked",
+        )?;
+        let metadata = DiarObject::Metadata(metadata);
+        let copied = write_listing(writer, tree.cfg, &metadata, tree.encryption)?;
+        self.copied.insert(id, copied);
+        Ok(copied)
+    }
+
+    /// Compresses a file again with the new dictionary, reading it only once.
+    ///
+    /// Files that fit in the default zstd window are read into memory first, so that their length
+    /// picks the window they are compressed with.
+    fn recompress_blob<W: Write>(
+        &mut self,
+        writer: &mut DiarIo<W>,
+        tree: &TreeWriter,
+        id: ObjectId,
+    ) -> Result<ObjectId> {
+        let mut stream = self.reader.open_file(id)?;
+        let mut start = Vec::new();
+        let limit = 1 << DEFAULT_WINDOW_LOG;
+        (&mut stream)
+            .take(limit)
+            .read_to_end(&mut start)
+            .map_err(Error::decompression_failed)?;
+        let len_hint = match start.len() as u64 {
+            len if len < limit => len,
+            _ => 0,
+        };
+        let (dict, filter_obj) = (Some(tree.dict), tree.filter_obj);
+        write_compressed_blob(writer, dict, filter_obj, tree.encryption, len_hint, |x| {
+            x.write_all(&start)?;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = match stream.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(Error::decompression_failed(e)),
+                };
+                x.write_all(&buf[..read])?;
+            }
+        })
+    }
+
+    /// Copies a blob and its filters as they are stored.
+    fn copy_blob<W: Write>(&mut self, writer: &mut DiarIo<W>, id: ObjectId) -> Result<ObjectId> {
+        if let Some(&copied) = self.copied.get(&id) {
+            return Ok(copied);
+        }
+        let DiarObject::BlobPlain(blob) = self.reader.read_stored(id)? else {
+            return corrupt(&"dictionary source is not a file");
+        };
+        let mut filters = Vec::new();
+        for filter in blob.filters {
+            filters.push(self.copy_filter(writer, filter)?);
+        }
+        let blob = DiarObject::BlobPlain(ObjBlobPlain { filters });
+        let copied = writer.write_object_with_data(&blob, |x| {
+            self.reader.copy_stored_data(id, x)?;
+            Ok(())
+        })?;
+        self.copied.insert(id, copied);
+        Ok(copied)
+    }
+
+    /// Copies a filter, and the dictionary it uses.
+    fn copy_filter<W: Write>(&mut self, writer: &mut DiarIo<W>, id: ObjectId) -> Result<ObjectId> {
+        if let Some(&copied) = self.copied.get(&id) {
+            return Ok(copied);
+        }
+        let copied = match self.reader.read_stored(id)? {
+            DiarObject::FilterZstd(zstd) => {
+                let mut dict_sources = Vec::new();
+                for source in zstd.dict_sources {
+                    dict_sources.push(self.copy_blob(writer, source)?);
+                }
+                writer.write_object(&DiarObject::FilterZstd(ObjFilterZstd { dict_sources }))?
+            }
+            obj @ DiarObject::FilterXChaCha20Poly1305(_) => writer.write_object(&obj)?,
+            DiarObject::Unknown(obj) => return Error::unknown_object_type(obj.object_type),
+            _ => return corrupt(&"filter list contains an object that is not a filter"),
+        };
+        self.copied.insert(id, copied);
+        Ok(copied)
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{
+    errors::*,
+    object_io::DiarIo,
+    objects::*,
+    reader::ObjectLocation,
+    writer::{
+        append::InPlace,
+        diar_builder::*,
+        dir_tree::{DataSource, DirNode},
+    },
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    io::{BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+use zstd::dict::EncoderDictionary;
+
+/// Hashes the contents of a file, counting their length.
+#[derive(Default)]
+pub(super) struct ContentHasher {
+    hasher: blake3::Hasher,
+    len: u64,
+}
+impl ContentHasher {
+    /// Returns a writer that passes data to `inner`, hashing what was written.
+    pub(super) fn tee<'a>(&'a mut self, inner: &'a mut dyn Write) -> impl Write + 'a {
+        Tee { inner, hasher: self }
+    }
+
+    fn finish(&self) -> (ContentHash, u64) {
+        let hash = self.hasher.finalize().as_bytes()[..CONTENT_HASH_LENGTH as usize]
+            .try_into()
+            .unwrap();
+        (hash, self.len)
+    }
+}
+impl Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Tee<'a> {
+    inner: &'a mut dyn Write,
+    hasher: &'a mut ContentHasher,
+}
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.write_all(&buf[..len])?;
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The files written before, by a hash of their contents, so that identical files are only
+/// stored once.
+pub(super) struct ContentIndex {
+    /// Files of earlier snapshots, which are registered with the writer when they are used.
+    known: HashMap<ContentHash, (u64, ObjectLocation)>,
+    /// Files of the snapshot being written.
+    used: BTreeMap<ContentHash, (u64, ObjectId)>,
+    /// The lengths of the files in `known` and `used`.
+    lengths: HashSet<u64>,
+}
+impl ContentIndex {
+    fn new(known: HashMap<ContentHash, (u64, ObjectLocation)>) -> Self {
+        let lengths = known.values().map(|x| x.0).collect();
+        ContentIndex { known, used: BTreeMap::new(), lengths }
+    }
+
+    /// Writes a file with `write`, unless a file with the same contents was written before.
+    ///
+    /// A file whose length no earlier file has cannot be a copy of one, so it is hashed while
+    /// `write` writes it, rather than being read twice.
+    pub(super) fn write_file<W: Write>(
+        &mut self,
+        target: &mut DiarIo<W>,
+        contents: &DataSource,
+        write: impl FnOnce(&mut DiarIo<W>, Option<&mut ContentHasher>) -> Result<ObjectId>,
+    ) -> Result<ObjectId> {
+        let len_hint = contents.len_hint();
+        if len_hint != 0 && !self.lengths.contains(&len_hint) {
+            let mut hasher = ContentHasher::default();
+            let id = write(target, Some(&mut hasher))?;
+            let (hash, len) = hasher.finish();
+            self.used.entry(hash).or_insert((len, id));
+            self.lengths.insert(len);
+            return Ok(id);
+        }
+
+        let mut hasher = ContentHasher::default();
+        contents.write_to_stream(&mut hasher)?;
+        let (hash, len) = hasher.finish();
+        if let Some((_, id)) = self.used.get(&hash) {
+            return Ok(*id);
+        }
+        let id = match self.known.get(&hash) {
+            Some((_, location)) => target.import_object(location.offset, location.hash)?,
+            None => write(target, None)?,
+        };
+        self.used.insert(hash, (len, id));
+        self.lengths.insert(len);
+        Ok(id)
+    }
+}
+
+/// Adds the contents of a directory to an archive file as a new snapshot.
+///
+/// See [`snapshot_nodes_to_file`].
+pub fn snapshot_to_file(
+    dir: &Path,
+    archive: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let nodes = DirNode::from_path_with_options(dir, &cfg.walk_options)?;
+    snapshot_nodes_to_file(&nodes, archive, cfg)
+}
+
+/// Adds a directory tree built in memory to an archive file as a new snapshot.
+///
+/// Every snapshot holds a complete tree, and is listed by the root object under a name made from
+/// its creation time, such as `2024-05-01T12:00:00Z`. The new snapshot becomes the one the archive
+/// is opened to. Files with the same contents as a file in an earlier snapshot are not stored
+/// again, but refer to the earlier copy.
+///
+/// If the archive does not exist, it is created with this as its only snapshot. Otherwise, the
+/// snapshot is added in place like [`append_nodes_to_file`], with the same requirements on the
+/// configuration. Contents the archive was opened to that are not a snapshot, such as those of an
+/// archive written by [`compress_to_file`], are kept as a snapshot named `unnamed`.
+///
+/// [`append_nodes_to_file`]: crate::writer::append_nodes_to_file
+pub fn snapshot_nodes_to_file(
+    nodes: &DirNode,
+    archive: impl AsRef<Path>,
+    cfg: &CompressConfiguration,
+) -> Result<()> {
+    let path = archive.as_ref();
+    let time = match cfg.snapshot_time {
+        Some(time) => time,
+        None => match source_date_epoch()? {
+            Some(time) => time,
+            None => match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(time) => time.as_secs() as i64,
+                Err(_) => return error(&"system time is before the Unix epoch"),
+            },
+        },
+    };
+    if !path.exists() {
+        return create_snapshot(nodes, path, cfg, time);
+    }
+
+    let mut archive = InPlace::open(path, nodes, cfg, FormatInfo::OPTIONAL_SNAPSHOTS)?;
+    let old_filters = archive.filters.take();
+    let cfg = &archive.cfg.clone();
+    let root = archive.reader.root_object().clone();
+
+    // files of every earlier snapshot can be shared
+    let mut known = HashMap::new();
+    let mut main_name = None;
+    for &id in root.alt.values().chain([&root.main]) {
+        let obj = archive.reader.read_archive(id)?;
+        for entry in archive.reader.content_index(&obj)? {
+            known.insert(entry.hash, (entry.len, archive.reader.location(entry.data)?));
+        }
+        if id == root.main && !root.alt.values().any(|x| *x == id) {
+            main_name = Some(match obj.metadata.get(&MetadataTag::CreationTime) {
+                Some(Metadata::VarInt(time)) => format_time(*time),
+                _ => "unnamed".to_string(),
+            });
+        }
+    }
+
+    archive.write(|writer, reader| {
+        let filters = match old_filters {
+            Some(filters) => import_filters(writer, filters, cfg)?,
+            None => write_filters(writer, nodes, cfg)?,
+        };
+        let mut alt = BTreeMap::new();
+        for (name, id) in &root.alt {
+            let location = reader.location(*id)?;
+            alt.insert(name.clone(), writer.import_object(location.offset, location.hash)?);
+        }
+        if let Some(name) = main_name {
+            let location = reader.location(root.main)?;
+            let id = writer.import_object(location.offset, location.hash)?;
+            alt.insert(unique_name(name, &alt), id);
+        }
+        write_snapshot(writer, nodes, cfg, filters, known, alt, time)
+    })
+}
+
+/// Creates an archive file holding a single snapshot.
+fn create_snapshot(
+    nodes: &DirNode,
+    target: &Path,
+    cfg: &CompressConfiguration,
+    time: i64,
+) -> Result<()> {
+    let cfg = &prepare_config(cfg)?;
+    let partial = partial_path(target);
+    let mut format = format_info(nodes, cfg);
+    format.optional_features |= FormatInfo::OPTIONAL_SNAPSHOTS;
+
+    let file = BufWriter::new(create_partial(&partial)?);
+    let mut writer = DiarIo::create(file, hash_objects(cfg), &format)?;
+    let filters = write_filters(&mut writer, nodes, cfg)?;
+    write_snapshot(&mut writer, nodes, cfg, filters, HashMap::new(), BTreeMap::new(), time)?;
+    finish_partial(writer.into_inner(), &partial, target)
+}
+
+/// Writes the tree of a snapshot and its content index, and finishes the archive.
+fn write_snapshot<W: Write>(
+    writer: &mut DiarIo<W>,
+    nodes: &DirNode,
+    cfg: &CompressConfiguration,
+    filters: Filters,
+    known: HashMap<ContentHash, (u64, ObjectLocation)>,
+    alt: BTreeMap<String, ObjectId>,
+    time: i64,
+) -> Result<()> {
+    let encryption = filters.encryption.as_ref();
+
+    trace!("Compressing data...");
+    let dict = EncoderDictionary::new(&filters.dictionary, LEVEL);
+    let mut tree = TreeWriter {
+        cfg,
+        filter_obj: filters.compression,
+        dict: &dict,
+        encryption,
+        index: Vec::new(),
+        resumed: HashMap::new(),
+        checkpoint: None,
+        contents: Some(ContentIndex::new(known)),
+    };
+    let root_dir = write_dir(writer, &mut tree, nodes, "")?;
+    trace!(" - Done!");
+
+    let TreeWriter { index, contents, .. } = tree;
+    let entries = contents
+        .map(|x| x.used)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(hash, (len, data))| ContentIndexEntry { hash, len, data })
+        .collect();
+    let content_index = DiarObject::ContentIndex(ObjContentIndex { entries });
+    let content_index = write_listing(writer, cfg, &content_index, encryption)?;
+
+    let mut metadata = MetadataMap::default();
+    metadata.insert(MetadataTag::CreationTime, Metadata::VarInt(time));
+    metadata.insert(MetadataTag::ContentIndex, Metadata::ObjectRef(content_index));
+    let name = Some(unique_name(format_time(time), &alt));
+    let extras = ArchiveExtras { metadata, alt, name };
+    finish_archive(writer, cfg, &filters, root_dir, cfg.path_index.then_some(index), extras)
+}
+
+/// Adds a number to a snapshot name if it is already taken.
+fn unique_name(name: String, alt: &BTreeMap<String, ObjectId>) -> String {
+    if !alt.contains_key(&name) {
+        return name;
+    }
+    (2..)
+        .map(|i| format!("{name}-{i}"))
+        .find(|x| !alt.contains_key(x))
+        .unwrap()
+}
+
+/// Formats a time in seconds since the Unix epoch as an ISO 8601 date and time in UTC.
+fn format_time(time: i64) -> String {
+    let (year, month, day) = civil_date(time);
+    let secs = time.rem_euclid(86400);
+    let (hour, minute, second) = (secs / 3600, secs / 60 % 60, secs % 60);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// Returns the year, month and day in UTC of a time in seconds since the Unix epoch.
+pub(super) fn civil_date(time: i64) -> (i64, i64, i64) {
+    // days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = time.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    recovery::{add_recovery_record, check_recovery_record, RecoveryConfiguration},
+    signature::{SignatureStatus, SigningKey},
+    writer::{
+        append_nodes_to_file, compress_nodes_to_file, CompressConfiguration, DataSource, DirNode,
+        OpenFn,
+    },
+    EncryptionKey,
+};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+fn original() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("README.md", contents(1, 300)),
+        ("src/main.rs", contents(2, 4000)),
+        ("src/lib.rs", contents(3, 6000)),
+    ]
+}
+
+fn added() -> Vec<(&'static str, Vec<u8>)> {
+    vec![("README.md", contents(10, 400)), ("src/added.rs", contents(11, 3000))]
+}
+
+fn tree(files: &[(&str, Vec<u8>)]) -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files {
+        tree.insert(path, DirNode::file(DataSource::from_data(data.clone())))
+            .unwrap();
+    }
+    tree
+}
+
+fn failing_tree() -> DirNode {
+    let open: OpenFn = Box::new(|| Err(io::Error::other("unreadable")));
+    let mut tree = DirNode::empty_dir();
+    tree.insert("broken", DirNode::file(DataSource::Reader { open, len_hint: 10 }))
+        .unwrap();
+    tree
+}
+
+fn write_archive(name: &str, cfg: &CompressConfiguration) -> PathBuf {
+    let path = temp_dir(name).join("archive.diar");
+    compress_nodes_to_file(&tree(&original()), &path, cfg).unwrap();
+    path
+}
+
+fn add_record(path: &Path) {
+    let mut file = File::options().read(true).write(true).open(path).unwrap();
+    let cfg = RecoveryConfiguration::default()
+        .percent(10)
+        .shard_size(4096);
+    add_recovery_record(&mut file, &cfg).unwrap();
+}
+
+fn assert_merged(reader: &mut ArchiveReader<impl Read + Seek>) {
+    let expected = [
+        ("README.md", contents(10, 400)),
+        ("src/main.rs", contents(2, 4000)),
+        ("src/lib.rs", contents(3, 6000)),
+        ("src/added.rs", contents(11, 3000)),
+    ];
+    for (path, data) in expected {
+        let id = reader.lookup(path).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), data, "{path}");
+    }
+}
+
+#[test]
+fn append_merges_into_archive() {
+    let cfg = CompressConfiguration::default()
+        .hash_objects(true)
+        .path_index(true);
+    let path = write_archive("append-merge", &cfg);
+    append_nodes_to_file(&tree(&added()), &path, &cfg).unwrap();
+
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    assert_merged(&mut reader);
+    assert!(reader.verify().is_ok());
+}
+
+#[test]
+fn append_writes_recovery_record_again() {
+    let cfg = CompressConfiguration::default();
+    let path = write_archive("append-recovery", &cfg);
+    add_record(&path);
+    let protected_len = fs::metadata(&path).unwrap().len();
+    append_nodes_to_file(&tree(&added()), &path, &cfg).unwrap();
+
+    // the new record covers the new objects, which are written after the old record
+    let report = check_recovery_record(File::open(&path).unwrap())
+        .unwrap()
+        .unwrap();
+    assert!(report.is_intact());
+    assert!(report.covered > protected_len);
+    assert_merged(&mut ArchiveReader::open(&path).unwrap());
+}
+
+#[test]
+fn failed_append_restores_archive() {
+    let cfg = CompressConfiguration::default();
+    for recovery in [false, true] {
+        let path = write_archive(&format!("append-failed-{recovery}"), &cfg);
+        if recovery {
+            add_record(&path);
+        }
+        let before = fs::read(&path).unwrap();
+        assert!(append_nodes_to_file(&failing_tree(), &path, &cfg).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+    }
+}
+
+#[test]
+fn signed_archive_requires_signing_key() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let cfg = CompressConfiguration::default().signing_key(key.clone());
+    let path = write_archive("append-signed", &cfg);
+    let before = fs::read(&path).unwrap();
+
+    let unsigned = CompressConfiguration::default();
+    assert!(append_nodes_to_file(&tree(&added()), &path, &unsigned).is_err());
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    append_nodes_to_file(&tree(&added()), &path, &cfg).unwrap();
+    let reader = ArchiveReader::open(&path).unwrap();
+    let status = reader.verify_signature(&key.verifying_key()).unwrap();
+    assert_eq!(status, SignatureStatus::Valid);
+}
+
+#[test]
+fn sealed_listings_stay_sealed() {
+    let key = EncryptionKey::Raw([3; 32]);
+    let cfg = CompressConfiguration::default().encryption_key(key.clone());
+    let path = write_archive("append-sealed", &cfg.clone().encrypt_listings(true));
+    append_nodes_to_file(&tree(&added()), &path, &cfg).unwrap();
+    let data = fs::read(&path).unwrap();
+    assert!(!data.windows(8).any(|x| x == b"added.rs"));
+
+    let reader_cfg = ReaderConfiguration::default().decryption_key(key);
+    let mut reader = ArchiveReader::open_with_config(&path, &reader_cfg).unwrap();
+    assert_merged(&mut reader);
+
+    let path = write_archive("append-unsealed", &cfg);
+    let sealing = cfg.encrypt_listings(true);
+    assert!(append_nodes_to_file(&tree(&added()), &path, &sealing).is_err());
+}
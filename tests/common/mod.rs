@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns an empty directory for a test, unique to the test binary and the given name.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("diar-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns some compressible contents that differ with `seed`.
+pub fn contents(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E3779B9) | 1;
+    (0..len)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if i % 7 == 0 {
+                (state % 26) as u8 + b'a'
+            } else {
+                b"the quick brown fox "[i % 20]
+            }
+        })
+        .collect()
+}
+
+/// Returns random bytes that differ with `seed`, which do not compress.
+pub fn noise(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Returns the offset of a byte of `noise` stored unchanged, and only once, in `archive`, after
+/// `skip` bytes of it, so that damaging it damages only the file containing it.
+pub fn find_noise(archive: &[u8], noise: &[u8], skip: usize) -> usize {
+    noise[skip..]
+        .chunks_exact(16)
+        .find_map(|chunk| {
+            let mut found = archive.windows(16).enumerate().filter(|(_, x)| *x == chunk);
+            match (found.next(), found.next()) {
+                (Some((offset, _)), None) => Some(offset),
+                _ => None,
+            }
+        })
+        .unwrap()
+}
+
+/// Writes files into `dir`, creating their parent directories.
+pub fn write_files(dir: &Path, files: &[(impl AsRef<Path>, impl AsRef<[u8]>)]) {
+    for (path, data) in files {
+        let path = dir.join(path.as_ref());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data.as_ref()).unwrap();
+    }
+}
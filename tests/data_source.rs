@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+};
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+};
+
+fn compress(tree: &DirNode) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(tree, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+fn read(archive: &[u8], path: &str) -> Vec<u8> {
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let id = reader.lookup(path).unwrap().unwrap();
+    reader.read_file(id).unwrap()
+}
+
+#[test]
+fn in_memory_data_round_trip() {
+    let mut sub = DirNode::empty_dir();
+    sub.insert("b.txt", DirNode::file(DataSource::from_data(contents(2, 5000))))
+        .unwrap();
+    sub.insert("empty", DirNode::file(DataSource::from_data(Vec::new())))
+        .unwrap();
+    let mut root = DirNode::empty_dir();
+    root.insert("a.txt", DirNode::file(DataSource::from_data(contents(1, 300))))
+        .unwrap();
+    root.insert("sub", sub).unwrap();
+
+    let archive = compress(&root);
+    assert_eq!(read(&archive, "a.txt"), contents(1, 300));
+    assert_eq!(read(&archive, "sub/b.txt"), contents(2, 5000));
+    assert_eq!(read(&archive, "sub/empty"), b"");
+}
+
+#[test]
+fn file_ranges_round_trip() {
+    let dir = temp_dir("ranges");
+    let data = contents(3, 100_000);
+    let path = dir.join("pack.bin");
+    fs::write(&path, &data).unwrap();
+
+    let mut root = DirNode::empty_dir();
+    root.insert("whole", DirNode::file(DataSource::from_range(&path, 0, 100_000).unwrap()))
+        .unwrap();
+    root.insert("middle", DirNode::file(DataSource::from_range(&path, 1234, 5000).unwrap()))
+        .unwrap();
+    root.insert("tail", DirNode::file(DataSource::from_range(&path, 90_000, 10_000).unwrap()))
+        .unwrap();
+    root.insert("empty", DirNode::file(DataSource::from_range(&path, 100_000, 0).unwrap()))
+        .unwrap();
+
+    let archive = compress(&root);
+    assert_eq!(read(&archive, "whole"), data);
+    assert_eq!(read(&archive, "middle"), &data[1234..6234]);
+    assert_eq!(read(&archive, "tail"), &data[90_000..]);
+    assert_eq!(read(&archive, "empty"), b"");
+
+    assert!(DataSource::from_range(&path, 90_000, 10_001).is_err());
+    assert!(DataSource::from_range(&path, u64::MAX, 1).is_err());
+}
+
+#[test]
+fn custom_readers_round_trip() {
+    let data = contents(4, 50_000);
+    let source = {
+        let data = data.clone();
+        DataSource::from_reader(data.len() as u64, move || {
+            Ok(Box::new(Cursor::new(data.clone())) as Box<dyn Read>)
+        })
+    };
+    let mut root = DirNode::empty_dir();
+    root.insert("stream.txt", DirNode::file(source)).unwrap();
+    root.insert("other.txt", DirNode::file(DataSource::from_data(contents(5, 1000))))
+        .unwrap();
+
+    let archive = compress(&root);
+    assert_eq!(read(&archive, "stream.txt"), data);
+    assert_eq!(read(&archive, "other.txt"), contents(5, 1000));
+}
+
+#[test]
+fn reader_errors_are_reported() {
+    let source = DataSource::from_reader(100, || Err(io::Error::other("source went away")));
+    let mut root = DirNode::empty_dir();
+    root.insert("broken", DirNode::file(source)).unwrap();
+
+    let mut out = Cursor::new(Vec::new());
+    assert!(compress_nodes(&root, &mut out, &CompressConfiguration::default()).is_err());
+}
@@ -0,0 +1,136 @@
+use diar::writer::{DataSource, DirNode};
+
+fn file(data: &str) -> DirNode {
+    DirNode::file(DataSource::from_data(data))
+}
+
+fn paths(tree: &DirNode) -> Vec<String> {
+    tree.walk().map(|(path, _)| path).collect()
+}
+
+#[test]
+fn insert_creates_parents() {
+    let mut root = DirNode::empty_dir();
+    assert!(root.insert("a/b/c.txt", file("c")).unwrap().is_none());
+    assert!(root.insert("a/d.txt", file("d")).unwrap().is_none());
+    assert!(root.get("a/b").unwrap().is_dir());
+    assert!(root.get("a/b/c.txt").unwrap().is_file());
+    assert_eq!(paths(&root), ["a", "a/b", "a/b/c.txt", "a/d.txt"]);
+
+    let old = root.insert("a/d.txt", file("new")).unwrap().unwrap();
+    assert!(old.is_file());
+}
+
+#[test]
+fn insert_rejects_bad_paths() {
+    let mut root = DirNode::empty_dir();
+    root.insert("file.txt", file("data")).unwrap();
+
+    for path in ["", "a/..", "./b", "a/./b", "c/../d", "file.txt/b"] {
+        assert!(root.insert(path, file("x")).is_err(), "{path}");
+    }
+    assert!(file("x").insert("a", file("y")).is_err());
+    assert_eq!(paths(&root), ["file.txt"]);
+}
+
+#[test]
+fn remove_returns_the_node() {
+    let mut root = DirNode::empty_dir();
+    root.insert("a/b/c.txt", file("c")).unwrap();
+    root.insert("a/d.txt", file("d")).unwrap();
+
+    let removed = root.remove("a/b").unwrap();
+    assert!(removed.get("c.txt").unwrap().is_file());
+    assert_eq!(paths(&root), ["a", "a/d.txt"]);
+
+    assert!(root.remove("a/b").is_err());
+    assert!(root.remove("missing/d.txt").is_err());
+    assert!(root.remove("a/d.txt/x").is_err());
+    assert!(root.remove("a/..").is_err());
+    assert_eq!(paths(&root), ["a", "a/d.txt"]);
+}
+
+#[test]
+fn rename_moves_subtree() {
+    let mut root = DirNode::empty_dir();
+    root.insert("a/b/c.txt", file("c")).unwrap();
+    root.rename("a/b", "d/e").unwrap();
+    assert!(root.get("a/b").is_none());
+    assert!(root.get("d/e/c.txt").unwrap().is_file());
+}
+
+#[test]
+fn rename_rejects_existing_destination() {
+    let mut root = DirNode::empty_dir();
+    root.insert("a.txt", file("a")).unwrap();
+    root.insert("b.txt", file("b")).unwrap();
+    assert!(root.rename("a.txt", "b.txt").is_err());
+    assert!(root.get("a.txt").is_some());
+    assert!(root.rename("missing.txt", "c.txt").is_err());
+}
+
+#[test]
+fn rename_rejects_move_into_itself() {
+    let mut root = DirNode::empty_dir();
+    root.insert("a/b.txt", file("b")).unwrap();
+    assert!(root.rename("a", "a/c").is_err());
+    assert!(root.get("a/b.txt").is_some());
+}
+
+#[test]
+fn merge_overlays_trees() {
+    let mut base = DirNode::empty_dir();
+    base.insert("shared/old.txt", file("old")).unwrap();
+    base.insert("shared/both.txt", file("base")).unwrap();
+    base.insert("replaced/inner.txt", file("inner")).unwrap();
+
+    let mut overlay = DirNode::empty_dir();
+    overlay.insert("shared/new.txt", file("new")).unwrap();
+    overlay.insert("shared/both.txt", file("overlay")).unwrap();
+    overlay.insert("replaced", file("now a file")).unwrap();
+
+    base.merge(overlay);
+    assert_eq!(paths(&base), [
+        "replaced", "shared", "shared/both.txt", "shared/new.txt", "shared/old.txt"
+    ],);
+    assert!(base.get("replaced").unwrap().is_file());
+    let mut data = Vec::new();
+    base.get("shared/both.txt")
+        .unwrap()
+        .contents()
+        .unwrap()
+        .push_to_vec(&mut data)
+        .unwrap();
+    assert_eq!(data, b"overlay");
+}
+
+#[test]
+fn retain_prunes_by_path() {
+    let mut root = DirNode::empty_dir();
+    root.insert("keep/a.txt", file("a")).unwrap();
+    root.insert("keep/b.log", file("b")).unwrap();
+    root.insert("drop/c.txt", file("c")).unwrap();
+
+    let mut visited = Vec::new();
+    root.retain(|path, _| {
+        visited.push(path.to_string());
+        !path.starts_with("drop") && !path.ends_with(".log")
+    });
+    assert_eq!(paths(&root), ["keep", "keep/a.txt"]);
+    // the children of removed directories are not visited
+    assert!(!visited.iter().any(|x| x == "drop/c.txt"));
+}
+
+#[test]
+fn failed_rename_keeps_subtree() {
+    let mut root = DirNode::empty_dir();
+    root.insert("dir/file.txt", file("data")).unwrap();
+    root.insert("blocker", file("blocker")).unwrap();
+
+    assert!(root.rename("dir", "blocker/dir").is_err());
+    assert!(root.get("dir/file.txt").unwrap().is_file());
+
+    assert!(root.rename("dir", "new/../dir").is_err());
+    assert!(root.get("dir/file.txt").unwrap().is_file());
+    assert!(root.get("new").is_none());
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    writer::{compress_with_config, CompressConfiguration},
+    EncryptionKey, ErrorKind,
+};
+use std::{
+    io::{Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+/// Writes a directory of private notes, enough for the compressor to train a dictionary on.
+fn notes_dir(name: &str) -> (PathBuf, Vec<(String, Vec<u8>)>) {
+    let dir = temp_dir(name);
+    let files: Vec<_> = (0..12)
+        .map(|i| (format!("secret-notes/note{i}.txt"), contents(i, 20_000)))
+        .collect();
+    write_files(&dir, &files);
+    (dir, files)
+}
+
+fn compress(dir: &Path, cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_with_config(dir, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+fn open(archive: &[u8], key: Option<EncryptionKey>) -> diar::Result<ArchiveReader<Cursor<&[u8]>>> {
+    let mut cfg = ReaderConfiguration::default();
+    cfg.decryption_key = key;
+    ArchiveReader::new_with_config(Cursor::new(archive), &cfg)
+}
+
+fn assert_files<S: Read + Seek>(reader: &mut ArchiveReader<S>, files: &[(String, Vec<u8>)]) {
+    for (path, data) in files {
+        let id = reader.lookup(path).unwrap().unwrap();
+        assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+    }
+}
+
+#[test]
+fn passphrase_round_trip() {
+    let (dir, files) = notes_dir("passphrase");
+    let key = EncryptionKey::Passphrase("correct horse".into());
+    let cfg = CompressConfiguration::default().encryption_key(key.clone());
+    let archive = compress(&dir, &cfg);
+
+    assert_files(&mut open(&archive, Some(key)).unwrap(), &files);
+}
+
+#[test]
+fn raw_key_round_trip() {
+    let (dir, files) = notes_dir("raw-key");
+    let key = EncryptionKey::Raw([42; 32]);
+    let cfg = CompressConfiguration::default()
+        .encryption_key(key.clone())
+        .hash_objects(true);
+    let archive = compress(&dir, &cfg);
+
+    assert_files(&mut open(&archive, Some(key)).unwrap(), &files);
+}
+
+#[test]
+fn wrong_keys_are_rejected() {
+    let (dir, _) = notes_dir("wrong-keys");
+    let passphrase = EncryptionKey::Passphrase("correct horse".into());
+    let raw = EncryptionKey::Raw([7; 32]);
+
+    for (key, wrong_keys) in [
+        (passphrase, [
+            EncryptionKey::Passphrase("battery staple".into()),
+            EncryptionKey::Raw([0; 32]),
+        ]),
+        (raw, [
+            EncryptionKey::Raw([0; 32]),
+            EncryptionKey::Passphrase("correct horse".into()),
+        ]),
+    ] {
+        let archive = compress(&dir, &CompressConfiguration::default().encryption_key(key));
+
+        let mut reader = open(&archive, None).unwrap();
+        let id = reader.lookup("secret-notes/note0.txt").unwrap().unwrap();
+        let err = reader.read_file(id).unwrap_err();
+        assert!(matches!(err.kind(), Some(ErrorKind::KeyRequired(..))), "{err}");
+
+        for wrong in wrong_keys {
+            let mut reader = open(&archive, Some(wrong)).unwrap();
+            let id = reader.lookup("secret-notes/note0.txt").unwrap().unwrap();
+            let err = reader.read_file(id).unwrap_err();
+            assert!(matches!(err.kind(), Some(ErrorKind::KeyInvalid(..))), "{err}");
+            assert!(!err.is_corruption());
+        }
+    }
+}
+
+#[test]
+fn encrypted_listings_hide_names() {
+    let (dir, files) = notes_dir("listings");
+    let key = EncryptionKey::Raw([7; 32]);
+    let cfg = CompressConfiguration::default().encryption_key(key.clone());
+    let name = b"secret-notes";
+
+    let archive = compress(&dir, &cfg);
+    assert!(archive.windows(name.len()).any(|x| x == name));
+
+    let archive = compress(&dir, &cfg.encrypt_listings(true));
+    assert!(!archive.windows(name.len()).any(|x| x == name));
+    assert_files(&mut open(&archive, Some(key)).unwrap(), &files);
+    let lookup = open(&archive, None).and_then(|mut x| x.lookup("secret-notes/note0.txt"));
+    assert!(lookup.is_err());
+}
+
+#[test]
+fn encrypted_listings_keep_the_path_index() {
+    let (dir, files) = notes_dir("listings-index");
+    let key = EncryptionKey::Raw([7; 32]);
+    for hash_objects in [false, true] {
+        let cfg = CompressConfiguration::default()
+            .encryption_key(key.clone())
+            .encrypt_listings(true)
+            .path_index(true)
+            .hash_objects(hash_objects);
+        let archive = compress(&dir, &cfg);
+
+        let mut reader = open(&archive, Some(key.clone())).unwrap();
+        assert!(reader.has_path_index());
+        assert_files(&mut reader, &files);
+        let dir = reader.lookup("secret-notes").unwrap().unwrap();
+        assert!(reader.is_dir(dir).unwrap());
+        assert_eq!(reader.lookup("secret-notes/missing.txt").unwrap(), None);
+        assert!(reader.verify().is_ok());
+
+        let lookup = open(&archive, None).and_then(|mut x| x.lookup("secret-notes/note0.txt"));
+        assert!(lookup.is_err());
+    }
+}
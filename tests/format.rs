@@ -0,0 +1,74 @@
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+    ErrorKind, FormatInfo,
+};
+use std::io::Cursor;
+
+/// The offset of the format version, directly after the magic number.
+const VERSION_OFFSET: usize = 8;
+
+fn archive(cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("hello.txt", DirNode::file(DataSource::from_data("hello")))
+        .unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, cfg).unwrap();
+    let archive = out.into_inner();
+
+    // the header varints are all a single byte in these tests
+    assert_eq!(archive[VERSION_OFFSET], FormatInfo::CURRENT_VERSION as u8);
+    archive
+}
+
+fn read_hello(archive: Vec<u8>) -> diar::Result<Vec<u8>> {
+    let mut reader = ArchiveReader::new(Cursor::new(archive))?;
+    let id = reader.lookup("hello.txt")?.unwrap();
+    reader.read_file(id)
+}
+
+#[test]
+fn format_is_recorded() {
+    let reader = ArchiveReader::new(Cursor::new(archive(&Default::default()))).unwrap();
+    let format = reader.format();
+    assert_eq!(format.version, FormatInfo::CURRENT_VERSION);
+    assert_eq!(format.required_features, 0);
+    assert_eq!(format.optional_features, 0);
+
+    let cfg = CompressConfiguration::default().path_index(true);
+    let reader = ArchiveReader::new(Cursor::new(archive(&cfg))).unwrap();
+    assert_eq!(reader.format().optional_features, FormatInfo::OPTIONAL_PATH_INDEX);
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    let mut newer = archive(&Default::default());
+    newer[VERSION_OFFSET] += 1;
+    let err = read_hello(newer).unwrap_err();
+    let version = FormatInfo::CURRENT_VERSION + 1;
+    assert!(err
+        .to_string()
+        .contains(&format!("format version {version} is not supported")));
+
+    let mut zero = archive(&Default::default());
+    zero[VERSION_OFFSET] = 0;
+    let err = read_hello(zero).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::UnsupportedVersion(0, _))), "{err}");
+}
+
+#[test]
+fn unknown_required_features_are_rejected() {
+    let mut archive = archive(&Default::default());
+    archive[VERSION_OFFSET + 1] = 0x40;
+    let err = read_hello(archive).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::UnsupportedFeatures(0x40, _))), "{err}");
+}
+
+#[test]
+fn unknown_optional_features_are_ignored() {
+    let mut archive = archive(&Default::default());
+    archive[VERSION_OFFSET + 2] = 0x40;
+    let reader = ArchiveReader::new(Cursor::new(archive.clone())).unwrap();
+    assert_eq!(reader.format().optional_features, 0x40);
+    assert_eq!(read_hello(archive).unwrap(), b"hello");
+}
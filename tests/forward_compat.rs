@@ -0,0 +1,119 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode, ManifestEntryType},
+    EntryMetadata,
+};
+use std::{fs, io::Cursor};
+
+const TARGET: &str = "link-target";
+const USER: &str = "someuser";
+
+/// Builds an archive with a symlink and a file with a user name, whose encodings are found and
+/// patched by the tests to simulate objects and metadata written by a newer version.
+fn archive() -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("file.txt", DirNode::file(DataSource::from_data("contents")))
+        .unwrap();
+    let metadata = EntryMetadata::default().user(USER).uid(1000);
+    let node = DirNode::file(DataSource::from_data("owned")).with_metadata(metadata);
+    tree.insert("owned.txt", node).unwrap();
+    tree.insert("link", DirNode::symlink(TARGET)).unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+fn find(archive: &[u8], pattern: &[u8]) -> usize {
+    let mut found = archive
+        .windows(pattern.len())
+        .enumerate()
+        .filter(|(_, x)| *x == pattern);
+    let (offset, _) = found.next().unwrap();
+    assert!(found.next().is_none());
+    offset
+}
+
+/// Changes the type of the symlink object to one this version does not know.
+fn patch_object_type(archive: &mut [u8], ty: u8) {
+    // type, data length, fields length, then the target as a length-prefixed string
+    let len = TARGET.len() as u8;
+    let mut header = vec![6, 0, len + 1, len];
+    header.extend_from_slice(TARGET.as_bytes());
+    archive[find(archive, &header)] = ty;
+}
+
+/// Returns the offset of the user name's metadata tag.
+fn user_tag_offset(archive: &[u8]) -> usize {
+    // tag, value type, then the name as a length-prefixed string
+    let mut entry = vec![4, 3, USER.len() as u8];
+    entry.extend_from_slice(USER.as_bytes());
+    find(archive, &entry)
+}
+
+fn owned_metadata(archive: Vec<u8>) -> EntryMetadata {
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let root = reader.root_dir();
+    let entries = reader.read_dir(root).unwrap();
+    let entry = entries.iter().find(|x| x.name == "owned.txt").unwrap();
+    reader.read_metadata(entry.metadata).unwrap()
+}
+
+#[test]
+fn unknown_objects_are_skipped() {
+    let mut archive = archive();
+    patch_object_type(&mut archive, 0x30);
+
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let link = reader.lookup("link").unwrap().unwrap();
+    assert_eq!(reader.unknown_type(link).unwrap(), Some(0x30));
+    assert!(!reader.is_symlink(link).unwrap());
+    let file = reader.lookup("file.txt").unwrap().unwrap();
+    assert_eq!(reader.unknown_type(file).unwrap(), None);
+
+    let entries = reader.entries().unwrap();
+    let entry = entries.iter().find(|x| x.path == "link").unwrap();
+    assert_eq!(entry.kind, ManifestEntryType::Unknown);
+    assert_eq!(entry.object_type, Some(0x30));
+    let entry = entries.iter().find(|x| x.path == "file.txt").unwrap();
+    assert_eq!(entry.object_type, None);
+
+    let dir = temp_dir("extract");
+    let root = reader.root_dir();
+    reader.extract(root, &dir).unwrap();
+    assert_eq!(fs::read(dir.join("file.txt")).unwrap(), b"contents");
+    assert!(fs::symlink_metadata(dir.join("link")).is_err());
+}
+
+#[test]
+fn unknown_metadata_tags_are_reported() {
+    let mut archive = archive();
+    let offset = user_tag_offset(&archive);
+    archive[offset] = 0x60;
+
+    let metadata = owned_metadata(archive.clone());
+    assert_eq!(metadata.user, None);
+    assert_eq!(metadata.uid, Some(1000));
+    assert_eq!(metadata.unknown_tags, [0x60]);
+
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let entries = reader.entries().unwrap();
+    let entry = entries.iter().find(|x| x.path == "owned.txt").unwrap();
+    assert_eq!(entry.metadata.unknown_tags, [0x60]);
+    let id = reader.lookup("owned.txt").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), b"owned");
+}
+
+#[test]
+fn unknown_metadata_types_are_reported() {
+    let mut archive = archive();
+    let offset = user_tag_offset(&archive);
+    archive[offset + 1] = 9;
+
+    let metadata = owned_metadata(archive);
+    assert_eq!(metadata.user, None);
+    assert_eq!(metadata.uid, Some(1000));
+    assert_eq!(metadata.unknown_tags, [4]);
+}
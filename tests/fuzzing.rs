@@ -0,0 +1,73 @@
+#![cfg(feature = "fuzzing")]
+
+mod common;
+
+use common::*;
+use diar::{
+    fuzzing,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+    EncryptionKey,
+};
+use std::io::Cursor;
+
+fn archive(cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("a.txt", DirNode::file(DataSource::from_data(contents(1, 3000))))
+        .unwrap();
+    tree.insert("b/c.txt", DirNode::file(DataSource::from_data(contents(2, 500))))
+        .unwrap();
+    tree.insert("b/link", DirNode::symlink("c.txt")).unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+fn configurations() -> [CompressConfiguration; 3] {
+    let key = EncryptionKey::Raw([1; 32]);
+    [
+        CompressConfiguration::default(),
+        CompressConfiguration::default()
+            .path_index(true)
+            .hash_objects(true),
+        CompressConfiguration::default()
+            .encryption_key(key)
+            .encrypt_listings(true),
+    ]
+}
+
+#[test]
+fn fuzz_entry_points_accept_valid_archives() {
+    for cfg in configurations() {
+        let archive = archive(&cfg);
+        fuzzing::parse_archive_header(&archive);
+        fuzzing::read_archive(&archive);
+        for len in [0, 8, archive.len() / 2, archive.len() - 1] {
+            fuzzing::read_archive(&archive[..len]);
+        }
+    }
+}
+
+#[test]
+fn fuzz_entry_points_accept_damaged_archives() {
+    for cfg in configurations() {
+        let archive = archive(&cfg);
+        for pos in (0..archive.len()).step_by(7) {
+            for bits in [0x01, 0x80, 0xFF] {
+                let mut damaged = archive.clone();
+                damaged[pos] ^= bits;
+                fuzzing::read_archive(&damaged);
+            }
+        }
+    }
+}
+
+#[test]
+fn fuzz_entry_points_accept_arbitrary_input() {
+    let long = contents(9, 1000);
+    for data in [&[][..], &[0], &[1, 0, 0], &[0, 0x80, 0x80, 0x80], &[0xFF; 64], &long] {
+        fuzzing::parse_object(data);
+        fuzzing::parse_directory(data);
+        fuzzing::parse_archive_header(data);
+        fuzzing::read_archive(data);
+    }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_with_config, CompressConfiguration},
+};
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+/// Writes a directory of text files, enough for the compressor to train a dictionary on.
+fn text_dir(name: &str) -> (PathBuf, Vec<(String, Vec<u8>)>) {
+    let dir = temp_dir(name);
+    let files: Vec<_> = (0..12)
+        .map(|i| (format!("dir{}/file{i}.txt", i % 3), contents(i, 20_000)))
+        .collect();
+    write_files(&dir, &files);
+    (dir, files)
+}
+
+fn compress(dir: &Path, cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_with_config(dir, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn hashed_archives_read_back() {
+    let (dir, files) = text_dir("read-back");
+    for hash_objects in [false, true] {
+        let cfg = CompressConfiguration::default().hash_objects(hash_objects);
+        let archive = compress(&dir, &cfg);
+        let mut reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+        assert_eq!(reader.is_hashed(), hash_objects);
+        for (path, data) in &files {
+            let id = reader.lookup(path).unwrap().unwrap();
+            assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+        }
+    }
+}
+
+#[test]
+fn damaged_file_fails_its_hash() {
+    let (dir, files) = text_dir("damaged-file");
+    let noise = noise(0x2545F491, 50_000);
+    write_files(&dir, &[("noise.bin", &noise)]);
+    let mut archive = compress(&dir, &CompressConfiguration::default().hash_objects(true));
+    let offset = find_noise(&archive, &noise, 20_000);
+    archive[offset] ^= 0x01;
+
+    let mut reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+    let id = reader.lookup("noise.bin").unwrap().unwrap();
+    assert!(reader.read_file(id).is_err());
+    // the other files can still be read, without hashing the whole archive
+    for (path, data) in &files {
+        let id = reader.lookup(path).unwrap().unwrap();
+        assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+    }
+}
+
+#[test]
+fn damaged_root_hash_is_detected() {
+    let (dir, _) = text_dir("damaged-root");
+    let mut archive = compress(&dir, &CompressConfiguration::default().hash_objects(true));
+    // the root hash sits directly before the 24 byte trailer
+    let len = archive.len();
+    archive[len - 30] ^= 0x01;
+
+    assert!(ArchiveReader::new(Cursor::new(&archive)).is_err());
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration, ReaderLimits},
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+    ErrorKind, ResourceLimit,
+};
+use std::io::Cursor;
+
+fn compress(tree: &DirNode) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(tree, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+/// Builds an archive with one large file, and a few nested directories.
+fn archive() -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("big.bin", DirNode::file(DataSource::from_data(contents(1, 200_000))))
+        .unwrap();
+    tree.insert("a/b/c/d.txt", DirNode::file(DataSource::from_data("deep")))
+        .unwrap();
+    tree.insert("a/e.txt", DirNode::file(DataSource::from_data("shallow")))
+        .unwrap();
+    compress(&tree)
+}
+
+fn open(archive: &[u8], limits: ReaderLimits) -> ArchiveReader<Cursor<&[u8]>> {
+    let cfg = ReaderConfiguration::default().limits(limits);
+    ArchiveReader::new_with_config(Cursor::new(archive), &cfg).unwrap()
+}
+
+fn limit_of(err: &diar::Error) -> Option<ResourceLimit> {
+    match err.kind() {
+        Some(ErrorKind::LimitExceeded(limit, _)) => Some(*limit),
+        _ => None,
+    }
+}
+
+#[test]
+fn default_limits_are_finite() {
+    let limits = ReaderLimits::default();
+    assert!(limits.max_entry_size.is_some());
+    assert!(limits.max_total_size.is_some());
+    assert!(limits.max_entries.is_some());
+}
+
+#[test]
+fn large_windows_exceed_the_window_limit() {
+    let archive = archive();
+    let mut reader = open(&archive, ReaderLimits::default().max_window_log(12));
+    let id = reader.lookup("big.bin").unwrap().unwrap();
+    let err = reader.read_file(id).unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::ZstdWindow), "{err}");
+
+    let mut reader = open(&archive, ReaderLimits::default());
+    let id = reader.lookup("big.bin").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), contents(1, 200_000));
+}
+
+#[test]
+fn unknown_lengths_use_the_default_window() {
+    let data = contents(11, 3 << 20);
+    let mut tree = DirNode::empty_dir();
+    let source = data.clone();
+    let file = DataSource::from_reader(0, move || Ok(Box::new(Cursor::new(source.clone()))));
+    tree.insert("stream", DirNode::file(file)).unwrap();
+    let archive = compress(&tree);
+
+    // the default window fits within the reader's default limits
+    let mut reader = open(&archive, ReaderLimits::default());
+    let id = reader.lookup("stream").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), data);
+
+    // and is larger than the smallest window, which a zero length would otherwise pick
+    let mut reader = open(&archive, ReaderLimits::default().max_window_log(10));
+    let id = reader.lookup("stream").unwrap().unwrap();
+    let err = reader.read_file(id).unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::ZstdWindow), "{err}");
+}
+
+#[test]
+fn size_limits_are_enforced() {
+    let archive = archive();
+
+    let mut reader = open(&archive, ReaderLimits::default().max_entry_size(100_000));
+    let id = reader.lookup("big.bin").unwrap().unwrap();
+    let err = reader.read_file(id).unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::EntrySize), "{err}");
+
+    let mut reader = open(&archive, ReaderLimits::default().max_total_size(1_000_000));
+    let id = reader.lookup("big.bin").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), contents(1, 200_000));
+    let err = (0..5).find_map(|_| reader.read_file(id).err()).unwrap();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::TotalSize), "{err}");
+}
+
+#[test]
+fn entry_count_and_depth_limits_are_enforced() {
+    let archive = archive();
+    let dir = temp_dir("extract");
+
+    let mut reader = open(&archive, ReaderLimits::default().max_entries(3));
+    let err = reader.entries().unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::EntryCount), "{err}");
+    let root = reader.root_dir();
+    let err = reader.extract(root, dir.join("count")).unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::EntryCount), "{err}");
+
+    let mut reader = open(&archive, ReaderLimits::default().max_depth(2));
+    let err = reader.entries().unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::Depth), "{err}");
+    let root = reader.root_dir();
+    let err = reader.extract(root, dir.join("depth")).unwrap_err();
+    assert_eq!(limit_of(&err), Some(ResourceLimit::Depth), "{err}");
+
+    let mut reader = open(&archive, ReaderLimits::default().max_entries(6).max_depth(4));
+    assert_eq!(reader.entries().unwrap().len(), 6);
+}
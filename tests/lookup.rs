@@ -0,0 +1,113 @@
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+};
+use std::{
+    cell::Cell,
+    io::{Cursor, Read, Seek, SeekFrom},
+    rc::Rc,
+};
+
+/// A stream that counts the reads made from it.
+struct CountingStream {
+    inner: Cursor<Vec<u8>>,
+    reads: Rc<Cell<usize>>,
+}
+impl Read for CountingStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.read(buf)
+    }
+}
+impl Seek for CountingStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Names that sort differently by bytes than by more natural orderings.
+const NAMES: &[&str] = &["B", "a", "a.txt", "a0", "a_b", "ab", "z", "Ä", "é", "日本"];
+
+fn archive() -> Vec<u8> {
+    let mut root = DirNode::empty_dir();
+    for i in 0..500 {
+        let node = DirNode::file(DataSource::from_data(format!("file {i}")));
+        root.insert(&format!("many/file{i:04}"), node).unwrap();
+    }
+    for name in NAMES {
+        root.insert(&format!("names/{name}"), DirNode::file(DataSource::from_data(*name)))
+            .unwrap();
+        root.insert(&format!("names/{name}.d/inner"), DirNode::empty_dir())
+            .unwrap();
+    }
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&root, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn directories_are_stored_sorted() {
+    let mut reader = ArchiveReader::new(Cursor::new(archive())).unwrap();
+    let dir = reader.lookup("names").unwrap().unwrap();
+    let names: Vec<_> = reader
+        .read_dir(dir)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.name)
+        .collect();
+    let mut sorted = names.clone();
+    sorted.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    assert_eq!(names, sorted);
+    assert_eq!(names.len(), NAMES.len() * 2);
+}
+
+#[test]
+fn sorted_lookup_finds_every_entry() {
+    let mut reader = ArchiveReader::new(Cursor::new(archive())).unwrap();
+    for i in 0..500 {
+        let id = reader.lookup(&format!("many/file{i:04}")).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), format!("file {i}").as_bytes());
+    }
+    for name in NAMES {
+        let id = reader.lookup(&format!("names/{name}")).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), name.as_bytes());
+        let id = reader
+            .lookup(&format!("names/{name}.d/inner"))
+            .unwrap()
+            .unwrap();
+        assert!(reader.is_dir(id).unwrap());
+    }
+}
+
+#[test]
+fn sorted_lookup_misses_absent_entries() {
+    let mut reader = ArchiveReader::new(Cursor::new(archive())).unwrap();
+    for path in [
+        "many/file", "many/file0000x", "many/file05000", "many/file0250.txt", "many/File0001",
+        "many/0", "many/zzz", "names/A", "names/a.", "names/aa", "names/日", "names/z/inner",
+        "names/a.d/missing", "missing/file0001",
+    ] {
+        assert_eq!(reader.lookup(path).unwrap(), None, "{path}");
+    }
+}
+
+#[test]
+fn lookups_reuse_parsed_directories() {
+    let reads = Rc::new(Cell::new(0));
+    let stream = CountingStream { inner: Cursor::new(archive()), reads: reads.clone() };
+    let mut reader = ArchiveReader::new(stream).unwrap();
+    assert!(!reader.has_path_index());
+
+    let first = reader.lookup("many/file0000").unwrap().unwrap();
+    assert_eq!(reader.read_file(first).unwrap(), b"file 0");
+    let before = reads.get();
+    for i in (1..500).rev() {
+        assert!(reader
+            .lookup(&format!("many/file{i:04}"))
+            .unwrap()
+            .is_some());
+    }
+    assert_eq!(reader.lookup("many/file0500").unwrap(), None);
+    // the root and `many` were already parsed, so finding their entries reads nothing
+    assert_eq!(reads.get(), before);
+}
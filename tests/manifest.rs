@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ManifestFormat},
+    writer::{compress_nodes, CompressConfiguration, Manifest, ManifestEntryType},
+    EntryMetadata, ErrorKind,
+};
+use std::{
+    fs,
+    io::{Cursor, Read, Seek},
+    path::Path,
+};
+
+fn compress(manifest: &str, base: &Path) -> Vec<u8> {
+    let tree = Manifest::parse(manifest.as_bytes())
+        .unwrap()
+        .to_tree(base)
+        .unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+fn entry_metadata<S: Read + Seek>(
+    reader: &mut ArchiveReader<S>,
+    dir: &str,
+    name: &str,
+) -> EntryMetadata {
+    let dir = reader.lookup(dir).unwrap().unwrap();
+    let entries = reader.read_dir(dir).unwrap();
+    let entry = entries.iter().find(|x| x.name == name).unwrap();
+    reader.read_metadata(entry.metadata).unwrap()
+}
+
+#[test]
+fn manifest_builds_tree() {
+    let dir = temp_dir("build");
+    fs::write(dir.join("source.txt"), b"from disk").unwrap();
+    let manifest = concat!(
+        r#"{"path": "bin/tool", "type": "file", "source": "source.txt", "mode": "755"}"#,
+        "\n",
+        r#"{"path": "etc/config", "type": "file", "data": "inline", "user": "root", "uid": 0}"#,
+        "\n",
+        r#"{"path": "etc/link", "type": "symlink", "target": "config"}"#,
+        "\n",
+        r#"{"path": "var", "type": "dir", "mtime": 1700000000}"#,
+    );
+    let archive = compress(manifest, &dir);
+
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let tool = reader.lookup("bin/tool").unwrap().unwrap();
+    assert_eq!(reader.read_file(tool).unwrap(), b"from disk");
+    let config = reader.lookup("etc/config").unwrap().unwrap();
+    assert_eq!(reader.read_file(config).unwrap(), b"inline");
+    let link = reader.lookup("etc/link").unwrap().unwrap();
+    assert!(reader.is_symlink(link).unwrap());
+    assert_eq!(reader.read_link(link).unwrap(), "config");
+    let var = reader.lookup("var").unwrap().unwrap();
+    assert!(reader.is_dir(var).unwrap());
+
+    assert_eq!(entry_metadata(&mut reader, "bin", "tool"), EntryMetadata::default().mode(0o755));
+    let expected = EntryMetadata::default().user("root").uid(0);
+    assert_eq!(entry_metadata(&mut reader, "etc", "config"), expected);
+    assert_eq!(
+        entry_metadata(&mut reader, "", "var"),
+        EntryMetadata::default().mtime(1700000000)
+    );
+    assert_eq!(entry_metadata(&mut reader, "etc", "link"), EntryMetadata::default());
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_restores_links_and_modes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = temp_dir("extract");
+    let manifest = concat!(
+        r#"{"path": "bin/tool", "type": "file", "data": "tool", "mode": "750"}"#,
+        "\n",
+        r#"{"path": "bin/alias", "type": "symlink", "target": "tool"}"#,
+    );
+    let archive = compress(manifest, &dir);
+
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    let root = reader.root_dir();
+    reader.extract(root, dir.join("out")).unwrap();
+    let mode = fs::metadata(dir.join("out/bin/tool"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o750);
+    assert_eq!(fs::read_link(dir.join("out/bin/alias")).unwrap().to_str(), Some("tool"));
+    assert_eq!(fs::read(dir.join("out/bin/alias")).unwrap(), b"tool");
+}
+
+#[test]
+fn manifest_rejects_unknown_entries() {
+    let manifest = r#"{"path": "thing", "type": "unknown"}"#;
+    assert!(Manifest::parse(manifest.as_bytes()).is_err());
+}
+
+#[test]
+fn manifest_rejects_invalid_entries() {
+    let dir = temp_dir("invalid");
+    let cases = [
+        r#"{"path": "a", "type": "file"}"#,
+        r#"{"path": "a", "type": "file", "data": "x", "target": "y"}"#,
+        r#"{"path": "a", "type": "file", "source": "missing.txt"}"#,
+        r#"{"path": "a", "type": "dir", "data": "x"}"#,
+        r#"{"path": "a", "type": "symlink"}"#,
+        r#"{"path": "a/..", "type": "dir"}"#,
+        "{\"path\": \"a\", \"type\": \"file\", \"data\": \"x\"}\n\
+         {\"path\": \"a\", \"type\": \"file\", \"data\": \"y\"}",
+    ];
+    for manifest in cases {
+        let manifest = Manifest::parse(manifest.as_bytes()).unwrap();
+        assert!(manifest.to_tree(&dir).is_err(), "{manifest:?}");
+    }
+
+    for manifest in [
+        r#"{"path": "a", "type": "file", "data": "x", "mode": "9"}"#,
+        r#"{"path": "a", "type": "file", "data": "x""#,
+        r#"{"type": "dir"}"#,
+    ] {
+        assert!(Manifest::parse(manifest.as_bytes()).is_err(), "{manifest}");
+    }
+}
+
+#[test]
+fn manifest_rejects_root_entries_that_are_not_directories() {
+    let dir = temp_dir("root-entries");
+    for path in ["", "/", "//"] {
+        for entry in [r#""type": "file", "data": "x""#, r#""type": "symlink", "target": "a""#] {
+            let manifest = format!(r#"{{"path": "{path}", {entry}}}"#);
+            let manifest = Manifest::parse(manifest.as_bytes()).unwrap();
+            let err = manifest.to_tree(&dir).unwrap_err();
+            assert!(matches!(err.kind(), Some(ErrorKind::ManifestRootEntry(..))), "{err}");
+            assert!(err.to_string().contains(&format!("{path:?}")), "{err}");
+        }
+    }
+
+    let manifest = r#"{"path": "/", "type": "dir", "mode": "755"}"#;
+    let manifest = Manifest::parse(manifest.as_bytes()).unwrap();
+    assert!(manifest.to_tree(&dir).is_ok());
+}
+
+#[test]
+fn exported_manifest_lists_entries() {
+    let dir = temp_dir("export");
+    let manifest = concat!(
+        r#"{"path": "src/lib.rs", "type": "file", "data": "pub fn lib() {}", "mode": "644"}"#,
+        "\n",
+        r#"{"path": "src/main.rs", "type": "file", "data": "fn main() {}"}"#,
+        "\n",
+        r#"{"path": "link", "type": "symlink", "target": "src/lib.rs"}"#,
+        "\n",
+        r#"{"path": "empty", "type": "dir"}"#,
+    );
+    let archive = compress(manifest, &dir);
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+
+    let entries = reader.entries().unwrap();
+    let paths: Vec<_> = entries.iter().map(|x| x.path.as_str()).collect();
+    assert_eq!(paths, ["empty", "link", "src", "src/lib.rs", "src/main.rs"]);
+    let lib = &entries[3];
+    assert_eq!(lib.kind, ManifestEntryType::File);
+    assert_eq!(lib.size, Some(15));
+    assert_eq!(lib.blake3.as_deref(), Some(blake3::hash(b"pub fn lib() {}").to_hex().as_str()));
+    assert_eq!(lib.metadata.mode, Some(0o644));
+    assert_eq!(entries[1].kind, ManifestEntryType::Symlink);
+    assert_eq!(entries[1].target.as_deref(), Some("src/lib.rs"));
+    assert_eq!(entries[0].kind, ManifestEntryType::Dir);
+
+    let mut csv = Vec::new();
+    reader
+        .export_manifest(&mut csv, ManifestFormat::Csv)
+        .unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert!(lines.next().unwrap().starts_with("path,type,size,"));
+    assert_eq!(lines.count(), entries.len());
+
+    // exported manifests can be read back to rebuild the same tree
+    let mut json = Vec::new();
+    reader
+        .export_manifest(&mut json, ManifestFormat::JsonLines)
+        .unwrap();
+    let exported = Manifest::parse(json.as_slice()).unwrap();
+    assert_eq!(exported.entries.len(), entries.len());
+    assert_eq!(exported.entries[3].metadata.mode, Some(0o644));
+}
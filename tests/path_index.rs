@@ -0,0 +1,127 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+};
+use std::io::Cursor;
+
+fn files() -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = (0..50)
+        .map(|i| (format!("pkg{}/lib/mod{i}.rs", i % 5), contents(i, 400)))
+        .collect();
+    files.push(("README".into(), contents(100, 1000)));
+    files.push(("pkg0/lib/deep/er/still.rs".into(), contents(101, 5000)));
+    files
+}
+
+fn compress(cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files() {
+        tree.insert(&path, DirNode::file(DataSource::from_data(data)))
+            .unwrap();
+    }
+    tree.insert("pkg9/empty", DirNode::empty_dir()).unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+fn check_lookups(cfg: &CompressConfiguration) {
+    let mut reader = ArchiveReader::new(Cursor::new(compress(cfg))).unwrap();
+    assert!(reader.has_path_index());
+
+    for (path, data) in files() {
+        let id = reader.lookup(&path).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), data, "{path}");
+    }
+    for dir in ["pkg3/lib", "pkg0/lib/deep", "pkg9/empty"] {
+        let id = reader.lookup(dir).unwrap().unwrap();
+        assert!(reader.is_dir(id).unwrap(), "{dir}");
+    }
+    assert_eq!(reader.lookup("/").unwrap(), Some(reader.root_dir()));
+    assert_eq!(reader.lookup("pkg1/lib/").unwrap(), reader.lookup("pkg1/lib").unwrap());
+    assert_eq!(reader.lookup("pkg1/lib/mod0.rs").unwrap(), None);
+    assert_eq!(reader.lookup("pkg7/lib").unwrap(), None);
+    assert_eq!(reader.lookup("README/inner").unwrap(), None);
+}
+
+#[test]
+fn lookup_with_path_index() {
+    check_lookups(&CompressConfiguration::default().path_index(true));
+}
+
+#[test]
+fn lookup_with_hashed_path_index() {
+    check_lookups(
+        &CompressConfiguration::default()
+            .path_index(true)
+            .hash_objects(true),
+    );
+}
+
+#[test]
+fn lookup_without_path_index() {
+    let mut reader =
+        ArchiveReader::new(Cursor::new(compress(&CompressConfiguration::default()))).unwrap();
+    assert!(!reader.has_path_index());
+    let id = reader.lookup("pkg0/lib/deep/er/still.rs").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), contents(101, 5000));
+    assert_eq!(reader.lookup("pkg1/lib/mod0.rs").unwrap(), None);
+}
+
+#[test]
+fn damaged_hashed_path_index_never_gives_wrong_results() {
+    let cfg = CompressConfiguration::default()
+        .path_index(true)
+        .hash_objects(true);
+    let archive = compress(&cfg);
+    let expected = contents(12, 400);
+
+    // the path index is written just before the archive and root objects at the end
+    let mut failures = 0;
+    for pos in (archive.len().saturating_sub(3000)..archive.len()).step_by(5) {
+        let mut damaged = archive.clone();
+        damaged[pos] ^= 0x40;
+        let Ok(mut reader) = ArchiveReader::new(Cursor::new(damaged)) else {
+            continue;
+        };
+        match reader.lookup("pkg2/lib/mod12.rs") {
+            Ok(Some(id)) => match reader.read_file(id) {
+                Ok(data) => assert_eq!(data, expected),
+                Err(_) => failures += 1,
+            },
+            Ok(None) => panic!("lookup missed an existing path at {pos}"),
+            Err(_) => failures += 1,
+        }
+    }
+    assert!(failures > 0);
+}
+
+#[test]
+fn lookup_in_large_path_index() {
+    let mut tree = DirNode::empty_dir();
+    for i in 0..2000 {
+        let node = DirNode::file(DataSource::from_data(format!("file {i}")));
+        tree.insert(&format!("d{}/f{i}", i % 40), node).unwrap();
+    }
+    for hash_objects in [false, true] {
+        let cfg = CompressConfiguration::default()
+            .path_index(true)
+            .hash_objects(hash_objects);
+        let mut out = Cursor::new(Vec::new());
+        compress_nodes(&tree, &mut out, &cfg).unwrap();
+        let mut reader = ArchiveReader::new(Cursor::new(out.into_inner())).unwrap();
+
+        for i in (0..2000).step_by(7) {
+            let id = reader
+                .lookup(&format!("d{}/f{i}", i % 40))
+                .unwrap()
+                .unwrap();
+            assert_eq!(reader.read_file(id).unwrap(), format!("file {i}").as_bytes());
+        }
+        assert_eq!(reader.lookup("d0/f1").unwrap(), None);
+        assert_eq!(reader.lookup("d40").unwrap(), None);
+    }
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+    EncryptionKey, EntryMetadata, ErrorKind,
+};
+use std::{
+    io,
+    io::{Cursor, Read, Seek, SeekFrom},
+};
+
+/// A stream that fails every read within a range of offsets.
+struct FailingStream {
+    inner: Cursor<Vec<u8>>,
+    fail: std::ops::Range<u64>,
+}
+impl Read for FailingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.fail.contains(&self.inner.position()) {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "disk went away"));
+        }
+        self.inner.read(buf)
+    }
+}
+impl Seek for FailingStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn key() -> EncryptionKey {
+    EncryptionKey::Raw([7; 32])
+}
+
+fn archive(cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("small.txt", DirNode::file(DataSource::from_data("small")))
+        .unwrap();
+    tree.insert("data/big.bin", DirNode::file(DataSource::from_data(noise(1, 200_000))))
+        .unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+fn open(archive: Vec<u8>) -> diar::Result<ArchiveReader<Cursor<Vec<u8>>>> {
+    let cfg = ReaderConfiguration::default().decryption_key(key());
+    ArchiveReader::new_with_config(Cursor::new(archive), &cfg)
+}
+
+#[test]
+fn damaged_framing_is_corruption() {
+    let mut bad_magic = archive(&Default::default());
+    bad_magic[0] ^= 1;
+    let err = open(bad_magic).err().unwrap();
+    assert!(matches!(err.kind(), Some(ErrorKind::BadMagic(..))), "{err}");
+    assert!(err.is_corruption());
+
+    let mut truncated = archive(&Default::default());
+    truncated.truncate(truncated.len() - 10);
+    let err = open(truncated).err().unwrap();
+    assert!(matches!(err.kind(), Some(ErrorKind::TruncatedTrailer(..))), "{err}");
+    assert!(err.is_corruption());
+
+    let err = open(b"DiarArc1".to_vec()).err().unwrap();
+    assert!(err.is_corruption(), "{err}");
+}
+
+#[test]
+fn io_errors_while_reading_files_stay_io_errors() {
+    for cfg in [
+        CompressConfiguration::default(),
+        CompressConfiguration::default().encryption_key(key()),
+    ] {
+        let archive = archive(&cfg);
+        let fail = 16..archive.len() as u64 / 2;
+        let stream = FailingStream { inner: Cursor::new(archive), fail };
+        let rcfg = ReaderConfiguration::default().decryption_key(key());
+        let mut reader = ArchiveReader::new_with_config(stream, &rcfg).unwrap();
+
+        let id = reader.lookup("data/big.bin").unwrap().unwrap();
+        let err = reader.read_file(id).unwrap_err();
+        match err.kind() {
+            Some(ErrorKind::IoError(e, _)) => {
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+            }
+            _ => panic!("expected an IO error, got {err}"),
+        }
+        assert!(!err.is_corruption());
+    }
+}
+
+#[test]
+fn damaged_encrypted_data_fails_to_decompress() {
+    let cfg = CompressConfiguration::default().encryption_key(key());
+    let mut archive = archive(&cfg);
+
+    // the largest file takes up most of the archive, so damage the middle of it
+    let mid = archive.len() / 2;
+    archive[mid] ^= 1;
+    let mut reader = open(archive).unwrap();
+    let id = reader.lookup("data/big.bin").unwrap().unwrap();
+    let err = reader.read_file(id).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::DecompressionFailed(..))), "{err}");
+    assert!(err.is_corruption());
+}
+
+#[test]
+fn wrong_key_fails_to_unseal_listings() {
+    let cfg = CompressConfiguration::default()
+        .encryption_key(key())
+        .encrypt_listings(true);
+    let archive = archive(&cfg);
+
+    let rcfg = ReaderConfiguration::default().decryption_key(EncryptionKey::Raw([8; 32]));
+    let err = match ArchiveReader::new_with_config(Cursor::new(archive.clone()), &rcfg) {
+        Ok(mut reader) => {
+            let root = reader.root_dir();
+            reader.read_dir(root).unwrap_err()
+        }
+        Err(e) => e,
+    };
+    assert!(!matches!(err.kind(), Some(ErrorKind::IoError(..))), "{err}");
+
+    let mut reader = open(archive).unwrap();
+    let id = reader.lookup("small.txt").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), b"small");
+}
+
+#[test]
+fn misuse_is_not_corruption() {
+    let mut reader = open(archive(&Default::default())).unwrap();
+    let dir = reader.lookup("data").unwrap().unwrap();
+    let err = reader.read_file(dir).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::WrongObjectType(..))), "{err}");
+    assert!(!err.is_corruption(), "{err}");
+}
+
+#[test]
+fn references_to_the_wrong_type_are_corruption() {
+    let user = "someuser";
+    let mut tree = DirNode::empty_dir();
+    let metadata = EntryMetadata::default().user(user);
+    let node = DirNode::file(DataSource::from_data("owned")).with_metadata(metadata);
+    tree.insert("owned.txt", node).unwrap();
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, &Default::default()).unwrap();
+    let mut archive = out.into_inner();
+
+    // turn the metadata object into a symlink, whose target is read from the start of the table
+    let mut entry = vec![4, 3, user.len() as u8];
+    entry.extend_from_slice(user.as_bytes());
+    let mut found = archive
+        .windows(entry.len())
+        .enumerate()
+        .filter(|(_, x)| *x == entry);
+    let (offset, _) = found.next().unwrap();
+    assert!(found.next().is_none());
+    // type, data length, then fields length
+    assert_eq!(archive[offset - 3], 2);
+    archive[offset - 3] = 6;
+
+    let mut reader = open(archive).unwrap();
+    let root = reader.root_dir();
+    let entry = reader.read_dir(root).unwrap().remove(0);
+    let err = reader.read_metadata(entry.metadata).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::WrongObjectType(..))), "{err}");
+    assert!(!err.is_corruption());
+
+    let err = reader.extract(root, temp_dir("wrong-type")).unwrap_err();
+    assert!(err.is_corruption(), "{err}");
+}
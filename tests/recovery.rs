@@ -0,0 +1,169 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    recovery::{
+        add_recovery_record, check_recovery_record, repair_recovery_record, RecoveryConfiguration,
+    },
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+};
+use std::io::Cursor;
+
+fn files() -> Vec<(String, Vec<u8>)> {
+    (0..8)
+        .map(|i| (format!("dir{}/file{i}.txt", i % 3), contents(i, 4000)))
+        .collect()
+}
+
+fn archive() -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files() {
+        tree.insert(&path, DirNode::file(DataSource::from_data(data)))
+            .unwrap();
+    }
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, &CompressConfiguration::default()).unwrap();
+    out.into_inner()
+}
+
+fn protected_archive() -> Vec<u8> {
+    let mut stream = Cursor::new(archive());
+    let cfg = RecoveryConfiguration::default()
+        .percent(20)
+        .shard_size(1024);
+    add_recovery_record(&mut stream, &cfg).unwrap();
+    stream.into_inner()
+}
+
+fn assert_readable(archive: &[u8]) {
+    let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+    for (path, data) in files() {
+        let id = reader.lookup(&path).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), data, "{path}");
+    }
+    let report = reader.verify();
+    assert!(report.is_ok());
+    assert!(report.recovery.is_none_or(|x| x.is_intact()));
+}
+
+#[test]
+fn recovery_record_keeps_archive_readable() {
+    let archive = archive();
+    let protected = protected_archive();
+    // the record is appended after the archive, without truncating it
+    assert!(protected.starts_with(&archive[..archive.len() - 24]));
+    assert_readable(&protected);
+
+    let report = check_recovery_record(Cursor::new(&protected))
+        .unwrap()
+        .unwrap();
+    assert!(report.is_intact());
+    assert!(report.covered > 0);
+    assert!(check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn repair_rebuilds_damaged_archive() {
+    let mut archive = protected_archive();
+    for byte in &mut archive[3000..3100] {
+        *byte ^= 0xFF;
+    }
+    let len = archive.len();
+    archive[len - 10] ^= 0xFF;
+
+    let report = check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .unwrap();
+    assert!(!report.is_intact());
+    assert!(report.damaged_shards > 0);
+    assert!(report.trailer_damaged);
+    assert!(report.is_repairable());
+    let verify = ArchiveReader::new(Cursor::new(&archive[..])).map(|mut x| x.verify());
+    if let Ok(verify) = verify {
+        assert!(!verify.is_ok());
+    }
+
+    let mut stream = Cursor::new(archive);
+    let report = repair_recovery_record(&mut stream).unwrap();
+    assert!(report.repaired_shards > 0);
+    let archive = stream.into_inner();
+    assert_readable(&archive);
+    assert!(check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .unwrap()
+        .is_intact());
+}
+
+#[test]
+fn heavy_damage_is_not_repairable() {
+    let mut archive = protected_archive();
+    let len = archive.len();
+    for byte in &mut archive[100..len / 2] {
+        *byte ^= 0xFF;
+    }
+
+    let report = check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .unwrap();
+    assert!(!report.is_repairable());
+    assert!(!report.unrecoverable.is_empty());
+}
+
+#[test]
+fn truncated_record_is_not_an_error() {
+    let archive = protected_archive();
+    for cut in [1, 24, 100, 5000] {
+        let truncated = &archive[..archive.len() - cut];
+        assert!(check_recovery_record(Cursor::new(truncated)).is_ok());
+    }
+}
+
+#[test]
+fn damaged_locator_is_repaired_from_its_copy() {
+    let mut archive = protected_archive();
+    let len = archive.len();
+    // the last locator sits directly before the 24 byte trailer
+    for byte in &mut archive[len - 24 - 40..len - 24] {
+        *byte = 0;
+    }
+
+    let report = check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .unwrap();
+    assert!(report.index_damaged);
+    assert_eq!(report.damaged_shards, 0);
+
+    let mut stream = Cursor::new(archive);
+    repair_recovery_record(&mut stream).unwrap();
+    let archive = stream.into_inner();
+    assert!(check_recovery_record(Cursor::new(&archive))
+        .unwrap()
+        .unwrap()
+        .is_intact());
+}
+
+#[test]
+fn replacing_record_keeps_archive_readable() {
+    let archive = protected_archive();
+    let mut stream = Cursor::new(archive.clone());
+    add_recovery_record(&mut stream, &RecoveryConfiguration::default().percent(10)).unwrap();
+    let readded = stream.into_inner();
+    assert!(readded.len() > archive.len());
+    assert_readable(&readded);
+    assert!(check_recovery_record(Cursor::new(&readded))
+        .unwrap()
+        .unwrap()
+        .is_intact());
+}
+
+#[test]
+fn recovery_record_requires_an_archive() {
+    let data = b"not an archive, but long enough to have a trailer".to_vec();
+    let mut stream = Cursor::new(data.clone());
+    let cfg = RecoveryConfiguration::default();
+    assert!(add_recovery_record(&mut stream, &cfg).is_err());
+    assert_eq!(stream.into_inner(), data);
+}
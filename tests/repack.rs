@@ -0,0 +1,99 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    writer::{
+        compress_nodes_to_file, repack_file, snapshot_nodes_to_file, CompressConfiguration,
+        DataSource, DirNode, RepackConfiguration,
+    },
+    EncryptionKey,
+};
+use std::{
+    fs,
+    io::{Read, Seek},
+};
+
+/// Returns the files of a version of a project, where only `data/changing.bin` differs between
+/// versions.
+fn version(seed: u32) -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("README.md", contents(1, 800)),
+        ("src/lib.rs", contents(2, 6000)),
+        ("data/big.bin", noise(3, 100_000)),
+        ("data/changing.bin", contents(seed, 50_000)),
+    ]
+}
+
+fn tree(files: &[(&str, Vec<u8>)]) -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files {
+        tree.insert(path, DirNode::file(DataSource::from_data(data.clone())))
+            .unwrap();
+    }
+    tree
+}
+
+fn assert_files(reader: &mut ArchiveReader<impl Read + Seek>, files: &[(&str, Vec<u8>)]) {
+    for (path, data) in files {
+        let id = reader.lookup(path).unwrap().unwrap();
+        assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+    }
+}
+
+#[test]
+fn repack_removes_old_snapshots() {
+    let path = temp_dir("repack-snapshots").join("archive.diar");
+    let cfg = CompressConfiguration::default()
+        .hash_objects(true)
+        .path_index(true);
+    for day in 0..3 {
+        let cfg = cfg.clone().snapshot_time(day * 86400);
+        snapshot_nodes_to_file(&tree(&version(day as u32 + 30)), &path, &cfg).unwrap();
+    }
+
+    let report = repack_file(&path, &cfg, &RepackConfiguration::default().keep_last(1)).unwrap();
+    assert_eq!(report.kept, ["1970-01-03T00:00:00Z"]);
+    assert_eq!(report.removed, ["1970-01-01T00:00:00Z", "1970-01-02T00:00:00Z"]);
+    assert!(report.new_length < report.old_length);
+    assert_eq!(fs::metadata(&path).unwrap().len(), report.new_length);
+
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    assert_eq!(reader.snapshots().unwrap().len(), 1);
+    assert_files(&mut reader, &version(32));
+    assert!(reader.verify().is_ok());
+}
+
+#[test]
+fn recompressing_keeps_contents() {
+    let path = temp_dir("repack-recompress").join("archive.diar");
+    let cfg = CompressConfiguration::default().hash_objects(true);
+    compress_nodes_to_file(&tree(&version(30)), &path, &cfg).unwrap();
+
+    let repack = RepackConfiguration::default().recompress(true);
+    repack_file(&path, &CompressConfiguration::default(), &repack).unwrap();
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    assert!(reader.is_hashed());
+    assert_files(&mut reader, &version(30));
+    assert!(reader.verify().is_ok());
+}
+
+#[test]
+fn sealed_listings_stay_sealed() {
+    let path = temp_dir("repack-sealed").join("archive.diar");
+    let key = EncryptionKey::Raw([9; 32]);
+    let cfg = CompressConfiguration::default().encryption_key(key.clone());
+    let sealing = cfg.clone().encrypt_listings(true);
+    compress_nodes_to_file(&tree(&version(30)), &path, &sealing).unwrap();
+
+    for recompress in [false, true] {
+        let repack = RepackConfiguration::default().recompress(recompress);
+        repack_file(&path, &cfg, &repack).unwrap();
+        let data = fs::read(&path).unwrap();
+        assert!(!data.windows(7).any(|x| x == b"big.bin"));
+
+        let reader_cfg = ReaderConfiguration::default().decryption_key(key.clone());
+        let mut reader = ArchiveReader::open_with_config(&path, &reader_cfg).unwrap();
+        assert_files(&mut reader, &version(30));
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::*;
+use diar::{
+    writer::{compress_nodes, compress_with_config, CompressConfiguration, DataSource, DirNode},
+    EntryMetadata,
+};
+use std::io::Cursor;
+
+fn tree_with_mtime(mtime: i64) -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for (i, name) in ["a.txt", "b/c.txt", "b/d.txt", "b/e/f.txt"]
+        .into_iter()
+        .enumerate()
+    {
+        let metadata = EntryMetadata::default()
+            .mode(0o644)
+            .uid(1000)
+            .user("user")
+            .mtime(mtime);
+        let node = DirNode::file(DataSource::from_data(contents(i as u32, 3000)));
+        tree.insert(name, node.with_metadata(metadata)).unwrap();
+    }
+    tree.insert("big.bin", DirNode::file(DataSource::from_data(contents(9, 200_000))))
+        .unwrap();
+    tree
+}
+
+fn compress(tree: &DirNode, cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn output_is_byte_identical() {
+    let cfg = CompressConfiguration::default().hash_objects(true);
+    let first = compress(&tree_with_mtime(1_600_000_000), &cfg);
+    for _ in 0..3 {
+        assert_eq!(compress(&tree_with_mtime(1_600_000_000), &cfg), first);
+    }
+
+    let dir = temp_dir("dir");
+    let files: Vec<_> = (0..12)
+        .map(|i| (format!("d{}/f{i}", i % 4), contents(i, 20_000)))
+        .collect();
+    write_files(&dir, &files);
+    let cfg = cfg.mtime_clamp(0);
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let mut out = Vec::new();
+        compress_with_config(&dir, &mut out, &cfg).unwrap();
+        outputs.push(out);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn modification_times_are_clamped() {
+    let cfg = CompressConfiguration::default();
+    let early = compress(&tree_with_mtime(1_600_000_000), &cfg);
+    assert_ne!(compress(&tree_with_mtime(1_700_000_000), &cfg), early);
+
+    let clamped = cfg.mtime_clamp(1_500_000_000);
+    let early = compress(&tree_with_mtime(1_600_000_000), &clamped);
+    assert_eq!(compress(&tree_with_mtime(1_700_000_000), &clamped), early);
+    assert_eq!(compress(&tree_with_mtime(1_500_000_000), &clamped), early);
+    assert_ne!(compress(&tree_with_mtime(1_400_000_000), &clamped), early);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    writer::{compress_nodes_to_file, CompressConfiguration, DataSource, DirNode},
+    EncryptionKey, FormatInfo,
+};
+use std::{fs, fs::File, path::Path};
+
+fn files() -> Vec<(String, Vec<u8>)> {
+    (0..24)
+        .map(|i| (format!("part{}/chunk{i}.bin", i % 4), noise(i + 1, 2000)))
+        .collect()
+}
+
+fn tree() -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files() {
+        tree.insert(&path, DirNode::file(DataSource::from_data(data)))
+            .unwrap();
+    }
+    tree
+}
+
+fn resume_cfg() -> CompressConfiguration {
+    CompressConfiguration::default()
+        .checkpoint_interval(8192)
+        .hash_objects(true)
+        .path_index(true)
+}
+
+fn assert_complete(target: &Path) {
+    let mut reader = ArchiveReader::open(target).unwrap();
+    for (path, data) in files() {
+        let id = reader.lookup(&path).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), data, "{path}");
+    }
+    assert!(reader.verify().is_ok());
+    assert!(!Path::new(&format!("{}.partial", target.display())).exists());
+}
+
+#[test]
+fn resume_continues_from_checkpoint() {
+    let dir = temp_dir("resume-checkpoint");
+    let full = dir.join("full.diar");
+    compress_nodes_to_file(&tree(), &full, &resume_cfg()).unwrap();
+    let data = fs::read(&full).unwrap();
+
+    for percent in [0, 10, 50, 90, 99] {
+        let target = dir.join(format!("{percent}.diar"));
+        let partial = dir.join(format!("{percent}.diar.partial"));
+        fs::write(&partial, &data[..data.len() * percent / 100]).unwrap();
+        compress_nodes_to_file(&tree(), &target, &resume_cfg().resume(true)).unwrap();
+        assert_complete(&target);
+    }
+}
+
+#[test]
+fn damaged_finished_partial_is_written_again() {
+    let dir = temp_dir("resume-damaged");
+    let target = dir.join("archive.diar");
+    compress_nodes_to_file(&tree(), &target, &resume_cfg()).unwrap();
+    let mut data = fs::read(&target).unwrap();
+    fs::remove_file(&target).unwrap();
+
+    let len = data.len();
+    data[len / 2] ^= 0xFF;
+    fs::write(dir.join("archive.diar.partial"), &data).unwrap();
+    compress_nodes_to_file(&tree(), &target, &resume_cfg().resume(true)).unwrap();
+    assert_complete(&target);
+}
+
+#[test]
+fn finished_partial_with_damaged_trailer_is_written_again() {
+    let dir = temp_dir("resume-damaged-trailer");
+    let target = dir.join("archive.diar");
+    compress_nodes_to_file(&tree(), &target, &resume_cfg()).unwrap();
+    let mut data = fs::read(&target).unwrap();
+    fs::remove_file(&target).unwrap();
+
+    let len = data.len();
+    data[len - 1] ^= 0xFF;
+    fs::write(dir.join("archive.diar.partial"), &data).unwrap();
+    compress_nodes_to_file(&tree(), &target, &resume_cfg().resume(true)).unwrap();
+    assert_complete(&target);
+}
+
+#[test]
+fn resume_starts_over_when_features_change() {
+    let dir = temp_dir("resume-features");
+    let target = dir.join("archive.diar");
+    let cfg = resume_cfg().path_index(false);
+    compress_nodes_to_file(&tree(), &target, &cfg).unwrap();
+    let data = fs::read(&target).unwrap();
+    fs::write(dir.join("archive.diar.partial"), &data[..data.len() / 2]).unwrap();
+
+    compress_nodes_to_file(&tree(), &target, &resume_cfg().resume(true)).unwrap();
+    assert_complete(&target);
+    let reader = ArchiveReader::open(&target).unwrap();
+    assert_ne!(reader.format().optional_features & FormatInfo::OPTIONAL_PATH_INDEX, 0);
+}
+
+#[test]
+fn locked_partial_is_not_clobbered() {
+    let dir = temp_dir("resume-locked");
+    let target = dir.join("archive.diar");
+    let partial = dir.join("archive.diar.partial");
+    fs::write(&partial, b"written by another run").unwrap();
+    let lock = File::options().write(true).open(&partial).unwrap();
+    lock.try_lock().unwrap();
+
+    for resume in [false, true] {
+        let cfg = resume_cfg().resume(resume);
+        assert!(compress_nodes_to_file(&tree(), &target, &cfg).is_err());
+        assert_eq!(fs::read(&partial).unwrap(), b"written by another run");
+    }
+    assert!(!target.exists());
+}
+
+#[test]
+fn resume_keeps_sealed_listings() {
+    let dir = temp_dir("resume-sealed");
+    let key = EncryptionKey::Raw([5; 32]);
+    let cfg = resume_cfg()
+        .encryption_key(key.clone())
+        .encrypt_listings(true);
+    let full = dir.join("full.diar");
+    compress_nodes_to_file(&tree(), &full, &cfg).unwrap();
+    let data = fs::read(&full).unwrap();
+
+    let target = dir.join("archive.diar");
+    fs::write(dir.join("archive.diar.partial"), &data[..data.len() / 2]).unwrap();
+    compress_nodes_to_file(&tree(), &target, &cfg.resume(true)).unwrap();
+
+    let reader_cfg = ReaderConfiguration::default().decryption_key(key);
+    let mut reader = ArchiveReader::open_with_config(&target, &reader_cfg).unwrap();
+    for (path, data) in files() {
+        let id = reader.lookup(&path).unwrap().unwrap();
+        assert_eq!(reader.read_file(id).unwrap(), data, "{path}");
+    }
+    assert!(reader.verify().is_ok());
+    // most files were not written again, so the archive is no larger than writing it once
+    assert!(fs::metadata(&target).unwrap().len() <= data.len() as u64 + 4096);
+}
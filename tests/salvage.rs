@@ -0,0 +1,142 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration, ReaderLimits},
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+};
+use std::{fs, io::Cursor, path::Path};
+
+fn files() -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = (0..6)
+        .map(|i| (format!("docs/part{i}.txt"), contents(i, 4000)))
+        .collect();
+    files.push(("noise.bin".into(), noise(0x2545F491, 50_000)));
+    files.push(("top.txt".into(), contents(10, 300)));
+    files
+}
+
+fn archive(cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files() {
+        tree.insert(&path, DirNode::file(DataSource::from_data(data)))
+            .unwrap();
+    }
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(&tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+fn read_dir_files(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut stack = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = stack.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().into_string().unwrap();
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            };
+            let ty = entry.file_type().unwrap();
+            if ty.is_dir() {
+                stack.push((entry.path(), path));
+            } else if ty.is_file() {
+                files.push((path, fs::read(entry.path()).unwrap()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn salvage_archive_without_trailer() {
+    let archive = archive(&CompressConfiguration::default().sync_markers(true));
+    let out = temp_dir("trailer");
+
+    let truncated = &archive[..archive.len() - 30];
+    let cfg = ReaderConfiguration::default();
+    let report = ArchiveReader::salvage(Cursor::new(truncated), &cfg, &out).unwrap();
+    assert!(report.found_root);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+    assert!(report.orphans.is_empty());
+    assert_eq!(report.files, 8);
+    let mut expected = files();
+    expected.sort();
+    assert_eq!(read_dir_files(&out), expected);
+}
+
+#[test]
+fn salvage_keeps_undamaged_files() {
+    let cfg = CompressConfiguration::default()
+        .sync_markers(true)
+        .hash_objects(true);
+    let mut archive = archive(&cfg);
+    let noise = noise(0x2545F491, 50_000);
+    let offset = find_noise(&archive, &noise, 40_000);
+    archive[offset..offset + 64].fill(0);
+    let out = temp_dir("damaged");
+
+    let cfg = ReaderConfiguration::default();
+    let report = ArchiveReader::salvage(Cursor::new(&archive), &cfg, &out).unwrap();
+    assert!(report.found_root);
+    assert_eq!(report.issues.len(), 1, "{:?}", report.issues);
+    assert_eq!(report.issues[0].path.as_deref(), Some("noise.bin"));
+    let recovered = read_dir_files(&out);
+    for (path, data) in files() {
+        if path != "noise.bin" {
+            assert!(recovered.contains(&(path, data)));
+        }
+    }
+}
+
+#[test]
+fn salvage_unfinished_archive_keeps_complete_files() {
+    let archive = archive(&CompressConfiguration::default().sync_markers(true));
+    let out = temp_dir("unfinished");
+
+    // cut off the root directory and the objects after it, leaving the files as orphans
+    let cfg = ReaderConfiguration::default();
+    let report = ArchiveReader::salvage(Cursor::new(&archive[..archive.len() - 1000]), &cfg, &out);
+    let report = report.unwrap();
+    assert!(!report.found_root);
+    assert!(!report.damaged.is_empty());
+    assert!(!report.orphans.is_empty());
+    assert!(report.files > 0);
+    for (_, data) in read_dir_files(&out.join("lost+found")) {
+        assert!(files().iter().any(|(_, x)| *x == data));
+    }
+}
+
+#[test]
+fn salvage_requires_sync_markers() {
+    let archive = archive(&CompressConfiguration::default());
+    let out = temp_dir("no-markers");
+    let cfg = ReaderConfiguration::default();
+    assert!(ArchiveReader::salvage(Cursor::new(&archive), &cfg, &out).is_err());
+}
+
+#[test]
+fn failed_directories_do_not_affect_their_siblings() {
+    let mut tree = DirNode::empty_dir();
+    for dir in ["a", "b", "c"] {
+        let file = DirNode::file(DataSource::from_data(dir.as_bytes()));
+        tree.insert(&format!("{dir}/file"), file).unwrap();
+    }
+    let mut archive = Cursor::new(Vec::new());
+    let cfg = CompressConfiguration::default().sync_markers(true);
+    compress_nodes(&tree, &mut archive, &cfg).unwrap();
+    let out = temp_dir("salvage-siblings");
+
+    // a file in the way of `a` makes salvaging it fail after entering it
+    fs::write(out.join("a"), b"in the way").unwrap();
+    let cfg = ReaderConfiguration::default().limits(ReaderLimits::default().max_depth(2));
+    archive.set_position(0);
+    let report = ArchiveReader::salvage(archive, &cfg, &out).unwrap();
+    assert_eq!(report.issues.len(), 1, "{:?}", report.issues);
+    assert_eq!(report.issues[0].path.as_deref(), Some("a"));
+    assert_eq!(fs::read(out.join("b/file")).unwrap(), b"b");
+    assert_eq!(fs::read(out.join("c/file")).unwrap(), b"c");
+}
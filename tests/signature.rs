@@ -0,0 +1,110 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    signature::{sign_detached, verify_detached, SignatureStatus, SigningKey},
+    writer::{compress_with_config, CompressConfiguration},
+    ErrorKind,
+};
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+/// Writes a directory of documents, enough for the compressor to train a dictionary on.
+fn docs_dir(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    let files: Vec<_> = (0..12)
+        .map(|i| (format!("page{i}.md"), contents(i, 20_000)))
+        .collect();
+    write_files(&dir, &files);
+    dir
+}
+
+fn compress(dir: &Path, cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_with_config(dir, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+#[test]
+fn root_hash_commits_to_contents() {
+    let dir = docs_dir("root-hash");
+    let cfg = CompressConfiguration::default().hash_objects(true);
+    let archive = compress(&dir, &cfg);
+    let reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+    let root = reader.root_hash().unwrap().unwrap();
+
+    fs::write(dir.join("page3.md"), contents(100, 20_000)).unwrap();
+    let changed = compress(&dir, &cfg);
+    let reader = ArchiveReader::new(Cursor::new(&changed)).unwrap();
+    assert_ne!(reader.root_hash().unwrap(), Some(root));
+
+    let plain = compress(&dir, &CompressConfiguration::default());
+    let reader = ArchiveReader::new(Cursor::new(&plain)).unwrap();
+    assert_eq!(reader.root_hash().unwrap(), None);
+}
+
+#[test]
+fn embedded_signature_round_trip() {
+    let dir = docs_dir("embedded");
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let other = SigningKey::from_bytes(&[2; 32]);
+    let archive = compress(&dir, &CompressConfiguration::default().signing_key(key.clone()));
+
+    let reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+    assert!(reader.is_hashed());
+    assert_eq!(reader.verify_signature(&key.verifying_key()).unwrap(), SignatureStatus::Valid);
+    let status = reader.verify_signature(&other.verifying_key()).unwrap();
+    assert_eq!(status, SignatureStatus::Invalid);
+
+    let unsigned = compress(&dir, &CompressConfiguration::default());
+    let reader = ArchiveReader::new(Cursor::new(&unsigned)).unwrap();
+    let status = reader.verify_signature(&key.verifying_key()).unwrap();
+    assert_eq!(status, SignatureStatus::Unsigned);
+}
+
+#[test]
+fn required_signatures_are_checked_on_open() {
+    let dir = docs_dir("required");
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let other = SigningKey::from_bytes(&[2; 32]);
+    let archive = compress(&dir, &CompressConfiguration::default().signing_key(key.clone()));
+    let unsigned = compress(&dir, &CompressConfiguration::default());
+
+    let cfg = ReaderConfiguration::default().require_signature(key.verifying_key());
+    let mut reader = ArchiveReader::new_with_config(Cursor::new(&archive), &cfg).unwrap();
+    let id = reader.lookup("page0.md").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), contents(0, 20_000));
+    let err = ArchiveReader::new_with_config(Cursor::new(&unsigned), &cfg)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), Some(ErrorKind::SignatureMissing(..))), "{err}");
+
+    let cfg = ReaderConfiguration::default().require_signature(other.verifying_key());
+    let err = ArchiveReader::new_with_config(Cursor::new(&archive), &cfg)
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), Some(ErrorKind::SignatureInvalid(..))), "{err}");
+    assert!(!err.is_corruption());
+}
+
+#[test]
+fn detached_signature_round_trip() {
+    let dir = docs_dir("detached");
+    let key = SigningKey::from_bytes(&[3; 32]);
+    let cfg = CompressConfiguration::default().hash_objects(true);
+    let archive = compress(&dir, &cfg);
+    let signature = sign_detached(Cursor::new(&archive), &key).unwrap();
+    assert!(verify_detached(Cursor::new(&archive), &key.verifying_key(), &signature).unwrap());
+
+    fs::remove_file(dir.join("page5.md")).unwrap();
+    let changed = compress(&dir, &cfg);
+    assert!(!verify_detached(Cursor::new(&changed), &key.verifying_key(), &signature).unwrap());
+
+    // there is nothing to sign without a root hash
+    let unhashed = compress(&dir, &CompressConfiguration::default());
+    assert!(sign_detached(Cursor::new(&unhashed), &key).is_err());
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{snapshot_nodes_to_file, CompressConfiguration, DataSource, DirNode},
+    ErrorKind,
+};
+use std::{
+    fs,
+    io::{Read, Seek},
+};
+
+fn first_files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("src/lib.rs", contents(1, 5000)),
+        ("src/main.rs", contents(2, 3000)),
+        ("data/big.bin", noise(3, 200_000)),
+    ]
+}
+
+fn second_files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("src/lib.rs", contents(20, 5000)),
+        ("data/big.bin", noise(3, 200_000)),
+        ("copy.bin", noise(3, 200_000)),
+    ]
+}
+
+fn tree(files: &[(&str, Vec<u8>)]) -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for (path, data) in files {
+        tree.insert(path, DirNode::file(DataSource::from_data(data.clone())))
+            .unwrap();
+    }
+    tree
+}
+
+fn assert_files(reader: &mut ArchiveReader<impl Read + Seek>, files: &[(&str, Vec<u8>)]) {
+    for (path, data) in files {
+        let id = reader.lookup(path).unwrap().unwrap();
+        assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+    }
+}
+
+#[test]
+fn snapshots_share_unchanged_files() {
+    let path = temp_dir("snapshot-share").join("archive.diar");
+    let cfg = CompressConfiguration::default().hash_objects(true);
+    let first = cfg.clone().snapshot_time(1_700_000_000);
+    snapshot_nodes_to_file(&tree(&first_files()), &path, &first).unwrap();
+    let first_len = fs::metadata(&path).unwrap().len();
+    let second = cfg.snapshot_time(1_700_086_400);
+    snapshot_nodes_to_file(&tree(&second_files()), &path, &second).unwrap();
+
+    // `data/big.bin` and its copy are not stored again
+    let added = fs::metadata(&path).unwrap().len() - first_len;
+    assert!(added < 20_000, "{added}");
+
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    let names: Vec<_> = reader
+        .snapshots()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.name)
+        .collect();
+    assert_eq!(names, ["2023-11-14T22:13:20Z", "2023-11-15T22:13:20Z"]);
+    assert_files(&mut reader, &second_files());
+    assert!(reader.lookup("src/main.rs").unwrap().is_none());
+    reader.open_snapshot(&names[0]).unwrap();
+    assert_files(&mut reader, &first_files());
+    assert!(reader.verify().is_ok());
+
+    let err = reader.open_snapshot("2023-11-16T22:13:20Z").unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::NotFound(..))), "{err}");
+    assert!(err.to_string().contains("2023-11-16T22:13:20Z"), "{err}");
+    assert_files(&mut reader, &first_files());
+}
+
+#[test]
+fn identical_files_are_stored_once() {
+    let dir = temp_dir("snapshot-identical");
+    let files: Vec<_> = ["a.bin", "b.bin", "c/d.bin"]
+        .into_iter()
+        .map(|x| (x, contents(7, 100_000)))
+        .collect();
+    let cfg = CompressConfiguration::default().snapshot_time(0);
+    snapshot_nodes_to_file(&tree(&files), dir.join("same.diar"), &cfg).unwrap();
+
+    let mut reader = ArchiveReader::open(dir.join("same.diar")).unwrap();
+    let ids: Vec<_> = files
+        .iter()
+        .map(|x| reader.lookup(x.0).unwrap().unwrap())
+        .collect();
+    assert!(ids.windows(2).all(|x| x[0] == x[1]));
+    assert_files(&mut reader, &files);
+}
+
+#[test]
+fn files_of_the_same_length_are_told_apart() {
+    let dir = temp_dir("snapshot-lengths");
+    let first = vec![("a.bin", contents(1, 10_000))];
+    let second = vec![("a.bin", contents(1, 10_000)), ("b.bin", contents(2, 10_000))];
+    let path = dir.join("archive.diar");
+    let cfg = CompressConfiguration::default();
+    snapshot_nodes_to_file(&tree(&first), &path, &cfg.clone().snapshot_time(0)).unwrap();
+    snapshot_nodes_to_file(&tree(&second), &path, &cfg.snapshot_time(1)).unwrap();
+
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    assert_files(&mut reader, &second);
+    let a = reader.lookup("a.bin").unwrap().unwrap();
+    let b = reader.lookup("b.bin").unwrap().unwrap();
+    assert_ne!(a, b);
+}
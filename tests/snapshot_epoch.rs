@@ -0,0 +1,28 @@
+//! Kept in its own test binary, as it sets an environment variable other tests would see.
+
+mod common;
+
+use common::*;
+use diar::{
+    reader::ArchiveReader,
+    writer::{snapshot_nodes_to_file, CompressConfiguration, DataSource, DirNode},
+};
+
+#[test]
+fn snapshot_time_defaults_to_source_date_epoch() {
+    let path = temp_dir("snapshot-epoch").join("archive.diar");
+    let mut tree = DirNode::empty_dir();
+    tree.insert("file.txt", DirNode::file(DataSource::from_data(contents(1, 1000))))
+        .unwrap();
+
+    std::env::set_var("SOURCE_DATE_EPOCH", "86400");
+    let result = snapshot_nodes_to_file(&tree, &path, &CompressConfiguration::default());
+    std::env::remove_var("SOURCE_DATE_EPOCH");
+    result.unwrap();
+
+    let mut reader = ArchiveReader::open(&path).unwrap();
+    let snapshots = reader.snapshots().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].name, "1970-01-02T00:00:00Z");
+    assert_eq!(snapshots[0].created, Some(86400));
+}
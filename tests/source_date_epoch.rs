@@ -0,0 +1,31 @@
+//! Kept in its own test binary, as it sets an environment variable other tests would see.
+
+use diar::{
+    writer::{compress_nodes, CompressConfiguration, DataSource, DirNode},
+    ErrorKind,
+};
+use std::io::Cursor;
+
+#[test]
+fn malformed_source_date_epoch_is_reported() {
+    let mut tree = DirNode::empty_dir();
+    tree.insert("file.txt", DirNode::file(DataSource::from_data("data")))
+        .unwrap();
+
+    std::env::set_var("SOURCE_DATE_EPOCH", "yesterday");
+    let result = compress_nodes(&tree, Cursor::new(Vec::new()), &CompressConfiguration::default());
+    let clamped = CompressConfiguration::default().mtime_clamp(0);
+    let explicit = compress_nodes(&tree, Cursor::new(Vec::new()), &clamped);
+    std::env::remove_var("SOURCE_DATE_EPOCH");
+
+    let err = result.unwrap_err();
+    match err.kind() {
+        Some(ErrorKind::InvalidSourceDateEpoch(value, _)) => assert_eq!(value, "yesterday"),
+        _ => panic!("expected an invalid SOURCE_DATE_EPOCH, got {err}"),
+    }
+    assert!(err.to_string().contains("\"yesterday\""), "{err}");
+    assert!(!err.is_corruption());
+
+    // the variable is not read when the clamp is given explicitly
+    explicit.unwrap();
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration},
+    writer::{compress_with_config, CompressConfiguration},
+    EncryptionKey,
+};
+use std::io::{self, Cursor, Write};
+
+/// A sink that can only be written to, like a pipe.
+struct Pipe(Vec<u8>);
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn archives_stream_to_unseekable_output() {
+    let dir = temp_dir("pipe");
+    let files: Vec<_> = (0..12)
+        .map(|i| (format!("logs/day{i}.log"), contents(i, 20_000)))
+        .collect();
+    write_files(&dir, &files);
+
+    let key = EncryptionKey::Raw([9; 32]);
+    for cfg in [
+        CompressConfiguration::default(),
+        CompressConfiguration::default().hash_objects(true),
+        CompressConfiguration::default()
+            .encryption_key(key.clone())
+            .encrypt_listings(true),
+    ] {
+        let mut pipe = Pipe(Vec::new());
+        compress_with_config(&dir, &mut pipe, &cfg).unwrap();
+
+        let mut read_cfg = ReaderConfiguration::default();
+        read_cfg.decryption_key = Some(key.clone());
+        let mut reader = ArchiveReader::new_with_config(Cursor::new(&pipe.0), &read_cfg).unwrap();
+        for (path, data) in &files {
+            let id = reader.lookup(path).unwrap().unwrap();
+            assert_eq!(&reader.read_file(id).unwrap(), data, "{path}");
+        }
+    }
+}
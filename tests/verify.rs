@@ -0,0 +1,131 @@
+mod common;
+
+use common::*;
+use diar::{
+    reader::{ArchiveReader, ReaderConfiguration, ReaderLimits},
+    writer::{
+        append_nodes_to_file, compress_nodes, compress_nodes_to_file, CompressConfiguration,
+        DataSource, DirNode,
+    },
+};
+use std::io::Cursor;
+
+const SEEDS: [u32; 2] = [0x2545F491, 0x9E3779B9];
+
+fn compress(tree: &DirNode, cfg: &CompressConfiguration) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    compress_nodes(tree, &mut out, cfg).unwrap();
+    out.into_inner()
+}
+
+/// Builds a tree with a few text files, and a file of noise for each of [`SEEDS`].
+fn tree() -> DirNode {
+    let mut tree = DirNode::empty_dir();
+    for i in 0..4 {
+        let node = DirNode::file(DataSource::from_data(contents(i, 5000)));
+        tree.insert(&format!("text/{i}.txt"), node).unwrap();
+    }
+    tree.insert("text/empty.txt", DirNode::file(DataSource::from_data("")))
+        .unwrap();
+    for (name, seed) in ["one.bin", "two.bin"].into_iter().zip(SEEDS) {
+        tree.insert(name, DirNode::file(DataSource::from_data(noise(seed, 50_000))))
+            .unwrap();
+    }
+    tree.insert("link", DirNode::symlink("text/0.txt")).unwrap();
+    tree
+}
+
+#[test]
+fn verify_counts_archive_contents() {
+    for hash_objects in [false, true] {
+        let cfg = CompressConfiguration::default().hash_objects(hash_objects);
+        let archive = compress(&tree(), &cfg);
+        let report = ArchiveReader::new(Cursor::new(&archive)).unwrap().verify();
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.hashed, hash_objects);
+        // directory listings and the dictionary are blobs too
+        assert!(report.bytes >= 4 * 5000 + 2 * 50_000);
+        assert!(report.blobs >= 6);
+        assert!(report.objects > report.blobs);
+        assert!(report.unknown_objects.is_empty());
+        assert!(report.unreachable.is_empty());
+    }
+}
+
+#[test]
+fn verify_ignores_the_total_size_limit() {
+    let archive = compress(&tree(), &CompressConfiguration::default());
+    let total = ArchiveReader::new(Cursor::new(&archive))
+        .unwrap()
+        .verify()
+        .bytes;
+    // enough to extract everything and then read a small file, but not to decompress it all again
+    let limits = ReaderLimits::default().max_total_size(total + 10_000);
+    let cfg = ReaderConfiguration::default().limits(limits);
+    let mut reader = ArchiveReader::new_with_config(Cursor::new(&archive), &cfg).unwrap();
+
+    let root = reader.root_dir();
+    reader.extract(root, temp_dir("verify-limits")).unwrap();
+    let report = reader.verify();
+    assert!(report.is_ok(), "{report:?}");
+    assert_eq!(report.bytes, total);
+
+    // verifying does not use up the limit for later reads either
+    let id = reader.lookup("text/0.txt").unwrap().unwrap();
+    assert_eq!(reader.read_file(id).unwrap(), contents(0, 5000));
+}
+
+#[test]
+fn verify_reports_unreachable_ranges() {
+    let path = temp_dir("verify-unreachable").join("archive.diar");
+    let cfg = CompressConfiguration::default();
+    compress_nodes_to_file(&tree(), &path, &cfg).unwrap();
+    let mut additions = DirNode::empty_dir();
+    additions
+        .insert("added", DirNode::file(DataSource::from_data(contents(9, 100))))
+        .unwrap();
+    append_nodes_to_file(&additions, &path, &cfg).unwrap();
+
+    // the replaced root directory is no longer used, but that is not a problem
+    let report = ArchiveReader::open(&path).unwrap().verify();
+    assert!(report.is_ok(), "{report:?}");
+    assert!(!report.unreachable.is_empty());
+}
+
+#[test]
+fn verify_reports_every_damaged_file() {
+    let mut archive = compress(&tree(), &CompressConfiguration::default().hash_objects(true));
+    for seed in SEEDS {
+        let offset = find_noise(&archive, &noise(seed, 50_000), 20_000);
+        archive[offset] ^= 0x01;
+    }
+
+    let report = ArchiveReader::new(Cursor::new(&archive)).unwrap().verify();
+    assert!(!report.is_ok());
+    let mut paths: Vec<_> = report
+        .issues
+        .iter()
+        .filter_map(|x| x.path.as_deref())
+        .collect();
+    paths.dedup();
+    assert_eq!(paths, ["one.bin", "two.bin"]);
+    assert!(report.issues.iter().all(|x| x.offset.is_some()));
+}
+
+#[test]
+fn verify_lists_unknown_objects() {
+    let mut archive = compress(&tree(), &CompressConfiguration::default());
+    // the symlink's header: its type, data length, fields length, then its target
+    let mut header = vec![6, 0, 11, 10];
+    header.extend_from_slice(b"text/0.txt");
+    let offset = archive
+        .windows(header.len())
+        .position(|x| x == header)
+        .unwrap();
+    archive[offset] = 0x30;
+
+    let report = ArchiveReader::new(Cursor::new(&archive)).unwrap().verify();
+    assert!(report.is_ok(), "{report:?}");
+    assert_eq!(report.unknown_objects.len(), 1);
+    assert_eq!(report.unknown_objects[0].1, 0x30);
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use common::*;
+use diar::{
+    writer::{DirNode, PathTransform, WalkOptions},
+    ErrorKind,
+};
+use std::path::{Path, PathBuf};
+
+fn write_dir(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    let tag = "Signature: 8a477f597d28d172789f06886806bc55\n";
+    write_files(&dir, &[
+        ("README.md", "readme"),
+        ("src/lib.rs", "lib"),
+        ("src/lib.rs.bak", "backup"),
+        ("src/nested/mod.rs", "nested"),
+        ("target/debug/out.o", "object"),
+        (".hidden/config", "config"),
+        (".git/HEAD", "ref"),
+        (".gitignore", "target/\n*.log\n"),
+        ("logs/today.log", "log"),
+        ("logs/.ignore", "!keep.log\n"),
+        ("logs/keep.log", "kept"),
+        ("cache/CACHEDIR.TAG", tag),
+        ("cache/entry", "cached"),
+        ("not_cache/CACHEDIR.TAG", "not a cache tag"),
+    ]);
+    dir
+}
+
+fn walk_files(dir: &Path, options: &WalkOptions) -> Vec<String> {
+    let tree = DirNode::from_path_with_options(dir, options).unwrap();
+    tree.walk()
+        .filter(|(_, node)| node.is_file())
+        .map(|(path, _)| path)
+        .collect()
+}
+
+#[test]
+fn walk_includes_everything_by_default() {
+    let dir = write_dir("default");
+    let tree = DirNode::from_path(&dir).unwrap();
+    assert_eq!(tree.walk().filter(|(_, node)| node.is_file()).count(), 14);
+    let mut data = Vec::new();
+    tree.get("src/lib.rs")
+        .unwrap()
+        .contents()
+        .unwrap()
+        .push_to_vec(&mut data)
+        .unwrap();
+    assert_eq!(data, b"lib");
+}
+
+#[test]
+fn walk_excludes_globs() {
+    let dir = write_dir("exclude");
+    let options = WalkOptions::default().exclude(vec!["*.bak".into(), "target/".into()]);
+    let paths = walk_files(&dir, &options);
+    assert!(paths.iter().any(|x| x == "src/lib.rs"));
+    assert!(!paths
+        .iter()
+        .any(|x| x.ends_with(".bak") || x.starts_with("target")));
+}
+
+#[test]
+fn walk_includes_globs() {
+    let dir = write_dir("include");
+    let options = WalkOptions::default().include(vec!["*.rs".into()]);
+    assert_eq!(walk_files(&dir, &options), ["src/lib.rs", "src/nested/mod.rs"]);
+    // directories without included files are left out
+    let tree = DirNode::from_path_with_options(&dir, &options).unwrap();
+    assert!(tree.get("target").is_none());
+
+    let options = options.exclude(vec!["nested/".into()]);
+    assert_eq!(walk_files(&dir, &options), ["src/lib.rs"]);
+}
+
+#[test]
+fn walk_respects_ignore_files() {
+    let dir = write_dir("ignore-files");
+    let options = WalkOptions::default().respect_ignore_files(true);
+    let paths = walk_files(&dir, &options);
+    assert!(!paths
+        .iter()
+        .any(|x| x.starts_with("target/") || x.starts_with(".git/")));
+    assert!(!paths.iter().any(|x| x == "logs/today.log"));
+    // more specific ignore files take precedence
+    assert!(paths.iter().any(|x| x == "logs/keep.log"));
+    assert!(paths.iter().any(|x| x == "src/lib.rs.bak"));
+}
+
+#[test]
+fn walk_skips_hidden_and_cache_dirs() {
+    let dir = write_dir("hidden");
+    let paths = walk_files(&dir, &WalkOptions::default().skip_hidden(true));
+    assert!(!paths.iter().any(|x| x.starts_with('.') || x.contains("/.")));
+    assert!(paths.iter().any(|x| x == "cache/entry"));
+
+    let paths = walk_files(&dir, &WalkOptions::default().skip_cache_dirs(true));
+    assert!(!paths.iter().any(|x| x.starts_with("cache/")));
+    assert!(paths.iter().any(|x| x == "not_cache/CACHEDIR.TAG"));
+}
+
+#[test]
+fn walk_limits_depth() {
+    let dir = write_dir("depth");
+    let options = WalkOptions::default().skip_hidden(true);
+    assert_eq!(walk_files(&dir, &options.clone().max_depth(1)), ["README.md"]);
+    let paths = walk_files(&dir, &options.max_depth(2));
+    assert!(paths.iter().any(|x| x == "src/lib.rs"));
+    assert!(!paths
+        .iter()
+        .any(|x| x == "src/nested/mod.rs" || x == "target/debug/out.o"));
+}
+
+#[test]
+fn walk_renames_paths() {
+    let dir = write_dir("transforms");
+    let options = WalkOptions::default()
+        .include(vec!["*.rs".into(), "*.md".into()])
+        .transforms(vec![
+            PathTransform::prefix("src", "lib/src"),
+            PathTransform::new(r"\.md$", ".txt").unwrap(),
+            PathTransform::new(r"^(.*)/nested/(.*)$", "$1/$2").unwrap(),
+        ])
+        .prefix("project");
+    assert_eq!(walk_files(&dir, &options), [
+        "project/README.txt",
+        "project/lib/src/lib.rs",
+        "project/lib/src/mod.rs"
+    ],);
+
+    // rules that empty a path remove it
+    let options = WalkOptions::default().transforms(vec![PathTransform::new("^.*$", "").unwrap()]);
+    assert!(walk_files(&dir, &options).is_empty());
+
+    assert!(PathTransform::new("(", "").is_err());
+    assert_eq!(PathTransform::prefix("src", "lib").apply("srcdir/a.rs"), "srcdir/a.rs");
+}
+
+#[test]
+fn map_paths_rejects_collisions() {
+    let dir = write_dir("collisions");
+    let tree = DirNode::from_path(&dir).unwrap();
+    let renamed = tree
+        .map_paths(|path| Some(format!("moved/{path}")))
+        .unwrap();
+    assert!(renamed.get("moved/src/lib.rs").unwrap().is_file());
+    assert!(renamed.get("src").is_none());
+
+    let tree = DirNode::from_path(&dir).unwrap();
+    assert!(tree.map_paths(|_| Some("same".into())).is_err());
+}
+
+#[test]
+fn mount_combines_trees() {
+    let first = temp_dir("mount-first");
+    write_files(&first, &[("a.txt", "a"), ("shared/b.txt", "b")]);
+    let second = temp_dir("mount-second");
+    write_files(&second, &[("shared/c.txt", "c")]);
+
+    let mut root = DirNode::empty_dir();
+    root.mount("", DirNode::from_path(&first).unwrap()).unwrap();
+    root.mount("", DirNode::from_path(&second).unwrap())
+        .unwrap();
+    root.mount("extra/second", DirNode::from_path(&second).unwrap())
+        .unwrap();
+    let paths: Vec<_> = root
+        .walk()
+        .filter(|(_, node)| node.is_file())
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(paths, ["a.txt", "extra/second/shared/c.txt", "shared/b.txt", "shared/c.txt"]);
+
+    assert!(root.mount("a.txt/inner", DirNode::empty_dir()).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn walk_rejects_non_utf8_names() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = temp_dir("non-utf8");
+    write_files(&dir, &[("ok.txt", "ok")]);
+    let name = OsStr::from_bytes(b"bad\xFFname.txt");
+    if std::fs::write(dir.join(name), "bad").is_err() {
+        // some filesystems only allow UTF-8 names
+        return;
+    }
+
+    let err = DirNode::from_path(&dir).unwrap_err();
+    assert!(matches!(err.kind(), Some(ErrorKind::NonUtf8Name(..))), "{err}");
+}